@@ -23,6 +23,12 @@ do
     cargo test --verbose --features="$feature"
 done
 
+# The async codec needs a newer compiler than the rest of the library
+if [ "$TRAVIS_RUST_VERSION" != "1.22.0" ]
+then
+    cargo test --verbose --features=async
+fi
+
 # Fuzz if told to
 if [ "$DO_FUZZ" = true ]
 then
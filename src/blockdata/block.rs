@@ -20,16 +20,21 @@
 //! these blocks and the blockchain.
 //!
 
-use hashes::{sha256d, Hash};
+use std::{error, fmt};
+
+use hashes::Hash;
 
 use util;
 use util::Error::{BlockBadTarget, BlockBadProofOfWork};
-use util::hash::{BitcoinHash, MerkleRoot, bitcoin_merkle_root};
+use hash_types::{Txid, Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
+use util::hash::{BitcoinHash, MerkleRoot, txid_merkle_root, witness_merkle_root};
 use util::uint::Uint256;
-use consensus::encode::Encodable;
+use consensus::encode::{Encodable, VarInt};
 use network::constants::Network;
-use blockdata::transaction::Transaction;
-use blockdata::constants::max_target;
+use blockdata::opcodes;
+use blockdata::script::{self, Instruction};
+use blockdata::transaction::{CheckTxError, Transaction};
+use blockdata::constants::{max_target, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT, WITNESS_SCALE_FACTOR};
 use hashes::HashEngine;
 
 /// A block header, which contains all the block's information except
@@ -39,9 +44,9 @@ pub struct BlockHeader {
     /// The protocol version. Should always be 1.
     pub version: u32,
     /// Reference to the previous block in the chain
-    pub prev_blockhash: sha256d::Hash,
+    pub prev_blockhash: BlockHash,
     /// The root hash of the merkle tree of transactions in the block
-    pub merkle_root: sha256d::Hash,
+    pub merkle_root: TxMerkleNode,
     /// The timestamp of the block, as claimed by the miner
     pub time: u32,
     /// The target value below which the blockhash must lie, encoded as a
@@ -61,7 +66,166 @@ pub struct Block {
     pub txdata: Vec<Transaction>
 }
 
+/// Reason a block fails `Block::check_block`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckBlockError {
+    /// The block hash is not below the header's target
+    BadProofOfWork,
+    /// The header's merkle root does not match the transactions
+    BadMerkleRoot,
+    /// The transaction list repeats transactions without changing the
+    /// merkle root (CVE-2012-2459)
+    MutatedMerkleRoot,
+    /// The block has no transactions, or too many transactions or bytes
+    /// without witness data
+    BadLength,
+    /// The block weighs more than `MAX_BLOCK_WEIGHT`
+    TooHeavy(usize),
+    /// The first transaction is not a coinbase
+    MissingCoinbase,
+    /// The transaction at this index, other than the first, is a coinbase
+    ExtraCoinbase(usize),
+    /// The block's legacy signature operations cost more than
+    /// `MAX_BLOCK_SIGOPS_COST`
+    TooManySigops(usize),
+    /// The transaction at this index is invalid
+    BadTransaction(usize, CheckTxError),
+}
+
+impl fmt::Display for CheckBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheckBlockError::TooHeavy(weight) => write!(f, "block weight {} too large", weight),
+            CheckBlockError::ExtraCoinbase(i) => write!(f, "transaction {} is a coinbase", i),
+            CheckBlockError::TooManySigops(cost) => write!(f, "block sigop cost {} too large", cost),
+            CheckBlockError::BadTransaction(i, ref e) => write!(f, "transaction {}: {}", i, e),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for CheckBlockError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            CheckBlockError::BadTransaction(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl CheckBlockError {
+    fn reason(&self) -> &'static str {
+        match *self {
+            CheckBlockError::BadProofOfWork => "block has bad proof of work",
+            CheckBlockError::BadMerkleRoot => "merkle root does not match transactions",
+            CheckBlockError::MutatedMerkleRoot => "duplicate transactions",
+            CheckBlockError::BadLength => "bad block length",
+            CheckBlockError::TooHeavy(..) => "block weight too large",
+            CheckBlockError::MissingCoinbase => "first transaction is not a coinbase",
+            CheckBlockError::ExtraCoinbase(..) => "more than one coinbase",
+            CheckBlockError::TooManySigops(..) => "block sigop cost too large",
+            CheckBlockError::BadTransaction(..) => "invalid transaction",
+        }
+    }
+}
+
+/// Reason the height of a block cannot be read from its coinbase, see
+/// `Block::bip34_height`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Bip34Error {
+    /// The block version is below 2, so the block need not commit to its
+    /// height
+    Unsupported,
+    /// The block has no coinbase or the coinbase scriptSig does not start
+    /// with a push
+    NotPresent,
+    /// The height is not pushed as a minimally encoded number
+    NonMinimalPush,
+    /// The push is too long for a height
+    UnexpectedPush(Vec<u8>),
+    /// The height pushed is negative
+    NegativeHeight,
+}
+
+impl fmt::Display for Bip34Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bip34Error::UnexpectedPush(ref p) => write!(f, "unexpected push of {} bytes", p.len()),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for Bip34Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl Bip34Error {
+    fn reason(&self) -> &'static str {
+        match *self {
+            Bip34Error::Unsupported => "block version does not commit to the height",
+            Bip34Error::NotPresent => "coinbase does not start with the height",
+            Bip34Error::NonMinimalPush => "height is not minimally encoded",
+            Bip34Error::UnexpectedPush(..) => "unexpected push in place of the height",
+            Bip34Error::NegativeHeight => "negative height",
+        }
+    }
+}
+
 impl Block {
+    /// The coinbase transaction, if the block has one
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        match self.txdata.first() {
+            Some(tx) if tx.is_coin_base() => Some(tx),
+            _ => None,
+        }
+    }
+
+    /// Read the height of the block from the start of its coinbase
+    /// scriptSig, where BIP34 requires blocks of version 2 or more to
+    /// push it as a minimally encoded number. Whether the block is past
+    /// the network's `bip34_height`, from which this is enforced, is not
+    /// checked.
+    pub fn bip34_height(&self) -> Result<u32, Bip34Error> {
+        if self.header.version < 2 {
+            return Err(Bip34Error::Unsupported);
+        }
+        let coinbase = self.coinbase().ok_or(Bip34Error::NotPresent)?;
+        let push = coinbase.input[0].script_sig.iter(true).next().ok_or(Bip34Error::NotPresent)?;
+        let height = match push {
+            Instruction::PushBytes(bytes) => {
+                if bytes.len() > 4 {
+                    return Err(Bip34Error::UnexpectedPush(bytes.to_vec()));
+                }
+                let height = script::read_scriptint(bytes).map_err(|_| Bip34Error::UnexpectedPush(bytes.to_vec()))?;
+                if script::build_scriptint(height) != bytes {
+                    return Err(Bip34Error::NonMinimalPush);
+                }
+                height
+            }
+            Instruction::Op(op) => match op.classify() {
+                opcodes::Class::PushNum(n) => n as i64,
+                _ => return Err(Bip34Error::NotPresent),
+            },
+            Instruction::Error(script::Error::NonMinimalPush) => return Err(Bip34Error::NonMinimalPush),
+            Instruction::Error(_) => return Err(Bip34Error::NotPresent),
+        };
+        if height < 0 {
+            return Err(Bip34Error::NegativeHeight);
+        }
+        Ok(height as u32)
+    }
+
     /// check if merkle root of header matches merkle root of the transaction list
     pub fn check_merkle_root (&self) -> bool {
         self.header.merkle_root == self.merkle_root()
@@ -82,7 +246,7 @@ impl Block {
                     .rposition(|o| {
                         o.script_pubkey.len () >= 38 &&
                         o.script_pubkey[0..6] == [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed] }) {
-                    let commitment = sha256d::Hash::from_slice(&coinbase.output[pos].script_pubkey.as_bytes()[6..38]).unwrap();
+                    let commitment = WitnessCommitment::from_slice(&coinbase.output[pos].script_pubkey.as_bytes()[6..38]).unwrap();
                     // witness reserved value is in coinbase input witness
                     if coinbase.input[0].witness.len() == 1 && coinbase.input[0].witness[0].len() == 32 {
                         let witness_root = self.witness_root();
@@ -95,40 +259,125 @@ impl Block {
     }
 
     /// compute witness commitment for the transaction list
-    pub fn compute_witness_commitment (witness_root: &sha256d::Hash, witness_reserved_value: &[u8]) -> sha256d::Hash {
-        let mut encoder = sha256d::Hash::engine();
+    pub fn compute_witness_commitment (witness_root: &WitnessMerkleNode, witness_reserved_value: &[u8]) -> WitnessCommitment {
+        let mut encoder = WitnessCommitment::engine();
         witness_root.consensus_encode(&mut encoder).unwrap();
         encoder.input(witness_reserved_value);
-        sha256d::Hash::from_engine(encoder)
+        WitnessCommitment::from_engine(encoder)
     }
 
     /// Merkle root of transactions hashed for witness
-    pub fn witness_root(&self) -> sha256d::Hash {
-        let mut txhashes = vec!(sha256d::Hash::default());
-        txhashes.extend(self.txdata.iter().skip(1).map(|t|t.bitcoin_hash()));
-        bitcoin_merkle_root(txhashes)
+    pub fn witness_root(&self) -> WitnessMerkleNode {
+        let mut wtxids = vec![Wtxid::default()];
+        wtxids.extend(self.txdata.iter().skip(1).map(Transaction::wtxid));
+        witness_merkle_root(&wtxids)
+    }
+
+    /// Gets the "weight" of this block, as defined by BIP141: the size of
+    /// the header and transaction count times 4 plus the weight of each
+    /// transaction.
+    pub fn get_weight(&self) -> usize {
+        let base_size = 80 + VarInt(self.txdata.len() as u64).len();
+        base_size * WITNESS_SCALE_FACTOR + self.txdata.iter().map(Transaction::get_weight).sum::<usize>()
+    }
+
+    /// Check the block for validity without any context, as Bitcoin Core's
+    /// `CheckBlock` does. The checks are those of the proof of work, the
+    /// merkle root, the size and weight limits, the coinbase, the legacy
+    /// signature operation count and `Transaction::check` for each
+    /// transaction. Signature operations in P2SH and witness scripts can
+    /// only be counted knowing the outputs spent and are not checked, see
+    /// `Transaction::get_sigop_cost`.
+    pub fn check_block(&self) -> Result<(), CheckBlockError> {
+        if self.header.validate_pow(&self.header.target()).is_err() {
+            return Err(CheckBlockError::BadProofOfWork);
+        }
+
+        let txids: Vec<Txid> = self.txdata.iter().map(Transaction::txid).collect();
+        if self.header.merkle_root != txid_merkle_root(&txids) {
+            return Err(CheckBlockError::BadMerkleRoot);
+        }
+        if merkle_tree_is_mutated(&txids) {
+            return Err(CheckBlockError::MutatedMerkleRoot);
+        }
+
+        let max_base_size = MAX_BLOCK_WEIGHT / WITNESS_SCALE_FACTOR;
+        if self.txdata.is_empty() || self.txdata.len() > max_base_size {
+            return Err(CheckBlockError::BadLength);
+        }
+        let stripped_size = 80 + VarInt(self.txdata.len() as u64).len() +
+            self.txdata.iter().map(Transaction::get_stripped_size).sum::<usize>();
+        if stripped_size > max_base_size {
+            return Err(CheckBlockError::BadLength);
+        }
+        let weight = self.get_weight();
+        if weight > MAX_BLOCK_WEIGHT {
+            return Err(CheckBlockError::TooHeavy(weight));
+        }
+
+        if !self.txdata[0].is_coin_base() {
+            return Err(CheckBlockError::MissingCoinbase);
+        }
+        if let Some(i) = self.txdata.iter().skip(1).position(Transaction::is_coin_base) {
+            return Err(CheckBlockError::ExtraCoinbase(i + 1));
+        }
+
+        for (i, tx) in self.txdata.iter().enumerate() {
+            tx.check().map_err(|e| CheckBlockError::BadTransaction(i, e))?;
+        }
+
+        let sigops: usize = self.txdata.iter().map(Transaction::get_legacy_sigop_count).sum();
+        if sigops * WITNESS_SCALE_FACTOR > MAX_BLOCK_SIGOPS_COST {
+            return Err(CheckBlockError::TooManySigops(sigops * WITNESS_SCALE_FACTOR));
+        }
+        Ok(())
     }
 }
 
+/// Whether the merkle tree of `txids` has two identical hashes where the
+/// last hash of an odd level would be duplicated, so that a different
+/// transaction list has the same merkle root
+fn merkle_tree_is_mutated(txids: &[Txid]) -> bool {
+    let mut level: Vec<TxMerkleNode> = txids.iter().map(|txid| TxMerkleNode::from_hash(txid.as_hash())).collect();
+    while level.len() > 1 {
+        if level.chunks(2).any(|pair| pair.len() == 2 && pair[0] == pair[1]) {
+            return true;
+        }
+        level = level.chunks(2).map(|pair| {
+            let mut engine = TxMerkleNode::engine();
+            pair[0].consensus_encode(&mut engine).unwrap();
+            pair[pair.len() - 1].consensus_encode(&mut engine).unwrap();
+            TxMerkleNode::from_engine(engine)
+        }).collect();
+    }
+    false
+}
+
 impl MerkleRoot for Block {
-    fn merkle_root(&self) -> sha256d::Hash {
-        bitcoin_merkle_root(self.txdata.iter().map(|obj| obj.txid()).collect())
+    fn merkle_root(&self) -> TxMerkleNode {
+        let txids: Vec<Txid> = self.txdata.iter().map(Transaction::txid).collect();
+        txid_merkle_root(&txids)
     }
 }
 
 impl BlockHeader {
     /// Computes the target [0, T] that a blockhash must land in to be valid
     pub fn target(&self) -> Uint256 {
+        Self::u256_from_compact_target(self.bits)
+    }
+
+    /// Computes the target value in Uint256 format from float format.
+    pub fn u256_from_compact_target(bits: u32) -> Uint256 {
         // This is a floating-point "compact" encoding originally used by
         // OpenSSL, which satoshi put into consensus code, so we're stuck
         // with it. The exponent needs to have 3 subtracted from it, hence
         // this goofy decoding code:
         let (mant, expt) = {
-            let unshifted_expt = self.bits >> 24;
+            let unshifted_expt = bits >> 24;
             if unshifted_expt <= 3 {
-                ((self.bits & 0xFFFFFF) >> (8 * (3 - unshifted_expt as usize)), 0)
+                ((bits & 0xFFFFFF) >> (8 * (3 - unshifted_expt as usize)), 0)
             } else {
-                (self.bits & 0xFFFFFF, 8 * ((self.bits >> 24) - 3))
+                (bits & 0xFFFFFF, 8 * ((bits >> 24) - 3))
             }
         };
 
@@ -188,15 +437,15 @@ impl BlockHeader {
     }
 }
 
-impl BitcoinHash for BlockHeader {
-    fn bitcoin_hash(&self) -> sha256d::Hash {
+impl BitcoinHash<BlockHash> for BlockHeader {
+    fn bitcoin_hash(&self) -> BlockHash {
         use consensus::encode::serialize;
-        sha256d::Hash::hash(&serialize(self))
+        BlockHash::hash(&serialize(self))
     }
 }
 
-impl BitcoinHash for Block {
-    fn bitcoin_hash(&self) -> sha256d::Hash {
+impl BitcoinHash<BlockHash> for Block {
+    fn bitcoin_hash(&self) -> BlockHash {
         self.header.bitcoin_hash()
     }
 }
@@ -210,8 +459,13 @@ serde_struct_impl!(Block, header, txdata);
 mod tests {
     use hex::decode as hex_decode;
 
-    use blockdata::block::{Block, BlockHeader};
+    use blockdata::block::{Bip34Error, Block, BlockHeader, CheckBlockError};
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{CheckTxError, OutPoint, Transaction, TxIn, TxOut};
     use consensus::encode::{deserialize, serialize};
+    use hash_types::Txid;
+    use hashes::Hash;
     use util::hash::MerkleRoot;
 
     #[test]
@@ -239,6 +493,9 @@ mod tests {
 
         // should be also ok for a non-witness block as commitment is optional in that case
         assert!(real_decode.check_witness_commitment());
+        assert_eq!(real_decode.check_block(), Ok(()));
+        assert_eq!(real_decode.get_weight(), some_block.len() * 4);
+        assert_eq!(real_decode.bip34_height(), Err(Bip34Error::Unsupported));
 
         assert_eq!(serialize(&real_decode), some_block);
     }
@@ -265,10 +522,132 @@ mod tests {
         // [test] TODO: check the transaction data
 
         assert!(real_decode.check_witness_commitment());
+        assert_eq!(real_decode.check_block(), Ok(()));
+        assert_eq!(real_decode.bip34_height(), Ok(924634));
 
         assert_eq!(serialize(&real_decode), segwit_block);
     }
 
+    fn tx(spends: &[u32], script_sig: Script, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: spends.iter().map(|&vout| TxIn {
+                previous_output: if vout == 0xffffffff {
+                    OutPoint::null()
+                } else {
+                    OutPoint::new(Txid::hash(&[1]), vout)
+                },
+                script_sig: script_sig.clone(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn coinbase(script_sig_len: usize) -> Transaction {
+        tx(&[0xffffffff], Script::from(vec![0x51; script_sig_len]), 50 * 100_000_000)
+    }
+
+    /// Build a block of `txdata` with a matching merkle root and easy
+    /// proof of work
+    fn block(txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 1_600_000_000,
+                bits: 0x207fffff,
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.merkle_root();
+        while block.header.validate_pow(&block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    #[test]
+    fn check_block_test() {
+        let spend = tx(&[0], Script::new(), 1000);
+        let other = tx(&[1], Script::new(), 1000);
+        assert_eq!(block(vec![coinbase(2), spend.clone(), other.clone()]).check_block(), Ok(()));
+
+        let mut bad = block(vec![coinbase(2), spend.clone()]);
+        bad.txdata.push(other.clone());
+        assert_eq!(bad.check_block(), Err(CheckBlockError::BadMerkleRoot));
+        while bad.header.validate_pow(&bad.header.target()).is_ok() {
+            bad.header.nonce += 1;
+        }
+        assert_eq!(bad.check_block(), Err(CheckBlockError::BadProofOfWork));
+
+        // Repeating the last transaction of an odd level keeps the merkle root
+        let mutated = block(vec![coinbase(2), spend.clone(), other.clone(), other.clone()]);
+        assert_eq!(mutated.header.merkle_root, block(vec![coinbase(2), spend.clone(), other.clone()]).header.merkle_root);
+        assert_eq!(mutated.check_block(), Err(CheckBlockError::MutatedMerkleRoot));
+
+        assert_eq!(block(vec![]).check_block(), Err(CheckBlockError::BadLength));
+        assert_eq!(block(vec![spend.clone()]).check_block(), Err(CheckBlockError::MissingCoinbase));
+        assert_eq!(block(vec![coinbase(2), coinbase(3)]).check_block(), Err(CheckBlockError::ExtraCoinbase(1)));
+        assert_eq!(block(vec![coinbase(1)]).check_block(),
+                   Err(CheckBlockError::BadTransaction(0, CheckTxError::BadCoinbaseLength(1))));
+        assert_eq!(block(vec![coinbase(101)]).check_block(),
+                   Err(CheckBlockError::BadTransaction(0, CheckTxError::BadCoinbaseLength(101))));
+        assert_eq!(block(vec![coinbase(2), tx(&[3, 3], Script::new(), 1000)]).check_block(),
+                   Err(CheckBlockError::BadTransaction(1, CheckTxError::DuplicateInput(OutPoint::new(Txid::hash(&[1]), 3)))));
+        assert_eq!(block(vec![coinbase(2), tx(&[0, 0xffffffff], Script::new(), 1000)]).check_block(),
+                   Err(CheckBlockError::BadTransaction(1, CheckTxError::NullPrevout(1))));
+        assert_eq!(block(vec![coinbase(2), tx(&[0], Script::new(), 21_000_001 * 100_000_000)]).check_block(),
+                   Err(CheckBlockError::BadTransaction(1, CheckTxError::OutputValueTooLarge(0))));
+
+        // A CHECKMULTISIG counts as 20 sigops, for 20001 in all
+        let mut heavy = tx(&[0], Script::from(vec![opcodes::all::OP_CHECKSIG.into_u8(); 10_001]), 1000);
+        heavy.output[0].script_pubkey = Builder::new().push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script();
+        let mut checksigs = tx(&[1], Script::new(), 1000);
+        checksigs.output[0].script_pubkey = Script::from(vec![opcodes::all::OP_CHECKSIG.into_u8(); 9_980]);
+        assert_eq!(block(vec![coinbase(2), heavy.clone()]).check_block(), Ok(()));
+        assert_eq!(block(vec![coinbase(2), heavy, checksigs]).check_block(),
+                   Err(CheckBlockError::TooManySigops(80_004)));
+    }
+
+    #[test]
+    fn bip34_test() {
+        let with_script_sig = |script_sig: Vec<u8>| {
+            let mut coinbase = coinbase(2);
+            coinbase.input[0].script_sig = Script::from(script_sig);
+            let mut block = block(vec![coinbase]);
+            block.header.version = 2;
+            block
+        };
+        for &height in &[0, 1, 16, 17, 127, 128, 255, 256, 32767, 32768, 8388607, 8388608, 0x7fffffff] {
+            let mut block = block(vec![Transaction {
+                input: vec![TxIn::coinbase(height, &[1, 2, 3])],
+                ..coinbase(2)
+            }]);
+            block.header.version = 2;
+            assert_eq!(block.bip34_height(), Ok(height));
+            assert_eq!(block.coinbase().unwrap().check(), Ok(()));
+        }
+        assert_eq!(with_script_sig(vec![0x03, 0xda, 0x1b, 0x0e, 0xff]).bip34_height(), Ok(924634));
+
+        assert_eq!(with_script_sig(vec![0x02, 0x05, 0x00]).bip34_height(), Err(Bip34Error::NonMinimalPush));
+        assert_eq!(with_script_sig(vec![0x01, 0x05]).bip34_height(), Err(Bip34Error::NonMinimalPush));
+        assert_eq!(with_script_sig(vec![0x4c, 0x01, 0x20]).bip34_height(), Err(Bip34Error::NonMinimalPush));
+        assert_eq!(with_script_sig(vec![0x02, 0xff, 0x80]).bip34_height(), Err(Bip34Error::NegativeHeight));
+        assert_eq!(with_script_sig(vec![0x4f, 0x00]).bip34_height(), Err(Bip34Error::NegativeHeight));
+        assert_eq!(with_script_sig(vec![0x05, 1, 2, 3, 4, 5]).bip34_height(),
+                   Err(Bip34Error::UnexpectedPush(vec![1, 2, 3, 4, 5])));
+        assert_eq!(with_script_sig(vec![0xac, 0x00]).bip34_height(), Err(Bip34Error::NotPresent));
+        assert_eq!(block(vec![]).bip34_height(), Err(Bip34Error::Unsupported));
+        let mut empty = block(vec![]);
+        empty.header.version = 2;
+        assert_eq!(empty.bip34_height(), Err(Bip34Error::NotPresent));
+    }
+
     #[test]
     fn compact_roundrtip_test() {
         let some_header = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b").unwrap();
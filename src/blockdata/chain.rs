@@ -0,0 +1,567 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Header chain
+//!
+//! This module defines `HeaderChain`, which assembles block headers into a
+//! tree rooted at the genesis block, checks their proof of work, difficulty
+//! and timestamps, keeps track of the cumulative work of each header and
+//! follows the branch with the most work, reporting the headers disconnected
+//! and connected when its tip changes. Headers are kept in a `HeaderStore`;
+//! `MemoryStore` keeps them in memory.
+//!
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{error, fmt};
+
+use blockdata::block::BlockHeader;
+use blockdata::constants::genesis_block;
+use consensus::params::Params;
+use consensus::pow;
+use consensus::versionbits::{Deployment, ThresholdState};
+use hash_types::BlockHash;
+use network::constants::Network;
+use util::hash::BitcoinHash;
+use util::uint::Uint256;
+
+/// Error of a `HeaderStore`
+pub type StoreError = Box<error::Error + Send + Sync>;
+
+/// Header chain error
+#[derive(Debug)]
+pub enum Error {
+    /// The parent of the header is not known
+    UnknownParent(BlockHash),
+    /// The hash of the header is not below its own target
+    BadProofOfWork(BlockHash),
+    /// The header does not fit its place in the chain
+    InvalidHeader(BlockHash, pow::Error),
+    /// The store holds a chain with a different genesis block
+    GenesisMismatch(BlockHash),
+    /// The store failed
+    Store(StoreError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownParent(ref h) => write!(f, "parent of header {} is not known", h),
+            Error::BadProofOfWork(ref h) => write!(f, "header {} has bad proof of work", h),
+            Error::InvalidHeader(ref h, ref e) => write!(f, "invalid header {}: {}", h, e),
+            Error::GenesisMismatch(ref h) => write!(f, "stored chain starts with block {}", h),
+            Error::Store(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::InvalidHeader(_, ref e) => Some(e),
+            Error::Store(ref e) => Some(&**e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::UnknownParent(..) => "parent of header is not known",
+            Error::BadProofOfWork(..) => "header has bad proof of work",
+            Error::InvalidHeader(..) => "invalid header",
+            Error::GenesisMismatch(..) => "stored chain has a different genesis block",
+            Error::Store(..) => "header store error",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<StoreError> for Error {
+    fn from(e: StoreError) -> Error {
+        Error::Store(e)
+    }
+}
+
+/// Error for a header the store should have but does not
+fn missing_header() -> Error {
+    Error::Store("header missing from store".into())
+}
+
+/// A header with its position in the chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StoredHeader {
+    /// The header
+    pub header: BlockHeader,
+    /// The height of the header; the genesis block is at height 0
+    pub height: u32,
+    /// The total work of the header and all its ancestors
+    pub chain_work: Uint256,
+}
+
+/// Storage for the headers of a `HeaderChain`. The store keeps all known
+/// headers, whether on the active chain or not, and the hashes of the
+/// headers of the active chain by height.
+pub trait HeaderStore {
+    /// Get the header with hash `hash`
+    fn get(&self, hash: &BlockHash) -> Result<Option<StoredHeader>, StoreError>;
+
+    /// Store `header`, whose hash is `hash`
+    fn insert(&mut self, hash: BlockHash, header: StoredHeader) -> Result<(), StoreError>;
+
+    /// Get the hash of the header at `height` of the active chain
+    fn hash_at(&self, height: u32) -> Result<Option<BlockHash>, StoreError>;
+
+    /// Get the height of the tip of the active chain, or `None` if the
+    /// store is empty
+    fn tip_height(&self) -> Result<Option<u32>, StoreError>;
+
+    /// Replace the active chain from `height` on with `hashes`
+    fn set_active(&mut self, height: u32, hashes: &[BlockHash]) -> Result<(), StoreError>;
+}
+
+/// A `HeaderStore` keeping headers in memory
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    headers: HashMap<BlockHash, StoredHeader>,
+    active: Vec<BlockHash>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl HeaderStore for MemoryStore {
+    fn get(&self, hash: &BlockHash) -> Result<Option<StoredHeader>, StoreError> {
+        Ok(self.headers.get(hash).cloned())
+    }
+
+    fn insert(&mut self, hash: BlockHash, header: StoredHeader) -> Result<(), StoreError> {
+        self.headers.insert(hash, header);
+        Ok(())
+    }
+
+    fn hash_at(&self, height: u32) -> Result<Option<BlockHash>, StoreError> {
+        Ok(self.active.get(height as usize).cloned())
+    }
+
+    fn tip_height(&self) -> Result<Option<u32>, StoreError> {
+        Ok(self.active.len().checked_sub(1).map(|h| h as u32))
+    }
+
+    fn set_active(&mut self, height: u32, hashes: &[BlockHash]) -> Result<(), StoreError> {
+        self.active.truncate(height as usize);
+        self.active.extend_from_slice(hashes);
+        Ok(())
+    }
+}
+
+/// A change of the tip of a `HeaderChain`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TipUpdate {
+    /// Headers removed from the active chain, the old tip first
+    pub disconnected: Vec<StoredHeader>,
+    /// Headers added to the active chain, the new tip last
+    pub connected: Vec<StoredHeader>,
+}
+
+impl TipUpdate {
+    /// Whether headers were disconnected, rather than the active chain
+    /// merely extended
+    pub fn is_reorg(&self) -> bool {
+        !self.disconnected.is_empty()
+    }
+}
+
+/// A chain of block headers, following the branch with the most work
+#[derive(Debug)]
+pub struct HeaderChain<S: HeaderStore> {
+    store: S,
+    params: Params,
+    tip: StoredHeader,
+}
+
+impl<S: HeaderStore> HeaderChain<S> {
+    /// Open the chain of `network` kept in `store`, starting it with the
+    /// genesis block if the store is empty
+    pub fn new(mut store: S, network: Network) -> Result<HeaderChain<S>, Error> {
+        let genesis = genesis_block(network).header;
+        let genesis_hash = genesis.bitcoin_hash();
+        let tip = match store.tip_height()? {
+            None => {
+                let tip = StoredHeader {
+                    header: genesis,
+                    height: 0,
+                    chain_work: genesis.work(),
+                };
+                store.insert(genesis_hash, tip)?;
+                store.set_active(0, &[genesis_hash])?;
+                tip
+            }
+            Some(height) => {
+                match store.hash_at(0)? {
+                    Some(hash) if hash != genesis_hash => return Err(Error::GenesisMismatch(hash)),
+                    _ => {}
+                }
+                let hash = store.hash_at(height)?.ok_or_else(missing_header)?;
+                store.get(&hash)?.ok_or_else(missing_header)?
+            }
+        };
+        Ok(HeaderChain { store, params: Params::new(network), tip })
+    }
+
+    /// The tip of the active chain
+    pub fn tip(&self) -> &StoredHeader {
+        &self.tip
+    }
+
+    /// The hash of the tip of the active chain
+    pub fn tip_hash(&self) -> BlockHash {
+        self.tip.header.bitcoin_hash()
+    }
+
+    /// The height of the tip of the active chain
+    pub fn height(&self) -> u32 {
+        self.tip.height
+    }
+
+    /// Get the header with hash `hash`, whether on the active chain or not
+    pub fn get(&self, hash: &BlockHash) -> Result<Option<StoredHeader>, Error> {
+        Ok(self.store.get(hash)?)
+    }
+
+    /// Get the header at `height` of the active chain
+    pub fn header_at(&self, height: u32) -> Result<Option<StoredHeader>, Error> {
+        match self.store.hash_at(height)? {
+            Some(hash) => self.get(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the header with hash `hash` is on the active chain
+    pub fn is_active(&self, hash: &BlockHash) -> Result<bool, Error> {
+        Ok(match self.store.get(hash)? {
+            Some(header) => self.store.hash_at(header.height)? == Some(*hash),
+            None => false,
+        })
+    }
+
+    /// The state of `deployment` for the block following the tip of the
+    /// active chain
+    pub fn deployment_state(&self, deployment: &Deployment) -> Result<ThresholdState, Error> {
+        deployment.state(&self.params, self.tip.height + 1, |height| {
+            Ok(self.header_at(height)?.ok_or_else(missing_header)?.header)
+        })
+    }
+
+    /// The consensus parameters of the chain's network
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// The underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Return the underlying store
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Add `header` to the chain, see `accept_headers`
+    pub fn accept(&mut self, header: BlockHeader) -> Result<Option<TipUpdate>, Error> {
+        self.accept_headers(&[header])
+    }
+
+    /// Add `headers` to the chain, as received in a `headers` message.
+    /// Each header must follow a known header or one earlier in `headers`.
+    /// Headers already known are skipped. The headers are accepted all or
+    /// none: if any is invalid, the chain is left unchanged.
+    ///
+    /// If the headers give a branch with more work than the active chain,
+    /// it becomes the active chain and the change is returned.
+    ///
+    /// Header times are checked against the system clock, see
+    /// `accept_headers_at`.
+    pub fn accept_headers(&mut self, headers: &[BlockHeader]) -> Result<Option<TipUpdate>, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.accept_headers_at(headers, now as u32)
+    }
+
+    /// Add `headers` to the chain like `accept_headers`, checking that no
+    /// header is too far ahead of `adjusted_time`, the network-adjusted
+    /// time in seconds since the Unix epoch
+    pub fn accept_headers_at(&mut self, headers: &[BlockHeader], adjusted_time: u32)
+        -> Result<Option<TipUpdate>, Error>
+    {
+        let mut pending: HashMap<BlockHash, StoredHeader> = HashMap::new();
+        let mut order = Vec::with_capacity(headers.len());
+        let mut best = self.tip;
+        for header in headers {
+            let hash = header.bitcoin_hash();
+            if pending.contains_key(&hash) || self.store.get(&hash)?.is_some() {
+                continue;
+            }
+            let parent = match pending.get(&header.prev_blockhash) {
+                Some(parent) => *parent,
+                None => self.store.get(&header.prev_blockhash)?.ok_or(Error::UnknownParent(hash))?,
+            };
+            if header.validate_pow(&header.target()).is_err() {
+                return Err(Error::BadProofOfWork(hash));
+            }
+            {
+                let store = &self.store;
+                let mut prev = |h: &BlockHeader| -> Result<BlockHeader, Error> {
+                    match pending.get(&h.prev_blockhash) {
+                        Some(prev) => Ok(prev.header),
+                        None => Ok(store.get(&h.prev_blockhash)?.ok_or_else(missing_header)?.header),
+                    }
+                };
+                let bits = pow::next_bits(&self.params, &parent.header, parent.height, header.time, &mut prev)?;
+                let median_time_past = pow::median_time_past(&parent.header, parent.height, &mut prev)?;
+                pow::check_header_context(header, bits, median_time_past, adjusted_time)
+                    .map_err(|e| Error::InvalidHeader(hash, e))?;
+            }
+            let stored = StoredHeader {
+                header: *header,
+                height: parent.height + 1,
+                chain_work: parent.chain_work + header.work(),
+            };
+            if stored.chain_work > best.chain_work {
+                best = stored;
+            }
+            pending.insert(hash, stored);
+            order.push(hash);
+        }
+
+        for hash in order {
+            self.store.insert(hash, pending[&hash])?;
+        }
+        if best == self.tip {
+            return Ok(None);
+        }
+        self.switch_tip(best).map(Some)
+    }
+
+    /// Make `new_tip` the tip of the active chain
+    fn switch_tip(&mut self, new_tip: StoredHeader) -> Result<TipUpdate, Error> {
+        // Walk back from the new tip to the active chain
+        let mut connected = vec![];
+        let mut header = new_tip;
+        while self.store.hash_at(header.height)? != Some(header.header.bitcoin_hash()) {
+            connected.push(header);
+            header = self.store.get(&header.header.prev_blockhash)?.ok_or_else(missing_header)?;
+        }
+        connected.reverse();
+        let fork_height = header.height;
+
+        let mut disconnected = vec![];
+        for height in (fork_height + 1..self.tip.height + 1).rev() {
+            disconnected.push(self.header_at(height)?.ok_or_else(missing_header)?);
+        }
+
+        let hashes: Vec<BlockHash> = connected.iter().map(|h| h.header.bitcoin_hash()).collect();
+        self.store.set_active(fork_height + 1, &hashes)?;
+        self.tip = new_tip;
+        Ok(TipUpdate { disconnected, connected })
+    }
+
+    /// Build a block locator for a `getheaders` or `getblocks` message:
+    /// the hashes of the last 10 headers of the active chain, then of
+    /// headers exponentially further apart, ending with the genesis block
+    pub fn locator(&self) -> Result<Vec<BlockHash>, Error> {
+        let mut locator = vec![];
+        let mut height = self.tip.height;
+        let mut step = 1;
+        loop {
+            locator.push(self.store.hash_at(height)?.ok_or_else(missing_header)?);
+            if height == 0 {
+                break;
+            }
+            height = height.saturating_sub(step);
+            if locator.len() > 10 {
+                step *= 2;
+            }
+        }
+        Ok(locator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use blockdata::constants::genesis_block;
+    use consensus::versionbits::{deployments, ThresholdState};
+    use hash_types::BlockHash;
+    use network::constants::Network;
+    use util::hash::BitcoinHash;
+    use util::uint::Uint256;
+
+    use super::*;
+
+    /// Mine a regtest header on top of `prev`, distinguished by `time`
+    fn mine(prev: &BlockHeader, time: u32) -> BlockHeader {
+        mine_with_bits(prev, time, prev.bits)
+    }
+
+    /// Mine a header with difficulty `bits` on top of `prev`
+    fn mine_with_bits(prev: &BlockHeader, time: u32, bits: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 4,
+            prev_blockhash: prev.bitcoin_hash(),
+            merkle_root: Default::default(),
+            time,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// Mine `count` headers on top of `prev`
+    fn mine_chain(prev: &BlockHeader, count: usize, time: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        for i in 0..count {
+            let header = mine(headers.last().unwrap_or(prev), time + i as u32);
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn hashes(headers: &[StoredHeader]) -> Vec<BlockHash> {
+        headers.iter().map(|h| h.header.bitcoin_hash()).collect()
+    }
+
+    #[test]
+    fn extend_and_reorg() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut chain = HeaderChain::new(MemoryStore::new(), Network::Regtest).unwrap();
+        assert_eq!(chain.height(), 0);
+        assert_eq!(chain.tip_hash(), genesis.bitcoin_hash());
+        assert_eq!(chain.tip().chain_work, Uint256::from_u64(2).unwrap());
+
+        let a = mine_chain(&genesis, 3, 1_600_000_000);
+        let update = chain.accept_headers(&a).unwrap().unwrap();
+        assert!(!update.is_reorg());
+        assert_eq!(hashes(&update.connected), a.iter().map(|h| h.bitcoin_hash()).collect::<Vec<_>>());
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.tip().chain_work, Uint256::from_u64(8).unwrap());
+        assert_eq!(chain.deployment_state(&deployments(Network::Regtest)[1]).unwrap(), ThresholdState::Active);
+        // Known headers are skipped
+        assert_eq!(chain.accept(a[1]).unwrap(), None);
+
+        // A fork with as much work does not replace the active chain
+        let b = mine_chain(&a[0], 2, 1_600_001_000);
+        assert_eq!(chain.accept_headers(&b).unwrap(), None);
+        assert_eq!(chain.tip_hash(), a[2].bitcoin_hash());
+        assert!(!chain.is_active(&b[1].bitcoin_hash()).unwrap());
+        assert_eq!(chain.get(&b[1].bitcoin_hash()).unwrap().unwrap().height, 3);
+
+        // One more header does
+        let c = mine(&b[1], 1_600_002_000);
+        let update = chain.accept(c).unwrap().unwrap();
+        assert!(update.is_reorg());
+        assert_eq!(hashes(&update.disconnected), vec![a[2].bitcoin_hash(), a[1].bitcoin_hash()]);
+        assert_eq!(hashes(&update.connected), vec![b[0].bitcoin_hash(), b[1].bitcoin_hash(), c.bitcoin_hash()]);
+        assert_eq!(chain.height(), 4);
+        assert!(chain.is_active(&b[0].bitcoin_hash()).unwrap());
+        assert!(!chain.is_active(&a[1].bitcoin_hash()).unwrap());
+        assert_eq!(chain.header_at(2).unwrap().unwrap().header, b[0]);
+        assert_eq!(chain.header_at(5).unwrap(), None);
+
+        // Reopening the store gives the same chain
+        let chain = HeaderChain::new(chain.into_store(), Network::Regtest).unwrap();
+        assert_eq!(chain.tip_hash(), c.bitcoin_hash());
+        match HeaderChain::new(chain.into_store(), Network::Bitcoin) {
+            Err(Error::GenesisMismatch(hash)) => assert_eq!(hash, genesis.bitcoin_hash()),
+            r => panic!("unexpected result {:?}", r.map(|c| c.tip_hash())),
+        }
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut chain = HeaderChain::new(MemoryStore::new(), Network::Regtest).unwrap();
+        let mut headers = mine_chain(&genesis, 3, 1_600_000_000);
+
+        // Headers are accepted all or none
+        let mut bad = headers[2];
+        while bad.validate_pow(&bad.target()).is_ok() {
+            bad.nonce += 1;
+        }
+        headers[2] = bad;
+        match chain.accept_headers(&headers) {
+            Err(Error::BadProofOfWork(hash)) => assert_eq!(hash, bad.bitcoin_hash()),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(chain.height(), 0);
+        assert_eq!(chain.get(&headers[0].bitcoin_hash()).unwrap(), None);
+
+        match chain.accept(headers[1]) {
+            Err(Error::UnknownParent(hash)) => assert_eq!(hash, headers[1].bitcoin_hash()),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn contextual_checks() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut chain = HeaderChain::new(MemoryStore::new(), Network::Regtest).unwrap();
+        let headers = mine_chain(&genesis, 11, 1_600_000_000);
+        chain.accept_headers(&headers).unwrap();
+
+        let bad = mine_with_bits(&headers[10], 1_600_000_011, 0x207ffffe);
+        match chain.accept(bad) {
+            Err(Error::InvalidHeader(hash, pow::Error::BadDifficultyBits { required, actual })) => {
+                assert_eq!(hash, bad.bitcoin_hash());
+                assert_eq!((required, actual), (0x207fffff, 0x207ffffe));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The median time of the last 11 headers is that of headers[5]
+        let bad = mine(&headers[10], 1_600_000_005);
+        match chain.accept(bad) {
+            Err(Error::InvalidHeader(_, pow::Error::TimeTooOld { median_time_past, .. })) => {
+                assert_eq!(median_time_past, 1_600_000_005);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        let good = mine(&headers[10], 1_600_000_006);
+        match chain.accept_headers_at(&[good], 1_600_000_006 - pow::MAX_FUTURE_BLOCK_TIME - 1) {
+            Err(Error::InvalidHeader(_, pow::Error::TimeTooNew { .. })) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(chain.accept_headers_at(&[good], 1_600_000_006 - pow::MAX_FUTURE_BLOCK_TIME).unwrap().is_some());
+    }
+
+    #[test]
+    fn locator() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut chain = HeaderChain::new(MemoryStore::new(), Network::Regtest).unwrap();
+        assert_eq!(chain.locator().unwrap(), vec![genesis.bitcoin_hash()]);
+
+        let headers = mine_chain(&genesis, 100, 1_600_000_000);
+        chain.accept_headers(&headers).unwrap();
+        let heights: Vec<u32> = chain.locator().unwrap().iter()
+            .map(|hash| chain.get(hash).unwrap().unwrap().height)
+            .collect();
+        assert_eq!(heights, vec![100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 89, 87, 83, 75, 59, 27, 0]);
+    }
+}
@@ -41,9 +41,15 @@ pub const DIFFCHANGE_INTERVAL: u32 = 2016;
 /// How much time on average should occur between diffchanges
 pub const DIFFCHANGE_TIMESPAN: u32 = 14 * 24 * 3600;
 /// The maximum allowed weight for a block, see BIP 141 (network rule)
-pub const MAX_BLOCK_WEIGHT: u32 = 4_000_000;
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
 /// The minimum transaction weight for a valid serialized transaction
-pub const MIN_TRANSACTION_WEIGHT: u32 = 4 * 60;
+pub const MIN_TRANSACTION_WEIGHT: usize = 4 * 60;
+/// How much more non-witness data weighs than witness data, see BIP 141
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// The maximum allowed signature operation cost for a block, see BIP 141 (network rule)
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+/// The maximum value allowed in an output, in satoshis
+pub const MAX_MONEY: u64 = 21_000_000 * COIN_VALUE;
 
 
 /// In Bitcoind this is insanely described as ~((u256)0 >> 32)
@@ -55,7 +61,7 @@ pub fn max_target(_: Network) -> Uint256 {
 /// since keeping everything below this value should prevent overflows
 /// if you are doing anything remotely sane with monetary values).
 pub fn max_money(_: Network) -> u64 {
-    21_000_000 * COIN_VALUE
+    MAX_MONEY
 }
 
 /// Constructs and returns the coinbase (and only) transaction of the Bitcoin genesis block
@@ -96,14 +102,15 @@ fn bitcoin_genesis_tx() -> Transaction {
 
 /// Constructs and returns the genesis block
 pub fn genesis_block(network: Network) -> Block {
+    let txdata = vec![bitcoin_genesis_tx()];
+    let merkle_root = txdata[0].txid().as_hash().into();
     match network {
         Network::Bitcoin => {
-            let txdata = vec![bitcoin_genesis_tx()];
             Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
-                    merkle_root: txdata[0].txid(),
+                    merkle_root,
                     time: 1231006505,
                     bits: 0x1d00ffff,
                     nonce: 2083236893
@@ -112,12 +119,11 @@ pub fn genesis_block(network: Network) -> Block {
             }
         }
         Network::Testnet => {
-            let txdata = vec![bitcoin_genesis_tx()];
             Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
-                    merkle_root: txdata[0].txid(),
+                    merkle_root,
                     time: 1296688602,
                     bits: 0x1d00ffff,
                     nonce: 414098458
@@ -126,12 +132,11 @@ pub fn genesis_block(network: Network) -> Block {
             }
         }
         Network::Regtest => {
-            let txdata = vec![bitcoin_genesis_tx()];
             Block {
                 header: BlockHeader {
                     version: 1,
                     prev_blockhash: Default::default(),
-                    merkle_root: txdata[0].txid(),
+                    merkle_root,
                     time: 1296688602,
                     bits: 0x207fffff,
                     nonce: 2
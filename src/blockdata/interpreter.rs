@@ -0,0 +1,2108 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Script interpreter
+//!
+//! A native implementation of the Bitcoin script language, following the
+//! consensus and standardness rules of Bitcoin Core's interpreter. Unlike
+//! `Script::verify`, this does not need `libbitcoinconsensus` to be linked,
+//! and failures report the reason along with the script, position and opcode
+//! at which execution stopped. `trace_script` additionally records every
+//! step of execution, for debugging scripts which do not behave as expected.
+//!
+
+use std::cell::RefCell;
+use std::{error, fmt, mem, ops};
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use secp256k1::{self, Secp256k1, Message, Signature, VerifyOnly};
+
+use blockdata::locktime::{self, Sequence};
+use blockdata::opcodes;
+use blockdata::script::{self, Builder, Instruction, Instructions, Script};
+use blockdata::transaction::Transaction;
+use util::bip143::SigHashCache;
+
+/// Maximum number of bytes pushable to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of non-push operations per script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of public keys per multisig
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+/// Maximum script length in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+/// Maximum number of values on the main and alt stacks combined
+pub const MAX_STACK_SIZE: usize = 1000;
+/// Threshold for `nLockTime`: below this value it is interpreted as block
+/// number, otherwise as UNIX timestamp
+pub const LOCKTIME_THRESHOLD: i64 = locktime::LOCK_TIME_THRESHOLD as i64;
+
+/// Sequence number flag which disables relative locktime (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = locktime::SEQUENCE_LOCKTIME_DISABLE_FLAG as i64;
+/// Sequence number flag which selects time-based relative locktime (BIP68)
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = locktime::SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+/// Mask of the relative locktime value within a sequence number (BIP68)
+const SEQUENCE_LOCKTIME_MASK: i64 = locktime::SEQUENCE_LOCKTIME_MASK as i64;
+
+/// Script verification flags, selecting which rules on top of the original
+/// script semantics are enforced. The bit values are the same as Bitcoin
+/// Core's `SCRIPT_VERIFY_*` flags (and so libbitcoinconsensus' flags).
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Hash)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    /// No additional rules
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+    /// Evaluate P2SH subscripts (BIP16)
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+    /// Require strict encoding of signatures (defined hashtype, DER) and public keys
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+    /// Require strict DER encoding of signatures (BIP66)
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+    /// Require signatures to have a low S value (BIP146)
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+    /// Require the CHECKMULTISIG dummy element to be empty (BIP147)
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+    /// Require the scriptSig to be push-only
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+    /// Require pushes and numbers to use their minimal encoding
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+    /// Fail on executing one of the NOPs reserved for soft forks
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+    /// Require exactly one element on the stack after evaluation
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+    /// Enable OP_CHECKLOCKTIMEVERIFY (BIP65)
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+    /// Enable OP_CHECKSEQUENCEVERIFY (BIP112)
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+    /// Evaluate segregated witness programs (BIP141, BIP143)
+    pub const WITNESS: VerifyFlags = VerifyFlags(1 << 11);
+    /// Fail on spending witness programs of unknown versions
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: VerifyFlags = VerifyFlags(1 << 12);
+    /// Require the argument of OP_IF/OP_NOTIF in witness scripts to be exactly empty or `0x01`
+    pub const MINIMALIF: VerifyFlags = VerifyFlags(1 << 13);
+    /// Require failing signatures to be empty (BIP146)
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 14);
+    /// Require public keys in witness scripts to be compressed
+    pub const WITNESS_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 15);
+    /// Fail on OP_CODESEPARATOR and signature removal in non-segwit scripts
+    pub const CONST_SCRIPTCODE: VerifyFlags = VerifyFlags(1 << 16);
+
+    /// The flags enforced by consensus on all blocks after the segwit activation
+    pub const CONSENSUS: VerifyFlags = VerifyFlags(
+        (1 << 0) | (1 << 2) | (1 << 4) | (1 << 9) | (1 << 10) | (1 << 11)
+    );
+    /// The flags Bitcoin Core enforces for mempool acceptance
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        (1 << 0) | (1 << 1) | (1 << 2) | (1 << 3) | (1 << 4) | (1 << 6) | (1 << 7) |
+        (1 << 8) | (1 << 9) | (1 << 10) | (1 << 11) | (1 << 12) | (1 << 13) | (1 << 14) |
+        (1 << 15) | (1 << 16)
+    );
+
+    /// Add [VerifyFlags] together.
+    ///
+    /// Returns itself.
+    pub fn add(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 |= other.0;
+        *self
+    }
+
+    /// Remove [VerifyFlags] from this.
+    ///
+    /// Returns itself.
+    pub fn remove(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 &= !other.0;
+        *self
+    }
+
+    /// Check whether [VerifyFlags] are included in this one.
+    pub fn has(&self, flags: VerifyFlags) -> bool {
+        (self.0 | flags.0) == self.0
+    }
+
+    /// Get the integer representation of this [VerifyFlags].
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for VerifyFlags {
+    fn from(f: u32) -> Self {
+        VerifyFlags(f)
+    }
+}
+
+impl ops::BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: VerifyFlags) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl ops::BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, rhs: VerifyFlags) {
+        self.add(rhs);
+    }
+}
+
+/// The signature scheme a script is executed under
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Hash)]
+pub enum SigVersion {
+    /// Legacy scripts: scriptSig, scriptPubkey and P2SH redeem scripts
+    Base,
+    /// Version 0 witness scripts, signed as described in BIP143
+    WitnessV0,
+}
+
+/// One of the scripts executed while verifying a spend
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Hash)]
+pub enum Stage {
+    /// The spending input's scriptSig
+    ScriptSig,
+    /// The spent output's scriptPubkey
+    ScriptPubkey,
+    /// The P2SH redeem script, taken from the top of the scriptSig stack
+    RedeemScript,
+    /// The witness script, or the implied pay-to-pubkey-hash script of a P2WPKH output
+    WitnessScript,
+}
+
+/// Reasons for a script to fail, corresponding to Bitcoin Core's `ScriptError`
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Hash)]
+pub enum Error {
+    /// Script evaluated without error but finished with a false/empty top stack element
+    EvalFalse,
+    /// OP_RETURN was encountered
+    OpReturn,
+    /// Script is larger than `MAX_SCRIPT_SIZE`
+    ScriptSize,
+    /// Push or witness element is larger than `MAX_SCRIPT_ELEMENT_SIZE`
+    PushSize,
+    /// More than `MAX_OPS_PER_SCRIPT` operations
+    OpCount,
+    /// Stacks hold more than `MAX_STACK_SIZE` elements
+    StackSize,
+    /// Signature count negative or greater than pubkey count
+    SigCount,
+    /// Pubkey count negative or greater than `MAX_PUBKEYS_PER_MULTISIG`
+    PubkeyCount,
+    /// OP_VERIFY failed
+    Verify,
+    /// OP_EQUALVERIFY failed
+    EqualVerify,
+    /// OP_CHECKMULTISIGVERIFY failed
+    CheckMultiSigVerify,
+    /// OP_CHECKSIGVERIFY failed
+    CheckSigVerify,
+    /// OP_NUMEQUALVERIFY failed
+    NumEqualVerify,
+    /// Opcode missing, malformed or not understood
+    BadOpcode,
+    /// Opcode disabled since the early days of Bitcoin
+    DisabledOpcode,
+    /// Operation not valid with the current stack size
+    InvalidStackOperation,
+    /// Operation not valid with the current altstack size
+    InvalidAltstackOperation,
+    /// Invalid OP_IF construction
+    UnbalancedConditional,
+    /// Negative locktime
+    NegativeLocktime,
+    /// Locktime requirement not satisfied
+    UnsatisfiedLocktime,
+    /// Signature hash type missing or not understood
+    SigHashType,
+    /// Non-canonical DER signature
+    SigDer,
+    /// Data push or number larger than necessary
+    MinimalData,
+    /// Only push operators allowed in signatures
+    SigPushOnly,
+    /// Non-canonical signature: S value is unnecessarily high
+    SigHighS,
+    /// Dummy CHECKMULTISIG argument must be zero
+    SigNullDummy,
+    /// Public key is neither compressed or uncompressed
+    PubkeyType,
+    /// Stack size must be exactly one after execution
+    CleanStack,
+    /// OP_IF/NOTIF argument must be minimal
+    MinimalIf,
+    /// Signature must be zero for failed CHECK(MULTI)SIG operation
+    SigNullFail,
+    /// NOPx reserved for soft-fork upgrades
+    DiscourageUpgradableNops,
+    /// Witness version reserved for soft-fork upgrades
+    DiscourageUpgradableWitnessProgram,
+    /// Witness program has incorrect length
+    WitnessProgramWrongLength,
+    /// Witness program was passed an empty witness
+    WitnessProgramWitnessEmpty,
+    /// Witness program hash mismatch
+    WitnessProgramMismatch,
+    /// Witness requires empty scriptSig
+    WitnessMalleated,
+    /// Witness requires only-redeemscript scriptSig
+    WitnessMalleatedP2sh,
+    /// Witness provided for non-witness script
+    WitnessUnexpected,
+    /// Using non-compressed keys in segwit
+    WitnessPubkeyType,
+    /// Using OP_CODESEPARATOR in non-witness script
+    OpCodeSeparator,
+    /// Signature is found in scriptCode
+    SigFindAndDelete,
+    /// Number on the stack is larger than allowed for the operation
+    NumericOverflow,
+    /// Number on the stack is not minimally encoded
+    NonMinimalNumber,
+}
+
+impl Error {
+    /// A description of the failure, as Bitcoin Core's `ScriptErrorString`
+    fn reason(&self) -> &'static str {
+        match *self {
+            Error::EvalFalse => "script evaluated without error but finished with a false/empty top stack element",
+            Error::OpReturn => "OP_RETURN was encountered",
+            Error::ScriptSize => "script is too big",
+            Error::PushSize => "push value size limit exceeded",
+            Error::OpCount => "operation limit exceeded",
+            Error::StackSize => "stack size limit exceeded",
+            Error::SigCount => "signature count negative or greater than pubkey count",
+            Error::PubkeyCount => "pubkey count negative or limit exceeded",
+            Error::Verify => "script failed an OP_VERIFY operation",
+            Error::EqualVerify => "script failed an OP_EQUALVERIFY operation",
+            Error::CheckMultiSigVerify => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            Error::CheckSigVerify => "script failed an OP_CHECKSIGVERIFY operation",
+            Error::NumEqualVerify => "script failed an OP_NUMEQUALVERIFY operation",
+            Error::BadOpcode => "opcode missing or not understood",
+            Error::DisabledOpcode => "attempted to use a disabled opcode",
+            Error::InvalidStackOperation => "operation not valid with the current stack size",
+            Error::InvalidAltstackOperation => "operation not valid with the current altstack size",
+            Error::UnbalancedConditional => "invalid OP_IF construction",
+            Error::NegativeLocktime => "negative locktime",
+            Error::UnsatisfiedLocktime => "locktime requirement not satisfied",
+            Error::SigHashType => "signature hash type missing or not understood",
+            Error::SigDer => "non-canonical DER signature",
+            Error::MinimalData => "data push larger than necessary",
+            Error::SigPushOnly => "only push operators allowed in signatures",
+            Error::SigHighS => "non-canonical signature: S value is unnecessarily high",
+            Error::SigNullDummy => "dummy CHECKMULTISIG argument must be zero",
+            Error::PubkeyType => "public key is neither compressed or uncompressed",
+            Error::CleanStack => "stack size must be exactly one after execution",
+            Error::MinimalIf => "OP_IF/NOTIF argument must be minimal",
+            Error::SigNullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            Error::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            Error::DiscourageUpgradableWitnessProgram => "witness version reserved for soft-fork upgrades",
+            Error::WitnessProgramWrongLength => "witness program has incorrect length",
+            Error::WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+            Error::WitnessProgramMismatch => "witness program hash mismatch",
+            Error::WitnessMalleated => "witness requires empty scriptSig",
+            Error::WitnessMalleatedP2sh => "witness requires only-redeemscript scriptSig",
+            Error::WitnessUnexpected => "witness provided for non-witness script",
+            Error::WitnessPubkeyType => "using non-compressed keys in segwit",
+            Error::OpCodeSeparator => "using OP_CODESEPARATOR in non-witness script",
+            Error::SigFindAndDelete => "signature is found in scriptCode",
+            Error::NumericOverflow => "script number overflow",
+            Error::NonMinimalNumber => "non-minimally encoded script number",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.reason())
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+/// A failure while executing a single script, with the location at which
+/// execution stopped
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub struct ExecError {
+    /// Why execution failed
+    pub error: Error,
+    /// Byte offset of the failing opcode in the script, if the failure is
+    /// attributable to a single opcode
+    pub position: Option<usize>,
+    /// The failing opcode, if any
+    pub opcode: Option<opcodes::All>,
+}
+
+impl ExecError {
+    /// An error which is not attributable to a single opcode
+    fn new(error: Error) -> ExecError {
+        ExecError {
+            error,
+            position: None,
+            opcode: None,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)?;
+        if let Some(opcode) = self.opcode {
+            write!(f, " ({:?}", opcode)?;
+            if let Some(position) = self.position {
+                write!(f, " at position {}", position)?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ExecError {
+    fn cause(&self) -> Option<&error::Error> { Some(&self.error) }
+
+    fn description(&self) -> &str {
+        self.error.reason()
+    }
+}
+
+/// A script verification failure, recording which of the scripts involved
+/// in spending an output was being executed when it failed
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub struct VerifyError {
+    /// The script in which verification failed
+    pub stage: Stage,
+    /// The failure itself
+    pub error: ExecError,
+}
+
+impl VerifyError {
+    fn at(stage: Stage, error: ExecError) -> VerifyError {
+        VerifyError {
+            stage,
+            error,
+        }
+    }
+
+    fn new(stage: Stage, error: Error) -> VerifyError {
+        VerifyError::at(stage, ExecError::new(error))
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.stage, self.error)
+    }
+}
+
+impl error::Error for VerifyError {
+    fn cause(&self) -> Option<&error::Error> { Some(&self.error) }
+
+    fn description(&self) -> &str {
+        self.error.error.reason()
+    }
+}
+
+/// Checks signatures and locktimes on behalf of the interpreter. This is
+/// what ties a script to the transaction spending it.
+pub trait SignatureChecker {
+    /// Check a signature, including its trailing sighash type byte, against
+    /// a serialized public key. `script_code` is the script being signed as
+    /// determined by the interpreter, that is, after the last executed
+    /// OP_CODESEPARATOR and, for legacy scripts, with the signature removed.
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script, sig_version: SigVersion) -> bool;
+
+    /// Check the argument of an OP_CHECKLOCKTIMEVERIFY against the transaction
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+
+    /// Check the argument of an OP_CHECKSEQUENCEVERIFY against the transaction
+    fn check_sequence(&self, sequence: i64) -> bool;
+}
+
+/// A checker for scripts evaluated outside of any transaction: every
+/// signature and locktime check fails
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Default)]
+pub struct NoChecker;
+
+impl SignatureChecker for NoChecker {
+    fn check_sig(&self, _: &[u8], _: &[u8], _: &Script, _: SigVersion) -> bool { false }
+    fn check_lock_time(&self, _: i64) -> bool { false }
+    fn check_sequence(&self, _: i64) -> bool { false }
+}
+
+/// Checks signatures and locktimes against an input of a transaction
+pub struct TransactionSignatureChecker<'a> {
+    tx: &'a Transaction,
+    input_index: usize,
+    amount: u64,
+    secp: Secp256k1<VerifyOnly>,
+    cache: RefCell<SigHashCache<&'a Transaction>>,
+}
+
+impl<'a> TransactionSignatureChecker<'a> {
+    /// Create a checker for the given input of `tx`, which spends an output of
+    /// value `amount` (only needed to check segwit signatures)
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to `tx.input.len()`
+    pub fn new(tx: &'a Transaction, input_index: usize, amount: u64) -> TransactionSignatureChecker<'a> {
+        assert!(input_index < tx.input.len());
+        TransactionSignatureChecker {
+            tx,
+            input_index,
+            amount,
+            secp: Secp256k1::verification_only(),
+            cache: RefCell::new(SigHashCache::new(tx)),
+        }
+    }
+}
+
+impl<'a> SignatureChecker for TransactionSignatureChecker<'a> {
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script, sig_version: SigVersion) -> bool {
+        let pubkey = match secp256k1::PublicKey::from_slice(pubkey) {
+            Ok(pk) => pk,
+            Err(_) => return false,
+        };
+        if sig.is_empty() {
+            return false;
+        }
+        let hash_type = sig[sig.len() - 1] as u32;
+        let mut signature = match Signature::from_der_lax(&sig[..sig.len() - 1]) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        // libsecp256k1 only verifies lower-S signatures
+        signature.normalize_s();
+
+        let sighash = match sig_version {
+            SigVersion::Base => {
+                let script_code = remove_codeseparators(script_code);
+                self.tx.signature_hash(self.input_index, &script_code, hash_type)
+            }
+            SigVersion::WitnessV0 => {
+                let mut enc = sha256d::Hash::engine();
+                self.cache.borrow_mut().encode_signing_data_to_u32(
+                    &mut enc,
+                    self.input_index,
+                    script_code,
+                    self.amount,
+                    hash_type,
+                ).expect("engines don't error");
+                sha256d::Hash::from_engine(enc)
+            }
+        };
+        let msg = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+        self.secp.verify(&msg, &signature, &pubkey).is_ok()
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        // Both must be block heights or both timestamps
+        if (tx_lock_time < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+        // A final input would make the transaction's locktime ineffective
+        !Sequence(self.tx.input[self.input_index].sequence).is_final()
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.input[self.input_index].sequence as i64;
+        // Relative locktimes are only enforced from version 2 on (BIP68)
+        if self.tx.version < 2 {
+            return false;
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence & mask;
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// Decode a number from the stack, as Bitcoin Core's `CScriptNum`
+fn read_num(v: &[u8], require_minimal: bool, max_size: usize) -> Result<i64, Error> {
+    if v.len() > max_size {
+        return Err(Error::NumericOverflow);
+    }
+    if require_minimal && !v.is_empty() {
+        // The most significant byte may only be zero (bar the sign bit) if
+        // the next byte would otherwise be mistaken for the sign
+        let last = v[v.len() - 1];
+        if last & 0x7f == 0 && (v.len() <= 1 || v[v.len() - 2] & 0x80 == 0) {
+            return Err(Error::NonMinimalNumber);
+        }
+    }
+    if v.is_empty() {
+        return Ok(0);
+    }
+    let mut ret: i64 = 0;
+    for (i, byte) in v.iter().enumerate() {
+        ret |= (*byte as i64) << (8 * i);
+    }
+    if v[v.len() - 1] & 0x80 != 0 {
+        ret &= !(0x80i64 << (8 * (v.len() - 1)));
+        ret = -ret;
+    }
+    Ok(ret)
+}
+
+/// Clamp a stack number to the `i32` range, as `CScriptNum::getint`
+fn num_to_i32(n: i64) -> i32 {
+    if n as i32 as i64 == n {
+        n as i32
+    } else if n.is_negative() {
+        -0x8000_0000
+    } else {
+        0x7fff_ffff
+    }
+}
+
+fn bool_to_vec(b: bool) -> Vec<u8> {
+    if b { vec![1] } else { vec![] }
+}
+
+/// Whether a push used the smallest possible opcode for its data
+fn is_minimal_push(data: &[u8], opcode: opcodes::All) -> bool {
+    let op = opcode.into_u8();
+    if data.is_empty() {
+        op == opcodes::all::OP_PUSHBYTES_0.into_u8()
+    } else if data.len() == 1 && data[0] >= 1 && data[0] <= 16 {
+        // Should have used OP_1 .. OP_16
+        false
+    } else if data.len() == 1 && data[0] == 0x81 {
+        // Should have used OP_1NEGATE
+        false
+    } else if data.len() <= 75 {
+        op as usize == data.len()
+    } else if data.len() <= 255 {
+        opcode == opcodes::all::OP_PUSHDATA1
+    } else if data.len() <= 65535 {
+        opcode == opcodes::all::OP_PUSHDATA2
+    } else {
+        true
+    }
+}
+
+/// Whether a signature is strictly DER encoded as required by BIP66. The
+/// trailing sighash type byte is included in `sig`.
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 { return false; }
+    if sig[0] != 0x30 { return false; }
+    if sig[1] as usize != sig.len() - 3 { return false; }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() { return false; }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() { return false; }
+
+    if sig[2] != 0x02 { return false; }
+    if len_r == 0 { return false; }
+    // Negative R is not allowed
+    if sig[4] & 0x80 != 0 { return false; }
+    // Nor is unnecessary padding
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 { return false; }
+
+    if sig[len_r + 4] != 0x02 { return false; }
+    if len_s == 0 { return false; }
+    if sig[len_r + 6] & 0x80 != 0 { return false; }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 { return false; }
+    true
+}
+
+/// Whether a signature (with its sighash type byte) has a low S value
+fn is_low_der_signature(sig: &[u8]) -> bool {
+    match Signature::from_der_lax(&sig[..sig.len() - 1]) {
+        Ok(parsed) => {
+            let mut normalized = parsed;
+            normalized.normalize_s();
+            normalized == parsed
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether the sighash type byte of a signature is one of the defined ones
+fn is_defined_hashtype_signature(sig: &[u8]) -> bool {
+    if sig.is_empty() {
+        return false;
+    }
+    let hash_type = sig[sig.len() - 1] & !0x80;
+    hash_type != 0 && hash_type <= 0x03
+}
+
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    // Empty signatures are an explicit and compact way to provide an invalid
+    // signature, e.g. for CHECKMULTISIG
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if (flags.has(VerifyFlags::DERSIG) || flags.has(VerifyFlags::LOW_S) || flags.has(VerifyFlags::STRICTENC))
+        && !is_valid_signature_encoding(sig) {
+        Err(Error::SigDer)
+    } else if flags.has(VerifyFlags::LOW_S) && !is_low_der_signature(sig) {
+        Err(Error::SigHighS)
+    } else if flags.has(VerifyFlags::STRICTENC) && !is_defined_hashtype_signature(sig) {
+        Err(Error::SigHashType)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_pubkey_encoding(pubkey: &[u8], flags: VerifyFlags, sig_version: SigVersion) -> Result<(), Error> {
+    let compressed = pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03);
+    let uncompressed = pubkey.len() == 65 && pubkey[0] == 0x04;
+    if flags.has(VerifyFlags::STRICTENC) && !compressed && !uncompressed {
+        return Err(Error::PubkeyType);
+    }
+    if flags.has(VerifyFlags::WITNESS_PUBKEYTYPE) && sig_version == SigVersion::WitnessV0 && !compressed {
+        return Err(Error::WitnessPubkeyType);
+    }
+    Ok(())
+}
+
+/// Remove all occurrences of the push of `sig` from `script`, returning how
+/// many were found. Matches are only looked for at opcode boundaries.
+fn find_and_delete(script: &mut Script, sig: &[u8]) -> usize {
+    let pattern = Builder::new().push_slice(sig).into_script();
+    let pattern = pattern.as_bytes();
+    if pattern.is_empty() {
+        return 0;
+    }
+
+    let bytes = script.as_bytes();
+    let mut found = 0;
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut iter = script.iter(false);
+    let mut pos = 0;
+    let mut copied = 0;
+    loop {
+        result.extend_from_slice(&bytes[copied..pos]);
+        while bytes.len() - pos >= pattern.len() && &bytes[pos..pos + pattern.len()] == pattern {
+            pos += pattern.len();
+            found += 1;
+        }
+        copied = pos;
+        // Resume decoding after the deleted pushes
+        let skipped = pos - iter.position();
+        iter.advance(skipped);
+        match iter.next() {
+            None | Some(Instruction::Error(_)) => break,
+            Some(_) => pos = iter.position(),
+        }
+    }
+    if found > 0 {
+        result.extend_from_slice(&bytes[copied..]);
+        *script = Script::from(result);
+    }
+    found
+}
+
+/// The script code signed by legacy signatures, which leaves out all
+/// OP_CODESEPARATORs
+fn remove_codeseparators(script: &Script) -> Script {
+    let bytes = script.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut iter = script.iter(false);
+    let mut pos = 0;
+    loop {
+        match iter.next() {
+            None => break,
+            Some(Instruction::Error(_)) => {
+                result.extend_from_slice(&bytes[pos..]);
+                break;
+            }
+            Some(Instruction::Op(opcodes::all::OP_CODESEPARATOR)) => {}
+            Some(_) => result.extend_from_slice(&bytes[pos..iter.position()]),
+        }
+        pos = iter.position();
+    }
+    Script::from(result)
+}
+
+/// The state of a single script being executed
+struct Exec<'a, C: SignatureChecker + 'a> {
+    script: &'a Script,
+    instructions: Instructions<'a>,
+    flags: VerifyFlags,
+    checker: &'a C,
+    sig_version: SigVersion,
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+    /// For each enclosing OP_IF, whether its active branch is executed
+    exec_stack: Vec<bool>,
+    op_count: usize,
+    /// Position just after the last executed OP_CODESEPARATOR
+    code_separator: usize,
+}
+
+impl<'a, C: SignatureChecker> Exec<'a, C> {
+    fn new(
+        script: &'a Script,
+        stack: Vec<Vec<u8>>,
+        flags: VerifyFlags,
+        checker: &'a C,
+        sig_version: SigVersion,
+    ) -> Result<Exec<'a, C>, ExecError> {
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(ExecError::new(Error::ScriptSize));
+        }
+        Ok(Exec {
+            script,
+            instructions: script.iter(false),
+            flags,
+            checker,
+            sig_version,
+            stack,
+            altstack: vec![],
+            exec_stack: vec![],
+            op_count: 0,
+            code_separator: 0,
+        })
+    }
+
+    /// Execute the next opcode. Returns `Ok(false)` once the end of the script
+    /// has been successfully reached.
+    fn step(&mut self) -> Result<bool, ExecError> {
+        let position = self.instructions.position();
+        let instruction = match self.instructions.next() {
+            Some(ins) => ins,
+            None => {
+                if !self.exec_stack.is_empty() {
+                    return Err(ExecError {
+                        error: Error::UnbalancedConditional,
+                        position: Some(position),
+                        opcode: None,
+                    });
+                }
+                return Ok(false);
+            }
+        };
+        let opcode = opcodes::All::from(self.script.as_bytes()[position]);
+        self.execute(instruction, opcode).map_err(|error| ExecError {
+            error,
+            position: Some(position),
+            opcode: Some(opcode),
+        })?;
+        Ok(true)
+    }
+
+    /// Execute the next opcode, describing it as a `Step` of `stage`. Returns
+    /// `Ok(None)` once the end of the script has been successfully reached.
+    fn step_recorded(&mut self, stage: Stage) -> Result<Option<Step>, ExecError> {
+        let position = self.instructions.position();
+        let stack_before = self.stack.clone();
+        let executed = self.executing();
+        if !self.step()? {
+            return Ok(None);
+        }
+        let end = self.instructions.position();
+        Ok(Some(Step {
+            stage,
+            position,
+            opcode: opcodes::All::from(self.script.as_bytes()[position]),
+            instruction: Script::from(self.script.as_bytes()[position..end].to_vec()),
+            executed,
+            stack_before,
+            stack_after: self.stack.clone(),
+            altstack: self.altstack.clone(),
+            exec_stack: self.exec_stack.clone(),
+        }))
+    }
+
+    /// Run the script to its end, recording each step if the recorder is enabled
+    fn run(&mut self, recorder: &mut Recorder) -> Result<(), ExecError> {
+        loop {
+            if recorder.enabled {
+                match self.step_recorded(recorder.stage)? {
+                    Some(step) => recorder.steps.push(step),
+                    None => return Ok(()),
+                }
+            } else if !self.step()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether the current branch of the script is being executed
+    fn executing(&self) -> bool {
+        !self.exec_stack.contains(&false)
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, Error> {
+        self.stack.pop().ok_or(Error::InvalidStackOperation)
+    }
+
+    /// The element `depth` places from the top of the stack, `0` being the top
+    fn top(&self, depth: usize) -> Result<&Vec<u8>, Error> {
+        if depth < self.stack.len() {
+            Ok(&self.stack[self.stack.len() - 1 - depth])
+        } else {
+            Err(Error::InvalidStackOperation)
+        }
+    }
+
+    fn require(&self, n: usize) -> Result<(), Error> {
+        if self.stack.len() < n { Err(Error::InvalidStackOperation) } else { Ok(()) }
+    }
+
+    fn pop_num(&mut self) -> Result<i64, Error> {
+        let require_minimal = self.flags.has(VerifyFlags::MINIMALDATA);
+        let n = read_num(self.top(0)?, require_minimal, 4)?;
+        self.stack.pop();
+        Ok(n)
+    }
+
+    fn push_num(&mut self, n: i64) {
+        self.stack.push(script::build_scriptint(n));
+    }
+
+    fn execute(&mut self, instruction: Instruction, opcode: opcodes::All) -> Result<(), Error> {
+        use blockdata::opcodes::all::*;
+
+        let executing = self.executing();
+        let require_minimal = self.flags.has(VerifyFlags::MINIMALDATA);
+
+        let op = match instruction {
+            Instruction::Error(_) => return Err(Error::BadOpcode),
+            Instruction::PushBytes(data) => {
+                if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(Error::PushSize);
+                }
+                if executing {
+                    if require_minimal && !is_minimal_push(data, opcode) {
+                        return Err(Error::MinimalData);
+                    }
+                    self.stack.push(data.to_vec());
+                }
+                return self.check_stack_size();
+            }
+            Instruction::Op(op) => op,
+        };
+
+        if op.into_u8() > OP_PUSHNUM_16.into_u8() {
+            self.op_count += 1;
+            if self.op_count > MAX_OPS_PER_SCRIPT {
+                return Err(Error::OpCount);
+            }
+        }
+
+        // Disabled opcodes fail the script even in unexecuted branches
+        match op {
+            OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR |
+            OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT => {
+                return Err(Error::DisabledOpcode);
+            }
+            OP_CODESEPARATOR if self.sig_version == SigVersion::Base &&
+                                self.flags.has(VerifyFlags::CONST_SCRIPTCODE) => {
+                return Err(Error::OpCodeSeparator);
+            }
+            _ => {}
+        }
+
+        // Conditionals are tracked even in unexecuted branches
+        let is_conditional = op.into_u8() >= OP_IF.into_u8() && op.into_u8() <= OP_ENDIF.into_u8();
+        if !executing && !is_conditional {
+            return self.check_stack_size();
+        }
+
+        match op {
+            OP_PUSHNUM_NEG1 => self.push_num(-1),
+            _ if op.into_u8() >= OP_PUSHNUM_1.into_u8() && op.into_u8() <= OP_PUSHNUM_16.into_u8() => {
+                let n = op.into_u8() - OP_PUSHNUM_1.into_u8() + 1;
+                self.push_num(n as i64);
+            }
+
+            // Control
+            OP_NOP => {}
+            OP_CLTV => {
+                if !self.flags.has(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+                    return self.upgradable_nop();
+                }
+                // Locktimes may exceed the 4 byte number limit
+                let lock_time = read_num(self.top(0)?, require_minimal, 5)?;
+                if lock_time < 0 {
+                    return Err(Error::NegativeLocktime);
+                }
+                if !self.checker.check_lock_time(lock_time) {
+                    return Err(Error::UnsatisfiedLocktime);
+                }
+            }
+            OP_CSV => {
+                if !self.flags.has(VerifyFlags::CHECKSEQUENCEVERIFY) {
+                    return self.upgradable_nop();
+                }
+                let sequence = read_num(self.top(0)?, require_minimal, 5)?;
+                if sequence < 0 {
+                    return Err(Error::NegativeLocktime);
+                }
+                // With the disable flag set, CSV behaves as a NOP
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && !self.checker.check_sequence(sequence) {
+                    return Err(Error::UnsatisfiedLocktime);
+                }
+            }
+            OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 => {
+                return self.upgradable_nop();
+            }
+            OP_IF | OP_NOTIF => {
+                let mut value = false;
+                if executing {
+                    let condition = self.stack.pop().ok_or(Error::UnbalancedConditional)?;
+                    if self.sig_version == SigVersion::WitnessV0 && self.flags.has(VerifyFlags::MINIMALIF) &&
+                        (condition.len() > 1 || (condition.len() == 1 && condition[0] != 1)) {
+                        return Err(Error::MinimalIf);
+                    }
+                    value = script::read_scriptbool(&condition);
+                    if op == OP_NOTIF {
+                        value = !value;
+                    }
+                }
+                self.exec_stack.push(value);
+            }
+            OP_ELSE => {
+                let last = self.exec_stack.last_mut().ok_or(Error::UnbalancedConditional)?;
+                *last = !*last;
+            }
+            OP_ENDIF => {
+                self.exec_stack.pop().ok_or(Error::UnbalancedConditional)?;
+            }
+            OP_VERIFY => {
+                if !script::read_scriptbool(self.top(0)?) {
+                    return Err(Error::Verify);
+                }
+                self.stack.pop();
+            }
+            OP_RETURN => return Err(Error::OpReturn),
+
+            // Stack operations
+            OP_TOALTSTACK => {
+                let top = self.pop()?;
+                self.altstack.push(top);
+            }
+            OP_FROMALTSTACK => {
+                let top = self.altstack.pop().ok_or(Error::InvalidAltstackOperation)?;
+                self.stack.push(top);
+            }
+            OP_2DROP => {
+                self.require(2)?;
+                self.stack.pop();
+                self.stack.pop();
+            }
+            OP_2DUP => {
+                let (a, b) = (self.top(1)?.clone(), self.top(0)?.clone());
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            OP_3DUP => {
+                let (a, b, c) = (self.top(2)?.clone(), self.top(1)?.clone(), self.top(0)?.clone());
+                self.stack.push(a);
+                self.stack.push(b);
+                self.stack.push(c);
+            }
+            OP_2OVER => {
+                let (a, b) = (self.top(3)?.clone(), self.top(2)?.clone());
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            OP_2ROT => {
+                self.require(6)?;
+                let len = self.stack.len();
+                let moved: Vec<_> = self.stack.drain(len - 6..len - 4).collect();
+                self.stack.extend(moved);
+            }
+            OP_2SWAP => {
+                self.require(4)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 4, len - 2);
+                self.stack.swap(len - 3, len - 1);
+            }
+            OP_IFDUP => {
+                let top = self.top(0)?.clone();
+                if script::read_scriptbool(&top) {
+                    self.stack.push(top);
+                }
+            }
+            OP_DEPTH => {
+                let depth = self.stack.len() as i64;
+                self.push_num(depth);
+            }
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_DUP => {
+                let top = self.top(0)?.clone();
+                self.stack.push(top);
+            }
+            OP_NIP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            }
+            OP_OVER => {
+                let second = self.top(1)?.clone();
+                self.stack.push(second);
+            }
+            OP_PICK | OP_ROLL => {
+                self.require(2)?;
+                let n = num_to_i32(self.pop_num()?);
+                if n < 0 || n as usize >= self.stack.len() {
+                    return Err(Error::InvalidStackOperation);
+                }
+                let index = self.stack.len() - 1 - n as usize;
+                let item = if op == OP_ROLL {
+                    self.stack.remove(index)
+                } else {
+                    self.stack[index].clone()
+                };
+                self.stack.push(item);
+            }
+            OP_ROT => {
+                self.require(3)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 3, len - 2);
+                self.stack.swap(len - 2, len - 1);
+            }
+            OP_SWAP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 2, len - 1);
+            }
+            OP_TUCK => {
+                let top = self.top(0)?.clone();
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.insert(len - 2, top);
+            }
+            OP_SIZE => {
+                let size = self.top(0)?.len() as i64;
+                self.push_num(size);
+            }
+
+            // Bitwise logic
+            OP_EQUAL | OP_EQUALVERIFY => {
+                self.require(2)?;
+                let equal = self.pop()? == self.pop()?;
+                if op == OP_EQUALVERIFY {
+                    if !equal {
+                        return Err(Error::EqualVerify);
+                    }
+                } else {
+                    self.stack.push(bool_to_vec(equal));
+                }
+            }
+
+            // Numeric
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                self.require(1)?;
+                let n = self.pop_num()?;
+                let result = match op {
+                    OP_1ADD => n + 1,
+                    OP_1SUB => n - 1,
+                    OP_NEGATE => -n,
+                    OP_ABS => n.abs(),
+                    OP_NOT => (n == 0) as i64,
+                    _ => (n != 0) as i64,
+                };
+                self.push_num(result);
+            }
+            OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY |
+            OP_NUMNOTEQUAL | OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL |
+            OP_GREATERTHANOREQUAL | OP_MIN | OP_MAX => {
+                self.require(2)?;
+                let a = read_num(self.top(1)?, require_minimal, 4)?;
+                let b = read_num(self.top(0)?, require_minimal, 4)?;
+                self.stack.pop();
+                self.stack.pop();
+                let result = match op {
+                    OP_ADD => a + b,
+                    OP_SUB => a - b,
+                    OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                    OP_NUMNOTEQUAL => (a != b) as i64,
+                    OP_LESSTHAN => (a < b) as i64,
+                    OP_GREATERTHAN => (a > b) as i64,
+                    OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    OP_MIN => if a < b { a } else { b },
+                    _ => if a > b { a } else { b },
+                };
+                if op == OP_NUMEQUALVERIFY {
+                    if result == 0 {
+                        return Err(Error::NumEqualVerify);
+                    }
+                } else {
+                    self.push_num(result);
+                }
+            }
+            OP_WITHIN => {
+                self.require(3)?;
+                let x = read_num(self.top(2)?, require_minimal, 4)?;
+                let min = read_num(self.top(1)?, require_minimal, 4)?;
+                let max = read_num(self.top(0)?, require_minimal, 4)?;
+                self.stack.truncate(self.stack.len() - 3);
+                self.stack.push(bool_to_vec(min <= x && x < max));
+            }
+
+            // Crypto
+            OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                let data = self.pop()?;
+                let hash = match op {
+                    OP_RIPEMD160 => ripemd160::Hash::hash(&data)[..].to_vec(),
+                    OP_SHA1 => sha1::Hash::hash(&data)[..].to_vec(),
+                    OP_SHA256 => sha256::Hash::hash(&data)[..].to_vec(),
+                    OP_HASH160 => hash160::Hash::hash(&data)[..].to_vec(),
+                    _ => sha256d::Hash::hash(&data)[..].to_vec(),
+                };
+                self.stack.push(hash);
+            }
+            OP_CODESEPARATOR => {
+                self.code_separator = self.instructions.position();
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                self.require(2)?;
+                let sig = self.top(1)?.clone();
+                let pubkey = self.top(0)?.clone();
+
+                let mut script_code = self.script_code();
+                if self.sig_version == SigVersion::Base {
+                    // Drop the signature, since there's no way for a signature to sign itself
+                    let found = find_and_delete(&mut script_code, &sig);
+                    if found > 0 && self.flags.has(VerifyFlags::CONST_SCRIPTCODE) {
+                        return Err(Error::SigFindAndDelete);
+                    }
+                }
+
+                check_signature_encoding(&sig, self.flags)?;
+                check_pubkey_encoding(&pubkey, self.flags, self.sig_version)?;
+                let success = self.checker.check_sig(&sig, &pubkey, &script_code, self.sig_version);
+                if !success && self.flags.has(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+                    return Err(Error::SigNullFail);
+                }
+
+                self.stack.pop();
+                self.stack.pop();
+                if op == OP_CHECKSIGVERIFY {
+                    if !success {
+                        return Err(Error::CheckSigVerify);
+                    }
+                } else {
+                    self.stack.push(bool_to_vec(success));
+                }
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                // Stack layout, from the top:
+                //   <n> <pubkey_n> .. <pubkey_1> <m> <sig_m> .. <sig_1> <dummy>
+                let mut i = 1;
+                self.require(i)?;
+                let mut keys_count = num_to_i32(read_num(self.top(i - 1)?, require_minimal, 4)?) as i64;
+                if keys_count > MAX_PUBKEYS_PER_MULTISIG || keys_count.is_negative() {
+                    return Err(Error::PubkeyCount);
+                }
+                self.op_count += keys_count as usize;
+                if self.op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(Error::OpCount);
+                }
+                i += 1;
+                let mut ikey = i;
+                // The top stack element which has to be empty on failure under NULLFAIL
+                let mut ikey2 = keys_count + 2;
+                i += keys_count as usize;
+                self.require(i)?;
+                let mut sigs_count = num_to_i32(read_num(self.top(i - 1)?, require_minimal, 4)?) as i64;
+                if sigs_count < 0 || sigs_count > keys_count {
+                    return Err(Error::SigCount);
+                }
+                i += 1;
+                let mut isig = i;
+                i += sigs_count as usize;
+                self.require(i)?;
+
+                let mut script_code = self.script_code();
+                if self.sig_version == SigVersion::Base {
+                    for k in 0..sigs_count as usize {
+                        let sig = self.top(isig + k - 1)?.clone();
+                        let found = find_and_delete(&mut script_code, &sig);
+                        if found > 0 && self.flags.has(VerifyFlags::CONST_SCRIPTCODE) {
+                            return Err(Error::SigFindAndDelete);
+                        }
+                    }
+                }
+
+                let mut success = true;
+                while success && sigs_count > 0 {
+                    let sig = self.top(isig - 1)?;
+                    let pubkey = self.top(ikey - 1)?;
+                    check_signature_encoding(sig, self.flags)?;
+                    check_pubkey_encoding(pubkey, self.flags, self.sig_version)?;
+
+                    if self.checker.check_sig(sig, pubkey, &script_code, self.sig_version) {
+                        isig += 1;
+                        sigs_count -= 1;
+                    }
+                    ikey += 1;
+                    keys_count -= 1;
+
+                    // If there are more signatures left than keys left, then
+                    // too many signatures have failed
+                    if sigs_count > keys_count {
+                        success = false;
+                    }
+                }
+
+                // Clean up the stack of actual arguments
+                while i > 1 {
+                    i -= 1;
+                    if !success && self.flags.has(VerifyFlags::NULLFAIL) && ikey2 == 0 && !self.top(0)?.is_empty() {
+                        return Err(Error::SigNullFail);
+                    }
+                    if ikey2 > 0 {
+                        ikey2 -= 1;
+                    }
+                    self.stack.pop();
+                }
+
+                // An extra element is consumed due to an off-by-one in the
+                // original implementation, which has to be kept for consensus
+                let dummy = self.pop()?;
+                if self.flags.has(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+                    return Err(Error::SigNullDummy);
+                }
+
+                if op == OP_CHECKMULTISIGVERIFY {
+                    if !success {
+                        return Err(Error::CheckMultiSigVerify);
+                    }
+                } else {
+                    self.stack.push(bool_to_vec(success));
+                }
+            }
+
+            _ => return Err(Error::BadOpcode),
+        }
+
+        self.check_stack_size()
+    }
+
+    fn upgradable_nop(&self) -> Result<(), Error> {
+        if self.flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+            Err(Error::DiscourageUpgradableNops)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_stack_size(&self) -> Result<(), Error> {
+        if self.stack.len() + self.altstack.len() > MAX_STACK_SIZE {
+            Err(Error::StackSize)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The part of the script signed by signature checks
+    fn script_code(&self) -> Script {
+        Script::from(self.script.as_bytes()[self.code_separator..].to_vec())
+    }
+}
+
+/// Executes a single script one instruction at a time, for debugging. Unlike
+/// `trace_script`, the caller decides when to stop and can inspect the stacks
+/// between any two instructions.
+pub struct Stepper<'a, C: SignatureChecker + 'a> {
+    exec: Exec<'a, C>,
+    stage: Stage,
+    finished: bool,
+}
+
+impl<'a, C: SignatureChecker> Stepper<'a, C> {
+    /// Prepare to execute `script` on top of `stack`, as `eval_script` would.
+    /// Returned steps are labelled with `stage`.
+    pub fn new(
+        script: &'a Script,
+        stack: Vec<Vec<u8>>,
+        stage: Stage,
+        flags: VerifyFlags,
+        checker: &'a C,
+        sig_version: SigVersion,
+    ) -> Result<Stepper<'a, C>, ExecError> {
+        Ok(Stepper {
+            exec: Exec::new(script, stack, flags, checker, sig_version)?,
+            stage,
+            finished: false,
+        })
+    }
+
+    /// Execute the next instruction. Returns `Ok(None)` once the script has
+    /// run to its end; after an error or the end, no further steps are taken.
+    pub fn step(&mut self) -> Result<Option<Step>, ExecError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let result = self.exec.step_recorded(self.stage);
+        match result {
+            Ok(Some(_)) => {}
+            _ => self.finished = true,
+        }
+        result
+    }
+
+    /// Whether the script has run to its end or failed
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Byte offset of the next instruction to be executed
+    pub fn position(&self) -> usize {
+        self.exec.instructions.position()
+    }
+
+    /// The current stack, top element last
+    pub fn stack(&self) -> &[Vec<u8>] {
+        &self.exec.stack
+    }
+
+    /// The current alt stack, top element last
+    pub fn altstack(&self) -> &[Vec<u8>] {
+        &self.exec.altstack
+    }
+
+    /// The current condition stack, see `Step::exec_stack`
+    pub fn exec_stack(&self) -> &[bool] {
+        &self.exec.exec_stack
+    }
+
+    /// Give up the stepper, returning the current stack
+    pub fn into_stack(self) -> Vec<Vec<u8>> {
+        self.exec.stack
+    }
+}
+
+/// Execute `script` on top of `stack`, leaving the resulting stack in place.
+/// This is a single script evaluation; use `verify_script` to check the
+/// spend of an output.
+pub fn eval_script<C: SignatureChecker>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerifyFlags,
+    checker: &C,
+    sig_version: SigVersion,
+) -> Result<(), ExecError> {
+    eval_script_recorded(stack, script, flags, checker, sig_version, &mut Recorder::disabled())
+}
+
+fn eval_script_recorded<C: SignatureChecker>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerifyFlags,
+    checker: &C,
+    sig_version: SigVersion,
+    recorder: &mut Recorder,
+) -> Result<(), ExecError> {
+    let mut exec = Exec::new(script, vec![], flags, checker, sig_version)?;
+    mem::swap(&mut exec.stack, stack);
+    let result = exec.run(recorder);
+    *stack = exec.stack;
+    result
+}
+
+/// Evaluate one of the scripts involved in verifying a spend
+fn eval_stage<C: SignatureChecker>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    stage: Stage,
+    flags: VerifyFlags,
+    checker: &C,
+    sig_version: SigVersion,
+    recorder: &mut Recorder,
+) -> Result<(), VerifyError> {
+    recorder.stage = stage;
+    eval_script_recorded(stack, script, flags, checker, sig_version, recorder)
+        .map_err(|e| VerifyError::at(stage, e))
+}
+
+/// Check that a stack left by a script is successful
+fn check_eval_true(stack: &[Vec<u8>], stage: Stage) -> Result<(), VerifyError> {
+    match stack.last() {
+        Some(top) if script::read_scriptbool(top) => Ok(()),
+        _ => Err(VerifyError::new(stage, Error::EvalFalse)),
+    }
+}
+
+fn verify_witness_program<C: SignatureChecker>(
+    witness: &[Vec<u8>],
+    version: u8,
+    program: &[u8],
+    flags: VerifyFlags,
+    checker: &C,
+    recorder: &mut Recorder,
+) -> Result<(), VerifyError> {
+    let stage = Stage::WitnessScript;
+    let (mut stack, script) = if version == 0 {
+        if program.len() == 32 {
+            // P2WSH: the last witness element is the script, committed to by the program
+            let (script, stack) = match witness.split_last() {
+                Some((script, stack)) => (Script::from(script.clone()), stack.to_vec()),
+                None => return Err(VerifyError::new(stage, Error::WitnessProgramWitnessEmpty)),
+            };
+            if sha256::Hash::hash(script.as_bytes())[..] != *program {
+                return Err(VerifyError::new(stage, Error::WitnessProgramMismatch));
+            }
+            (stack, script)
+        } else if program.len() == 20 {
+            // P2WPKH: the witness is a signature and public key for an implied P2PKH script
+            if witness.len() != 2 {
+                return Err(VerifyError::new(stage, Error::WitnessProgramMismatch));
+            }
+            let script = Builder::new()
+                .push_opcode(opcodes::all::OP_DUP)
+                .push_opcode(opcodes::all::OP_HASH160)
+                .push_slice(program)
+                .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+                .into_script();
+            (witness.to_vec(), script)
+        } else {
+            return Err(VerifyError::new(stage, Error::WitnessProgramWrongLength));
+        }
+    } else if flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+        return Err(VerifyError::new(stage, Error::DiscourageUpgradableWitnessProgram));
+    } else {
+        // Higher version witness scripts return true for future softfork compatibility
+        return Ok(());
+    };
+
+    if stack.iter().any(|elem| elem.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(VerifyError::new(stage, Error::PushSize));
+    }
+
+    eval_stage(&mut stack, &script, stage, flags, checker, SigVersion::WitnessV0, recorder)?;
+
+    // Scripts inside witness implicitly require cleanstack behaviour
+    if stack.len() != 1 {
+        return Err(VerifyError::new(stage, Error::CleanStack));
+    }
+    check_eval_true(&stack, stage)
+}
+
+/// The version and program of a witness program script
+fn witness_program(script: &Script) -> Option<(u8, &[u8])> {
+    if !script.is_witness_program() {
+        return None;
+    }
+    let version = match script.as_bytes()[0] {
+        0 => 0,
+        op => op - opcodes::all::OP_PUSHNUM_1.into_u8() + 1,
+    };
+    Some((version, &script.as_bytes()[2..]))
+}
+
+/// Verify that `script_sig` and `witness` satisfy `script_pubkey` under the
+/// given flags, as Bitcoin Core's `VerifyScript`
+pub fn verify_script<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    flags: VerifyFlags,
+    checker: &C,
+) -> Result<(), VerifyError> {
+    verify_script_recorded(script_sig, script_pubkey, witness, flags, checker, &mut Recorder::disabled())
+}
+
+/// Verify a spend like `verify_script`, recording every step of execution.
+/// The trace ends at the step before the failing instruction, if any; the
+/// failure itself is in `Trace::result`.
+pub fn trace_script<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    flags: VerifyFlags,
+    checker: &C,
+) -> Trace {
+    let mut recorder = Recorder {
+        enabled: true,
+        stage: Stage::ScriptSig,
+        steps: vec![],
+    };
+    let result = verify_script_recorded(script_sig, script_pubkey, witness, flags, checker, &mut recorder);
+    Trace {
+        steps: recorder.steps,
+        result,
+    }
+}
+
+fn verify_script_recorded<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    flags: VerifyFlags,
+    checker: &C,
+    recorder: &mut Recorder,
+) -> Result<(), VerifyError> {
+    let mut had_witness = false;
+
+    if flags.has(VerifyFlags::SIGPUSHONLY) && !script_sig.is_push_only() {
+        return Err(VerifyError::new(Stage::ScriptSig, Error::SigPushOnly));
+    }
+
+    // scriptSig and scriptPubKey are evaluated sequentially on the same stack
+    let mut stack = vec![];
+    eval_stage(&mut stack, script_sig, Stage::ScriptSig, flags, checker, SigVersion::Base, recorder)?;
+    let stack_copy = if flags.has(VerifyFlags::P2SH) { stack.clone() } else { vec![] };
+    eval_stage(&mut stack, script_pubkey, Stage::ScriptPubkey, flags, checker, SigVersion::Base, recorder)?;
+    check_eval_true(&stack, Stage::ScriptPubkey)?;
+
+    // Bare witness programs
+    if flags.has(VerifyFlags::WITNESS) {
+        if let Some((version, program)) = witness_program(script_pubkey) {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                return Err(VerifyError::new(Stage::ScriptSig, Error::WitnessMalleated));
+            }
+            verify_witness_program(witness, version, program, flags, checker, recorder)?;
+            // Bypass the cleanstack check at the end. The actual stack is obviously not clean
+            stack.truncate(1);
+        }
+    }
+
+    // Additional validation for spend-to-script-hash transactions
+    if flags.has(VerifyFlags::P2SH) && script_pubkey.is_p2sh() {
+        if !script_sig.is_push_only() {
+            return Err(VerifyError::new(Stage::ScriptSig, Error::SigPushOnly));
+        }
+
+        stack = stack_copy;
+        // The stack cannot be empty here, or the P2SH HASH160 <> EQUAL
+        // scriptPubKey would have failed on an empty stack above
+        let redeem_script = Script::from(stack.pop().expect("non-empty stack"));
+
+        eval_stage(&mut stack, &redeem_script, Stage::RedeemScript, flags, checker, SigVersion::Base, recorder)?;
+        check_eval_true(&stack, Stage::RedeemScript)?;
+
+        // P2SH witness program
+        if flags.has(VerifyFlags::WITNESS) {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                // The scriptSig must be _exactly_ a single push of the redeemScript,
+                // otherwise we reintroduce malleability
+                let expected = Builder::new().push_slice(redeem_script.as_bytes()).into_script();
+                if *script_sig != expected {
+                    return Err(VerifyError::new(Stage::ScriptSig, Error::WitnessMalleatedP2sh));
+                }
+                verify_witness_program(witness, version, program, flags, checker, recorder)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    // The CLEANSTACK check is only performed after potential P2SH evaluation,
+    // as the non-P2SH evaluation of a P2SH script will obviously not result in
+    // a clean stack (the P2SH inputs remain)
+    if flags.has(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        let stage = if flags.has(VerifyFlags::P2SH) && script_pubkey.is_p2sh() {
+            Stage::RedeemScript
+        } else {
+            Stage::ScriptPubkey
+        };
+        return Err(VerifyError::new(stage, Error::CleanStack));
+    }
+
+    if flags.has(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+        return Err(VerifyError::new(Stage::WitnessScript, Error::WitnessUnexpected));
+    }
+
+    Ok(())
+}
+
+/// A single instruction executed while verifying a spend, as recorded by
+/// `trace_script` or returned by `Stepper::step`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Step {
+    /// The script the instruction belongs to
+    pub stage: Stage,
+    /// Byte offset of the instruction in its script
+    pub position: usize,
+    /// The opcode, which for data pushes is the push opcode
+    pub opcode: opcodes::All,
+    /// The instruction including any pushed data, as a script of its own
+    pub instruction: Script,
+    /// Whether the instruction was in an executed branch. Unexecuted
+    /// instructions are still recorded, as conditionals are evaluated
+    /// regardless and other opcodes may fail when disabled or invalid.
+    pub executed: bool,
+    /// The stack before the instruction, top element last
+    pub stack_before: Vec<Vec<u8>>,
+    /// The stack after the instruction, top element last
+    pub stack_after: Vec<Vec<u8>>,
+    /// The alt stack after the instruction, top element last
+    pub altstack: Vec<Vec<u8>>,
+    /// The condition stack after the instruction: for each enclosing
+    /// OP_IF/OP_NOTIF, whether the branch being executed is active
+    pub exec_stack: Vec<bool>,
+}
+
+/// Write stack elements as hex, space separated and top element last
+fn fmt_stack(f: &mut fmt::Formatter, stack: &[Vec<u8>]) -> fmt::Result {
+    f.write_str("[")?;
+    for (i, elem) in stack.iter().enumerate() {
+        if i > 0 {
+            f.write_str(" ")?;
+        }
+        for byte in elem {
+            write!(f, "{:02x}", byte)?;
+        }
+    }
+    f.write_str("]")
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {}: ", self.stage, self.position)?;
+        self.instruction.fmt_asm(f)?;
+        if !self.executed {
+            f.write_str(" (not executed)")?;
+        }
+        f.write_str(" stack: ")?;
+        fmt_stack(f, &self.stack_after)?;
+        if !self.altstack.is_empty() {
+            f.write_str(" altstack: ")?;
+            fmt_stack(f, &self.altstack)?;
+        }
+        if !self.exec_stack.is_empty() {
+            f.write_str(" conditions: [")?;
+            for (i, cond) in self.exec_stack.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                f.write_str(if *cond { "true" } else { "false" })?;
+            }
+            f.write_str("]")?;
+        }
+        Ok(())
+    }
+}
+
+/// The steps taken while verifying a spend, and the verification result
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Trace {
+    /// Every instruction successfully executed, in order
+    pub steps: Vec<Step>,
+    /// The outcome of verification, as returned by `verify_script`
+    pub result: Result<(), VerifyError>,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        match self.result {
+            Ok(()) => f.write_str("success"),
+            Err(ref e) => write!(f, "failure: {}", e),
+        }
+    }
+}
+
+/// Collects steps for `trace_script`. When disabled nothing is recorded, so
+/// plain verification doesn't pay for copying stacks.
+struct Recorder {
+    enabled: bool,
+    /// The script currently being executed
+    stage: Stage,
+    steps: Vec<Step>,
+}
+
+impl Recorder {
+    fn disabled() -> Recorder {
+        Recorder {
+            enabled: false,
+            stage: Stage::ScriptSig,
+            steps: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+    use serde_json;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use super::*;
+
+    /// Parse a script in the format of Bitcoin Core's `script_tests.json`
+    fn parse_script(s: &str) -> Script {
+        Script::from_asm(s).unwrap()
+    }
+
+    /// Parse the flags of a test vector, or `None` if a flag is one the
+    /// interpreter does not implement
+    fn parse_flags(s: &str) -> Option<VerifyFlags> {
+        let mut flags = VerifyFlags::NONE;
+        for name in s.split(',').filter(|n| !n.is_empty()) {
+            flags |= match name {
+                "NONE" => VerifyFlags::NONE,
+                "P2SH" => VerifyFlags::P2SH,
+                "STRICTENC" => VerifyFlags::STRICTENC,
+                "DERSIG" => VerifyFlags::DERSIG,
+                "LOW_S" => VerifyFlags::LOW_S,
+                "NULLDUMMY" => VerifyFlags::NULLDUMMY,
+                "SIGPUSHONLY" => VerifyFlags::SIGPUSHONLY,
+                "MINIMALDATA" => VerifyFlags::MINIMALDATA,
+                "DISCOURAGE_UPGRADABLE_NOPS" => VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS,
+                "CLEANSTACK" => VerifyFlags::CLEANSTACK,
+                "CHECKLOCKTIMEVERIFY" => VerifyFlags::CHECKLOCKTIMEVERIFY,
+                "CHECKSEQUENCEVERIFY" => VerifyFlags::CHECKSEQUENCEVERIFY,
+                "WITNESS" => VerifyFlags::WITNESS,
+                "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM" => VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM,
+                "MINIMALIF" => VerifyFlags::MINIMALIF,
+                "NULLFAIL" => VerifyFlags::NULLFAIL,
+                "WITNESS_PUBKEYTYPE" => VerifyFlags::WITNESS_PUBKEYTYPE,
+                "CONST_SCRIPTCODE" => VerifyFlags::CONST_SCRIPTCODE,
+                "TAPROOT" | "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION" |
+                "DISCOURAGE_OP_SUCCESS" | "DISCOURAGE_UPGRADABLE_PUBKEYTYPE" => return None,
+                _ => panic!("unknown flag {}", name),
+            };
+        }
+        Some(flags)
+    }
+
+    /// The name Bitcoin Core's tests use for an error
+    fn error_name(e: Error) -> &'static str {
+        match e {
+            Error::EvalFalse => "EVAL_FALSE",
+            Error::OpReturn => "OP_RETURN",
+            Error::ScriptSize => "SCRIPT_SIZE",
+            Error::PushSize => "PUSH_SIZE",
+            Error::OpCount => "OP_COUNT",
+            Error::StackSize => "STACK_SIZE",
+            Error::SigCount => "SIG_COUNT",
+            Error::PubkeyCount => "PUBKEY_COUNT",
+            Error::Verify => "VERIFY",
+            Error::EqualVerify => "EQUALVERIFY",
+            Error::CheckMultiSigVerify => "CHECKMULTISIGVERIFY",
+            Error::CheckSigVerify => "CHECKSIGVERIFY",
+            Error::NumEqualVerify => "NUMEQUALVERIFY",
+            Error::BadOpcode => "BAD_OPCODE",
+            Error::DisabledOpcode => "DISABLED_OPCODE",
+            Error::InvalidStackOperation => "INVALID_STACK_OPERATION",
+            Error::InvalidAltstackOperation => "INVALID_ALTSTACK_OPERATION",
+            Error::UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+            Error::NegativeLocktime => "NEGATIVE_LOCKTIME",
+            Error::UnsatisfiedLocktime => "UNSATISFIED_LOCKTIME",
+            Error::SigHashType => "SIG_HASHTYPE",
+            Error::SigDer => "SIG_DER",
+            Error::MinimalData => "MINIMALDATA",
+            Error::SigPushOnly => "SIG_PUSHONLY",
+            Error::SigHighS => "SIG_HIGH_S",
+            Error::SigNullDummy => "SIG_NULLDUMMY",
+            Error::PubkeyType => "PUBKEYTYPE",
+            Error::CleanStack => "CLEANSTACK",
+            Error::MinimalIf => "MINIMALIF",
+            Error::SigNullFail => "NULLFAIL",
+            Error::DiscourageUpgradableNops => "DISCOURAGE_UPGRADABLE_NOPS",
+            Error::DiscourageUpgradableWitnessProgram => "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+            Error::WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+            Error::WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+            Error::WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+            Error::WitnessMalleated => "WITNESS_MALLEATED",
+            Error::WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+            Error::WitnessUnexpected => "WITNESS_UNEXPECTED",
+            Error::WitnessPubkeyType => "WITNESS_PUBKEYTYPE",
+            Error::OpCodeSeparator => "OP_CODESEPARATOR",
+            Error::SigFindAndDelete => "SIG_FINDANDDELETE",
+            // Core reports script number exceptions as unknown errors
+            Error::NumericOverflow | Error::NonMinimalNumber => "UNKNOWN_ERROR",
+        }
+    }
+
+    /// The transaction creating the output spent by a test vector
+    fn crediting_tx(script_pubkey: &Script, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(0).push_int(0).into_script(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: script_pubkey.clone(),
+            }],
+        }
+    }
+
+    /// The transaction spending the output of `crediting_tx` in a test vector
+    fn spending_tx(script_sig: &Script, witness: &[Vec<u8>], credit: &Transaction) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(credit.txid(), 0),
+                script_sig: script_sig.clone(),
+                sequence: 0xffffffff,
+                witness: witness.to_vec(),
+            }],
+            output: vec![TxOut {
+                value: credit.output[0].value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    struct Vector {
+        script_sig: Script,
+        script_pubkey: Script,
+        witness: Vec<Vec<u8>>,
+        amount: u64,
+        flags: VerifyFlags,
+        expected: String,
+        description: String,
+    }
+
+    /// The vectors of `script_tests.json`, skipping those with flags the
+    /// interpreter does not implement
+    fn script_test_vectors() -> Vec<Vector> {
+        let json: serde_json::Value = serde_json::from_str(include_str!("../../test_data/script_tests.json")).unwrap();
+        let mut vectors = vec![];
+        for test in json.as_array().unwrap() {
+            let test = test.as_array().unwrap();
+            // Single element entries are comments
+            if test.len() == 1 {
+                continue;
+            }
+            let pos = if test[0].is_array() { 1 } else { 0 };
+            // Skip vectors for unimplemented flags before parsing anything
+            // else, as their witnesses and scripts may use unsupported syntax
+            let flags = match parse_flags(test[pos + 2].as_str().unwrap()) {
+                Some(flags) => flags,
+                None => continue,
+            };
+            let (witness, amount) = match test[0].as_array() {
+                Some(wit) => {
+                    let (amount, items) = wit.split_last().unwrap();
+                    let items = items.iter().map(|w| Vec::<u8>::from_hex(w.as_str().unwrap()).unwrap()).collect();
+                    (items, (amount.as_f64().unwrap() * 100_000_000.0).round() as u64)
+                }
+                None => (vec![], 0),
+            };
+            vectors.push(Vector {
+                script_sig: parse_script(test[pos].as_str().unwrap()),
+                script_pubkey: parse_script(test[pos + 1].as_str().unwrap()),
+                witness,
+                amount,
+                flags,
+                expected: test[pos + 3].as_str().unwrap().to_owned(),
+                description: test.get(pos + 4).and_then(|d| d.as_str()).unwrap_or("").to_owned(),
+            });
+        }
+        vectors
+    }
+
+    fn run_vector(v: &Vector) -> Result<(), VerifyError> {
+        let credit = crediting_tx(&v.script_pubkey, v.amount);
+        let spend = spending_tx(&v.script_sig, &v.witness, &credit);
+        let checker = TransactionSignatureChecker::new(&spend, 0, v.amount);
+        verify_script(&v.script_sig, &v.script_pubkey, &v.witness, v.flags, &checker)
+    }
+
+    #[test]
+    fn script_tests() {
+        let vectors = script_test_vectors();
+        assert!(vectors.len() > 100);
+        for v in &vectors {
+            let result = match run_vector(v) {
+                Ok(()) => "OK",
+                Err(e) => error_name(e.error.error),
+            };
+            assert_eq!(
+                result, v.expected,
+                "script_sig: {}, script_pubkey: {}, flags: {:?}: {}",
+                v.script_sig.asm(), v.script_pubkey.asm(), v.flags, v.description
+            );
+        }
+    }
+
+    #[test]
+    fn stepper() {
+        let script = parse_script("1 2 ADD 4 EQUALVERIFY");
+        let mut stepper = Stepper::new(
+            &script, vec![], Stage::ScriptPubkey, VerifyFlags::NONE, &NoChecker, SigVersion::Base,
+        ).unwrap();
+
+        // Stop after the addition and look at the stack
+        for _ in 0..3 {
+            assert!(stepper.step().unwrap().is_some());
+        }
+        assert_eq!(stepper.position(), 3);
+        assert_eq!(stepper.stack(), &[vec![3]][..]);
+        assert!(!stepper.is_finished());
+
+        let step = stepper.step().unwrap().unwrap();
+        assert_eq!(step.to_string(), "ScriptPubkey 3: OP_PUSHNUM_4 stack: [03 04]");
+        let err = stepper.step().unwrap_err();
+        assert_eq!(err.error, Error::EqualVerify);
+        assert_eq!(err.position, Some(4));
+        assert!(stepper.is_finished());
+        assert_eq!(stepper.step(), Ok(None));
+
+        let script = parse_script("1 2");
+        let mut stepper = Stepper::new(
+            &script, vec![vec![0]], Stage::ScriptSig, VerifyFlags::NONE, &NoChecker, SigVersion::Base,
+        ).unwrap();
+        while stepper.step().unwrap().is_some() {}
+        assert_eq!(stepper.into_stack(), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn script_limits() {
+        let eval = |script: &Script| {
+            let mut stack = vec![];
+            eval_script(&mut stack, script, VerifyFlags::NONE, &NoChecker, SigVersion::Base)
+        };
+
+        let push = Builder::new().push_slice(&[0; MAX_SCRIPT_ELEMENT_SIZE]).into_script();
+        assert!(eval(&push).is_ok());
+        let push = Builder::new().push_slice(&[0; MAX_SCRIPT_ELEMENT_SIZE + 1]).into_script();
+        let err = eval(&push).unwrap_err();
+        assert_eq!(err.error, Error::PushSize);
+        assert_eq!(err.position, Some(0));
+
+        let mut nops = Builder::new();
+        for _ in 0..MAX_OPS_PER_SCRIPT {
+            nops = nops.push_opcode(opcodes::all::OP_NOP);
+        }
+        assert!(eval(&nops.clone().into_script()).is_ok());
+        let err = eval(&nops.push_opcode(opcodes::all::OP_NOP).into_script()).unwrap_err();
+        assert_eq!(err.error, Error::OpCount);
+        assert_eq!(err.position, Some(MAX_OPS_PER_SCRIPT));
+
+        let mut pushes = Builder::new();
+        for _ in 0..MAX_STACK_SIZE {
+            pushes = pushes.push_int(1);
+        }
+        assert!(eval(&pushes.clone().into_script()).is_ok());
+        let err = eval(&pushes.push_int(1).into_script()).unwrap_err();
+        assert_eq!(err.error, Error::StackSize);
+
+        let big = Script::from(vec![opcodes::all::OP_NOP.into_u8(); MAX_SCRIPT_SIZE + 1]);
+        assert_eq!(eval(&big).unwrap_err().error, Error::ScriptSize);
+    }
+
+    #[test]
+    fn trace() {
+        let trace = trace_script(
+            &parse_script("1 2"), &parse_script("ADD 3 EQUAL"), &[], VerifyFlags::P2SH, &NoChecker,
+        );
+        assert_eq!(trace.result, Ok(()));
+        assert_eq!(trace.steps.len(), 5);
+        assert_eq!(trace.steps[1].stage, Stage::ScriptSig);
+        assert_eq!(trace.steps[1].stack_after, vec![vec![1], vec![2]]);
+        assert_eq!(trace.steps[2].stage, Stage::ScriptPubkey);
+        assert_eq!(trace.steps[2].opcode, opcodes::all::OP_ADD);
+        assert_eq!(trace.steps[2].stack_before, vec![vec![1], vec![2]]);
+        assert_eq!(trace.steps[2].stack_after, vec![vec![3]]);
+        assert_eq!(trace.steps[2].to_string(), "ScriptPubkey 0: OP_ADD stack: [03]");
+        assert!(trace.to_string().ends_with("ScriptPubkey 2: OP_EQUAL stack: [01]\nsuccess"));
+
+        // Unexecuted branches and the condition stack
+        let trace = trace_script(
+            &parse_script("0"), &parse_script("IF 0x02 0x0102 ELSE 3 ENDIF 4 EQUAL"), &[], VerifyFlags::P2SH, &NoChecker,
+        );
+        let steps: Vec<_> = trace.steps.iter().map(|s| (s.position, s.executed, s.exec_stack.clone())).collect();
+        assert_eq!(steps, vec![
+            (0, true, vec![]),
+            (0, true, vec![false]),
+            (1, false, vec![false]),
+            (4, false, vec![true]),
+            (5, true, vec![true]),
+            (6, true, vec![]),
+            (7, true, vec![]),
+            (8, true, vec![]),
+        ]);
+        assert_eq!(trace.steps[2].to_string(), "ScriptPubkey 1: OP_PUSHBYTES_2 0102 (not executed) stack: [] conditions: [false]");
+        assert_eq!(trace.result, Err(VerifyError::new(Stage::ScriptPubkey, Error::EvalFalse)));
+
+        // The trace stops before a failing instruction
+        let trace = trace_script(
+            &parse_script("1"), &parse_script("TOALTSTACK 1 CHECKLOCKTIMEVERIFY"),
+            &[], VerifyFlags::CHECKLOCKTIMEVERIFY, &NoChecker,
+        );
+        assert_eq!(trace.steps.len(), 3);
+        assert_eq!(trace.steps[2].to_string(), "ScriptPubkey 1: OP_PUSHNUM_1 stack: [01] altstack: [01]");
+        let err = trace.result.unwrap_err();
+        assert_eq!(err.stage, Stage::ScriptPubkey);
+        assert_eq!(err.error.error, Error::UnsatisfiedLocktime);
+        assert_eq!(err.error.position, Some(2));
+        assert_eq!(err.error.opcode, Some(opcodes::all::OP_CLTV));
+    }
+
+    #[test]
+    fn find_and_delete_signature() {
+        let sig = [0xaa, 0xbb];
+        let mut script = Builder::new()
+            .push_slice(&sig).push_opcode(opcodes::all::OP_DROP)
+            .push_slice(&sig).push_slice(&[0xaa])
+            .into_script();
+        assert_eq!(find_and_delete(&mut script, &sig), 2);
+        assert_eq!(script, Builder::new().push_opcode(opcodes::all::OP_DROP).push_slice(&[0xaa]).into_script());
+
+        // Consecutive matches are deleted in one pass
+        let mut builder = Builder::new();
+        for _ in 0..3000 {
+            builder = builder.push_slice(&sig);
+        }
+        let mut script = builder.push_opcode(opcodes::all::OP_DROP).into_script();
+        assert_eq!(find_and_delete(&mut script, &sig), 3000);
+        assert_eq!(script, Builder::new().push_opcode(opcodes::all::OP_DROP).into_script());
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoinconsensus")]
+    fn script_tests_match_bitcoinconsensus() {
+        use bitcoinconsensus;
+        use consensus::encode::serialize;
+
+        // The flags libbitcoinconsensus accepts
+        let supported = VerifyFlags::P2SH | VerifyFlags::DERSIG | VerifyFlags::NULLDUMMY |
+            VerifyFlags::CHECKLOCKTIMEVERIFY | VerifyFlags::CHECKSEQUENCEVERIFY | VerifyFlags::WITNESS;
+        for v in script_test_vectors() {
+            if !supported.has(v.flags) {
+                continue;
+            }
+            let credit = crediting_tx(&v.script_pubkey, v.amount);
+            let spend = spending_tx(&v.script_sig, &v.witness, &credit);
+            let native = run_vector(&v);
+            let library = bitcoinconsensus::verify_with_flags(
+                v.script_pubkey.as_bytes(), v.amount, &serialize(&spend), 0, v.flags.as_u32()
+            );
+            assert_eq!(native.is_ok(), library.is_ok(), "{}: {:?} vs {:?}", v.description, native, library);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Lock times
+//!
+//! This module gives meaning to the raw `lock_time` of a transaction and
+//! `sequence` of its inputs: an absolute lock time is a block height or a
+//! UNIX time, and a sequence number may carry a relative lock time (BIP68)
+//! and signal replaceability (BIP125).
+//!
+
+use std::cmp;
+
+/// Lock times below this value are block heights, others are UNIX times
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// Sequence number flag which disables the relative lock time (BIP68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// Sequence number flag which makes the relative lock time a time rather
+/// than a number of blocks (BIP68)
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// Mask of the relative lock time value within a sequence number (BIP68)
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+/// Relative lock times in time are in units of 2^9 = 512 seconds (BIP68)
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// An absolute lock time: a transaction with it can be included in a block
+/// only after the given height or median time past
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LockTime {
+    /// A block height, below `LOCK_TIME_THRESHOLD`
+    Blocks(u32),
+    /// A UNIX time, at or above `LOCK_TIME_THRESHOLD`
+    Seconds(u32),
+}
+
+impl LockTime {
+    /// The lock time of transactions which are not locked
+    pub const ZERO: LockTime = LockTime::Blocks(0);
+
+    /// Interpret a transaction's `lock_time`
+    pub fn from_consensus(n: u32) -> LockTime {
+        if n < LOCK_TIME_THRESHOLD {
+            LockTime::Blocks(n)
+        } else {
+            LockTime::Seconds(n)
+        }
+    }
+
+    /// A lock time at block `height`, or `None` if the height would be
+    /// read as a time
+    pub fn from_height(height: u32) -> Option<LockTime> {
+        if height < LOCK_TIME_THRESHOLD {
+            Some(LockTime::Blocks(height))
+        } else {
+            None
+        }
+    }
+
+    /// A lock time at UNIX `time`, or `None` if the time would be read as
+    /// a height
+    pub fn from_time(time: u32) -> Option<LockTime> {
+        if time >= LOCK_TIME_THRESHOLD {
+            Some(LockTime::Seconds(time))
+        } else {
+            None
+        }
+    }
+
+    /// The value of a transaction's `lock_time` for this lock time
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(n) | LockTime::Seconds(n) => n,
+        }
+    }
+
+    /// Whether this lock time is a block height
+    pub fn is_block_height(self) -> bool {
+        match self {
+            LockTime::Blocks(..) => true,
+            LockTime::Seconds(..) => false,
+        }
+    }
+
+    /// Whether this lock time is a UNIX time
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether `self` and `other` are both heights or both times
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether a transaction with this lock time can be included in the
+    /// block at `height` whose parent has median time past `time`. The
+    /// lock time is only enforced if an input is not final.
+    pub fn is_satisfied_by(self, height: u32, time: u32) -> bool {
+        match self {
+            LockTime::Blocks(n) => n < height,
+            LockTime::Seconds(n) => n < time,
+        }
+    }
+
+    /// Whether a transaction with lock time `other` satisfies an
+    /// OP_CHECKLOCKTIMEVERIFY requiring `self` (BIP65)
+    pub fn is_implied_by(self, other: LockTime) -> bool {
+        self.is_same_unit(other) && self.to_consensus_u32() <= other.to_consensus_u32()
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(n: u32) -> LockTime {
+        LockTime::from_consensus(n)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+/// A relative lock time (BIP68): an input with it can be included in a
+/// block only once the output it spends is old enough
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum RelativeLockTime {
+    /// A number of blocks
+    Blocks(u16),
+    /// A time, in units of 512 seconds
+    Time(u16),
+}
+
+impl RelativeLockTime {
+    /// The relative lock time of at least `seconds`, or `None` if that
+    /// is more than can be expressed
+    pub fn from_seconds_ceil(seconds: u32) -> Option<RelativeLockTime> {
+        let intervals = (seconds as u64 + (1 << SEQUENCE_LOCKTIME_GRANULARITY) - 1) >> SEQUENCE_LOCKTIME_GRANULARITY;
+        if intervals <= SEQUENCE_LOCKTIME_MASK as u64 {
+            Some(RelativeLockTime::Time(intervals as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Whether this lock time is a number of blocks
+    pub fn is_block_height(self) -> bool {
+        match self {
+            RelativeLockTime::Blocks(..) => true,
+            RelativeLockTime::Time(..) => false,
+        }
+    }
+
+    /// Whether this lock time is a number of 512 second intervals
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether `self` and `other` are both numbers of blocks or both times
+    pub fn is_same_unit(self, other: RelativeLockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether an input with relative lock time `other` satisfies an
+    /// OP_CHECKSEQUENCEVERIFY requiring `self` (BIP112)
+    pub fn is_implied_by(self, other: RelativeLockTime) -> bool {
+        match (self, other) {
+            (RelativeLockTime::Blocks(a), RelativeLockTime::Blocks(b)) |
+            (RelativeLockTime::Time(a), RelativeLockTime::Time(b)) => a <= b,
+            _ => false,
+        }
+    }
+}
+
+/// The sequence number of a transaction input
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// The sequence number of a final input, which disables the
+    /// transaction's lock time if all its inputs have it
+    pub const MAX: Sequence = Sequence(0xffffffff);
+    /// The highest sequence number which enables the lock time without
+    /// signalling replaceability
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xfffffffe);
+    /// The highest sequence number which signals replaceability (BIP125)
+    /// without a relative lock time
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xfffffffd);
+    /// A relative lock time of zero blocks
+    pub const ZERO: Sequence = Sequence(0);
+
+    /// The sequence number with a relative lock time of `height` blocks
+    pub fn from_height(height: u16) -> Sequence {
+        Sequence(height as u32)
+    }
+
+    /// The sequence number with a relative lock time of `intervals` times
+    /// 512 seconds
+    pub fn from_512_second_intervals(intervals: u16) -> Sequence {
+        Sequence(SEQUENCE_LOCKTIME_TYPE_FLAG | intervals as u32)
+    }
+
+    /// Whether the input is final
+    pub fn is_final(self) -> bool {
+        self == Sequence::MAX
+    }
+
+    /// Whether the input signals that its transaction may be replaced
+    /// (BIP125)
+    pub fn is_rbf(self) -> bool {
+        self < Sequence::ENABLE_LOCKTIME_NO_RBF
+    }
+
+    /// Whether the input lets the transaction's lock time be enforced
+    pub fn enables_absolute_lock_time(self) -> bool {
+        !self.is_final()
+    }
+
+    /// Whether the sequence number has a relative lock time, which is only
+    /// enforced for transactions of version 2 or more
+    pub fn is_relative_lock_time(self) -> bool {
+        self.0 & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+    }
+
+    /// The relative lock time of the sequence number, if any
+    pub fn relative_lock_time(self) -> Option<RelativeLockTime> {
+        if !self.is_relative_lock_time() {
+            return None;
+        }
+        let value = (self.0 & SEQUENCE_LOCKTIME_MASK) as u16;
+        if self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLockTime::Time(value))
+        } else {
+            Some(RelativeLockTime::Blocks(value))
+        }
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::MAX
+    }
+}
+
+impl From<u32> for Sequence {
+    fn from(n: u32) -> Sequence {
+        Sequence(n)
+    }
+}
+
+impl From<Sequence> for u32 {
+    fn from(sequence: Sequence) -> u32 {
+        sequence.0
+    }
+}
+
+impl From<RelativeLockTime> for Sequence {
+    fn from(lock_time: RelativeLockTime) -> Sequence {
+        match lock_time {
+            RelativeLockTime::Blocks(n) => Sequence::from_height(n),
+            RelativeLockTime::Time(n) => Sequence::from_512_second_intervals(n),
+        }
+    }
+}
+
+/// The relative lock times of a transaction's inputs, combined as Bitcoin
+/// Core's `CalculateSequenceLocks` does: the transaction can be included in
+/// a block after `min_height` whose parent's median time past is after
+/// `min_time`
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SequenceLocks {
+    /// The last height at which the transaction cannot be included, if
+    /// any input has a relative lock time in blocks
+    pub min_height: Option<u32>,
+    /// The last median time past at which the transaction cannot be
+    /// included, if any input has a relative lock time in time
+    pub min_time: Option<u32>,
+}
+
+impl SequenceLocks {
+    /// Account for a relative lock of `lock_time` on an input spending an
+    /// output confirmed at `height` in a block whose parent's median time
+    /// past is `median_time_past`
+    pub(crate) fn add(&mut self, lock_time: RelativeLockTime, height: u32, median_time_past: u32) {
+        // A lock of zero is satisfied in the block of the output, so the
+        // last locked height or time is one less
+        match lock_time {
+            RelativeLockTime::Blocks(n) => {
+                if let Some(last) = height.saturating_add(n as u32).checked_sub(1) {
+                    self.min_height = Some(cmp::max(self.min_height.unwrap_or(0), last));
+                }
+            }
+            RelativeLockTime::Time(n) => {
+                let seconds = (n as u32) << SEQUENCE_LOCKTIME_GRANULARITY;
+                if let Some(last) = median_time_past.saturating_add(seconds).checked_sub(1) {
+                    self.min_time = Some(cmp::max(self.min_time.unwrap_or(0), last));
+                }
+            }
+        }
+    }
+
+    /// Whether the locks allow the transaction in the block at `height`
+    /// whose parent has median time past `median_time_past`
+    pub fn is_satisfied_by(&self, height: u32, median_time_past: u32) -> bool {
+        let height_ok = match self.min_height {
+            Some(min_height) => min_height < height,
+            None => true,
+        };
+        let time_ok = match self.min_time {
+            Some(min_time) => min_time < median_time_past,
+            None => true,
+        };
+        height_ok && time_ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_time_test() {
+        assert_eq!(LockTime::from_consensus(0), LockTime::ZERO);
+        assert_eq!(LockTime::from_consensus(499_999_999), LockTime::Blocks(499_999_999));
+        assert_eq!(LockTime::from_consensus(500_000_000), LockTime::Seconds(500_000_000));
+        assert_eq!(LockTime::from_height(500_000_000), None);
+        assert_eq!(LockTime::from_time(499_999_999), None);
+        assert_eq!(u32::from(LockTime::from(1_600_000_000)), 1_600_000_000);
+
+        let height = LockTime::Blocks(100);
+        assert!(!height.is_satisfied_by(100, 2_000_000_000));
+        assert!(height.is_satisfied_by(101, 0));
+        let time = LockTime::Seconds(1_600_000_000);
+        assert!(!time.is_satisfied_by(1000, 1_600_000_000));
+        assert!(time.is_satisfied_by(0, 1_600_000_001));
+
+        assert!(height.is_implied_by(LockTime::Blocks(100)));
+        assert!(!height.is_implied_by(LockTime::Blocks(99)));
+        assert!(!height.is_implied_by(time));
+        assert!(time.is_implied_by(LockTime::Seconds(1_600_000_001)));
+    }
+
+    #[test]
+    fn sequence_test() {
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::MAX.is_rbf());
+        assert!(!Sequence::MAX.enables_absolute_lock_time());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_LOCKTIME_NO_RBF.enables_absolute_lock_time());
+        assert!(Sequence::ENABLE_RBF_NO_LOCKTIME.is_rbf());
+        assert_eq!(Sequence::ENABLE_RBF_NO_LOCKTIME.relative_lock_time(), None);
+
+        assert_eq!(Sequence::ZERO.relative_lock_time(), Some(RelativeLockTime::Blocks(0)));
+        assert_eq!(Sequence::from_height(144).relative_lock_time(), Some(RelativeLockTime::Blocks(144)));
+        assert_eq!(Sequence(0x00400010).relative_lock_time(), Some(RelativeLockTime::Time(16)));
+        // Bits outside the flags and the value are ignored
+        assert_eq!(Sequence(0x7fbf0010).relative_lock_time(), Some(RelativeLockTime::Blocks(16)));
+        assert_eq!(Sequence::from(RelativeLockTime::Time(16)), Sequence(0x00400010));
+
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0), Some(RelativeLockTime::Time(0)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(513), Some(RelativeLockTime::Time(2)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0xffff * 512), Some(RelativeLockTime::Time(0xffff)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0xffff * 512 + 1), None);
+        assert!(RelativeLockTime::Blocks(10).is_implied_by(RelativeLockTime::Blocks(10)));
+        assert!(!RelativeLockTime::Blocks(10).is_implied_by(RelativeLockTime::Time(10)));
+        assert!(RelativeLockTime::Time(3).is_block_time());
+        assert!(RelativeLockTime::Time(3).is_same_unit(RelativeLockTime::Time(10)));
+        assert!(!RelativeLockTime::Time(3).is_same_unit(RelativeLockTime::Blocks(3)));
+    }
+
+    #[test]
+    fn sequence_locks_test() {
+        let mut locks = SequenceLocks::default();
+        assert!(locks.is_satisfied_by(0, 0));
+        locks.add(RelativeLockTime::Blocks(0), 0, 0);
+        assert_eq!(locks, SequenceLocks::default());
+
+        locks.add(RelativeLockTime::Blocks(10), 100, 0);
+        locks.add(RelativeLockTime::Blocks(5), 104, 0);
+        assert_eq!(locks.min_height, Some(109));
+        assert!(!locks.is_satisfied_by(109, 0));
+        assert!(locks.is_satisfied_by(110, 0));
+
+        locks.add(RelativeLockTime::Time(2), 100, 1_600_000_000);
+        assert_eq!(locks.min_time, Some(1_600_001_023));
+        assert!(!locks.is_satisfied_by(110, 1_600_001_023));
+        assert!(locks.is_satisfied_by(110, 1_600_001_024));
+
+        // Heights past the end of the range saturate rather than wrap around
+        let mut locks = SequenceLocks::default();
+        locks.add(RelativeLockTime::Blocks(0xffff), 0xffff_fff0, 0);
+        assert_eq!(locks.min_height, Some(0xffff_fffe));
+        assert!(!locks.is_satisfied_by(0xffff_fffe, 0));
+    }
+}
@@ -21,6 +21,9 @@
 pub mod constants;
 pub mod opcodes;
 pub mod script;
+pub mod interpreter;
+pub mod locktime;
 pub mod transaction;
 pub mod block;
+pub mod chain;
 
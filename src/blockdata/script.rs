@@ -32,9 +32,11 @@ use std::{error, fmt, io};
 use blockdata::opcodes;
 use consensus::{encode, Decodable, Encodable};
 use hashes::{hash160, sha256, Hash};
+use hashes::hex::FromHex;
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
 #[cfg(feature="bitcoinconsensus")] use std::convert;
-#[cfg(feature="bitcoinconsensus")] use OutPoint;
+use blockdata::interpreter;
+use blockdata::transaction::OutPoint;
 
 use util::key::PublicKey;
 
@@ -94,7 +96,8 @@ pub enum Error {
     #[cfg(feature="bitcoinconsensus")]
     /// Error validating the script with bitcoinconsensus library
     BitcoinConsensus(bitcoinconsensus::Error),
-    #[cfg(feature="bitcoinconsensus")]
+    /// Error validating the script with the native interpreter
+    Interpreter(interpreter::VerifyError),
     /// Can not find the spent output
     UnknownSpentOutput(OutPoint),
     #[cfg(feature="bitcoinconsensus")]
@@ -104,12 +107,20 @@ pub enum Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(error::Error::description(self))
+        match *self {
+            Error::Interpreter(ref e) => fmt::Display::fmt(e, f),
+            _ => f.write_str(error::Error::description(self)),
+        }
     }
 }
 
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> { None }
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Interpreter(ref e) => Some(e),
+            _ => None,
+        }
+    }
 
     fn description(&self) -> &'static str {
         match *self {
@@ -118,7 +129,7 @@ impl error::Error for Error {
             Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
             #[cfg(feature="bitcoinconsensus")]
             Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
-            #[cfg(feature="bitcoinconsensus")]
+            Error::Interpreter(ref _e) => "script verification failed",
             Error::UnknownSpentOutput(ref _point) => "unknown spent output Transaction::verify()",
             #[cfg(feature="bitcoinconsensus")]
             Error::SerializationError => "can not serialize the spending transaction in Transaction::verify()",
@@ -135,8 +146,112 @@ impl convert::From<bitcoinconsensus::Error> for Error {
         }
     }
 }
+
+#[doc(hidden)]
+impl From<interpreter::VerifyError> for Error {
+    fn from(err: interpreter::VerifyError) -> Error {
+        Error::Interpreter(err)
+    }
+}
+
+/// Ways that parsing a script from its assembly representation might fail.
+/// Each variant carries the byte offset in the input of the token at fault.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AsmError {
+    /// A token which is neither an opcode name, a number, `0x`-prefixed
+    /// hex, nor a quoted string
+    UnknownToken(usize, String),
+    /// A string starting with `'` but not ending with one
+    UnterminatedString(usize),
+    /// Invalid hex in raw bytes or push data
+    InvalidHex(usize),
+    /// A number outside of the range of 64-bit integers
+    NumberOutOfRange(usize),
+    /// An `OP_PUSHBYTES_n` opcode not followed by its data
+    MissingPushData(usize),
+    /// Push data of a different length than its `OP_PUSHBYTES_n` opcode
+    /// says, or too long for its `OP_PUSHDATAn` opcode: (position, expected,
+    /// actual)
+    PushLengthMismatch(usize, usize, usize),
+}
+
+impl AsmError {
+    /// The byte offset of the offending token in the parsed string
+    pub fn position(&self) -> usize {
+        match *self {
+            AsmError::UnknownToken(pos, _) |
+            AsmError::UnterminatedString(pos) |
+            AsmError::InvalidHex(pos) |
+            AsmError::NumberOutOfRange(pos) |
+            AsmError::MissingPushData(pos) |
+            AsmError::PushLengthMismatch(pos, _, _) => pos,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match *self {
+            AsmError::UnknownToken(..) => "unknown token",
+            AsmError::UnterminatedString(..) => "unterminated string",
+            AsmError::InvalidHex(..) => "invalid hex",
+            AsmError::NumberOutOfRange(..) => "number out of range",
+            AsmError::MissingPushData(..) => "missing push data",
+            AsmError::PushLengthMismatch(..) => "push data length mismatch",
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmError::UnknownToken(pos, ref token) => write!(f, "unknown token {} at position {}", token, pos),
+            AsmError::PushLengthMismatch(pos, expected, actual) =>
+                write!(f, "push of {} bytes where {} expected at position {}", actual, expected, pos),
+            _ => write!(f, "{} at position {}", self.reason(), self.position()),
+        }
+    }
+}
+
+impl error::Error for AsmError {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+/// Look up an opcode by its assembly name, with or without the `OP_` prefix.
+/// Accepts the names `fmt_asm` writes as well as Bitcoin Core's.
+fn opcode_from_name(name: &str) -> Option<opcodes::All> {
+    let prefixed = name.starts_with("OP_");
+    let name = if prefixed { &name[3..] } else { name };
+
+    match name {
+        "0" | "FALSE" => return Some(opcodes::all::OP_PUSHBYTES_0),
+        "TRUE" => return Some(opcodes::all::OP_PUSHNUM_1),
+        "1NEGATE" => return Some(opcodes::all::OP_PUSHNUM_NEG1),
+        "NOP2" | "CHECKLOCKTIMEVERIFY" => return Some(opcodes::all::OP_CLTV),
+        "NOP3" | "CHECKSEQUENCEVERIFY" => return Some(opcodes::all::OP_CSV),
+        _ => {}
+    }
+    // `OP_1` to `OP_16`; without the prefix these are numbers
+    if prefixed {
+        if let Ok(n) = name.parse::<u8>() {
+            if n != 0 && n <= 16 {
+                return Some(opcodes::All::from(opcodes::all::OP_PUSHNUM_1.into_u8() + n - 1));
+            }
+        }
+    }
+
+    let mut debug = String::new();
+    (0..256).map(|code| opcodes::All::from(code as u8)).find(|op| {
+        debug.clear();
+        fmt::write(&mut debug, format_args!("{:?}", op)).unwrap();
+        debug[3..] == *name
+    })
+}
+
 /// Helper to encode an integer in script format
-fn build_scriptint(n: i64) -> Vec<u8> {
+pub(crate) fn build_scriptint(n: i64) -> Vec<u8> {
     if n == 0 { return vec![] }
 
     let neg = n < 0;
@@ -324,6 +439,87 @@ impl Script {
                                opcodes::All::from(self.0[0]).classify() == opcodes::Class::IllegalOp)
     }
 
+    /// Whether the script only consists of push operations, in the sense of
+    /// Bitcoin Core's `IsPushOnly` (which also accepts OP_RESERVED)
+    pub fn is_push_only(&self) -> bool {
+        self.iter(false).all(|ins| match ins {
+            Instruction::PushBytes(_) => true,
+            Instruction::Op(op) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+            Instruction::Error(_) => false,
+        })
+    }
+
+    /// Count the signature operations of the script the way blocks have
+    /// always been limited: each CHECKMULTISIG counts as 20, whatever its
+    /// number of keys.
+    pub fn count_sigops_legacy(&self) -> usize {
+        self.count_sigops_internal(false)
+    }
+
+    /// Count the signature operations of the script, counting a
+    /// CHECKMULTISIG preceded by OP_1 to OP_16 as that many and any other
+    /// as 20. This is how redeem scripts and witness scripts are counted.
+    pub fn count_sigops(&self) -> usize {
+        self.count_sigops_internal(true)
+    }
+
+    fn count_sigops_internal(&self, accurate: bool) -> usize {
+        let mut n = 0;
+        let mut last = None;
+        for instruction in self.iter(false) {
+            match instruction {
+                Instruction::Op(opcodes::all::OP_CHECKSIG) |
+                Instruction::Op(opcodes::all::OP_CHECKSIGVERIFY) => n += 1,
+                Instruction::Op(opcodes::all::OP_CHECKMULTISIG) |
+                Instruction::Op(opcodes::all::OP_CHECKMULTISIGVERIFY) => {
+                    n += match last.map(|op: opcodes::All| op.classify()) {
+                        Some(opcodes::Class::PushNum(keys)) if accurate && keys >= 1 => keys as usize,
+                        _ => interpreter::MAX_PUBKEYS_PER_MULTISIG as usize,
+                    };
+                }
+                Instruction::Error(_) => break,
+                _ => {}
+            }
+            last = match instruction {
+                Instruction::Op(op) => Some(op),
+                _ => None,
+            };
+        }
+        n
+    }
+
+    /// Count the signature operations of the redeem script that
+    /// `script_sig` provides when spending this script, if it is P2SH.
+    /// Otherwise count the signature operations of this script as
+    /// `count_sigops` does.
+    pub fn count_p2sh_sigops(&self, script_sig: &Script) -> usize {
+        if !self.is_p2sh() {
+            return self.count_sigops();
+        }
+        match last_push(script_sig) {
+            Some(redeem_script) => Script::from(redeem_script.to_vec()).count_sigops(),
+            None => 0,
+        }
+    }
+
+    /// Count the signature operations of the witness script when this
+    /// script is spent with `script_sig` and `witness`: 1 for P2WPKH, the
+    /// operations of the witness script for P2WSH, including when nested
+    /// in P2SH, and none otherwise.
+    pub fn count_witness_sigops(&self, script_sig: &Script, witness: &[Vec<u8>]) -> usize {
+        if self.is_witness_program() {
+            return witness_program_sigops(self.as_bytes(), witness);
+        }
+        if self.is_p2sh() && script_sig.is_push_only() {
+            if let Some(redeem_script) = last_push(script_sig) {
+                if Script::from(redeem_script.to_vec()).is_witness_program() {
+                    return witness_program_sigops(redeem_script, witness);
+                }
+            }
+        }
+        0
+    }
+
     /// Iterate over the script in the form of `Instruction`s, which are an enum covering
     /// opcodes, datapushes and errors. At most one error will be returned and then the
     /// iterator will end. To instead iterate over the script as sequence of bytes, treat
@@ -331,6 +527,7 @@ impl Script {
     pub fn iter(&self, enforce_minimal: bool) -> Instructions {
         Instructions {
             data: &self.0[..],
+            len: self.0.len(),
             enforce_minimal: enforce_minimal,
         }
     }
@@ -420,6 +617,87 @@ impl Script {
         self.fmt_asm(&mut buf).unwrap();
         buf
     }
+
+    /// Parse a script from its assembly representation, space separated.
+    ///
+    /// This accepts the output of `fmt_asm`, so that `Script::from_asm(&s.asm())`
+    /// gives back `s` for any script which decodes without errors (that is,
+    /// whose assembly contains no `<...>` markers). Data pushes are encoded
+    /// exactly as their opcodes say, even if not minimal.
+    ///
+    /// Bitcoin Core's test vector syntax is also accepted: opcode names without
+    /// the `OP_` prefix, decimal numbers (pushed as by `Builder::push_int`),
+    /// `0x`-prefixed hex which is inserted into the script as raw bytes, and
+    /// single-quoted strings without spaces, which are pushed as data.
+    ///
+    /// The data of an `OP_PUSHDATA1`, `OP_PUSHDATA2` or `OP_PUSHDATA4` is the
+    /// hex token following it, if any; without one the push is empty.
+    pub fn from_asm(asm: &str) -> Result<Script, AsmError> {
+        let mut script = vec![];
+        let mut tokens = asm.split_whitespace()
+            .map(|token| (token.as_ptr() as usize - asm.as_ptr() as usize, token))
+            .peekable();
+
+        while let Some((pos, token)) = tokens.next() {
+            let is_number = {
+                let digits = token.as_bytes();
+                let digits = if digits[0] == b'-' { &digits[1..] } else { digits };
+                !digits.is_empty() && digits.iter().all(|c| b"0123456789".contains(c))
+            };
+
+            if is_number {
+                let n = token.parse::<i64>().map_err(|_| AsmError::NumberOutOfRange(pos))?;
+                script.extend_from_slice(&Builder::new().push_int(n).into_script()[..]);
+            } else if token.get(..2) == Some("0x") {
+                let bytes = Vec::<u8>::from_hex(&token[2..]).map_err(|_| AsmError::InvalidHex(pos))?;
+                script.extend(bytes);
+            } else if token.starts_with('\'') {
+                if token.len() < 2 || !token.ends_with('\'') {
+                    return Err(AsmError::UnterminatedString(pos));
+                }
+                let data = &token.as_bytes()[1..token.len() - 1];
+                script.extend_from_slice(&Builder::new().push_slice(data).into_script()[..]);
+            } else if let Some(opcode) = opcode_from_name(token) {
+                script.push(opcode.into_u8());
+
+                let (len_bytes, max_len) = match opcode {
+                    opcodes::all::OP_PUSHDATA1 => (1, 0xff),
+                    opcodes::all::OP_PUSHDATA2 => (2, 0xffff),
+                    opcodes::all::OP_PUSHDATA4 => (4, 0xffffffff),
+                    _ => match opcode.classify() {
+                        opcodes::Class::PushBytes(n) if n > 0 => (0, n as usize),
+                        _ => continue,
+                    },
+                };
+                let data = match tokens.peek() {
+                    Some(&(data_pos, data)) if opcode_from_name(data).is_none() => {
+                        Some((data_pos, Vec::<u8>::from_hex(data).map_err(|_| AsmError::InvalidHex(data_pos))?))
+                    }
+                    _ => None,
+                };
+                let data = match data {
+                    Some((data_pos, data)) => {
+                        tokens.next();
+                        let len_ok = if len_bytes == 0 { data.len() == max_len } else { data.len() <= max_len };
+                        if !len_ok {
+                            return Err(AsmError::PushLengthMismatch(data_pos, max_len, data.len()));
+                        }
+                        data
+                    }
+                    None if len_bytes == 0 => return Err(AsmError::MissingPushData(pos)),
+                    None => vec![],
+                };
+                let len = data.len() as u32;
+                for i in 0..len_bytes {
+                    script.push((len >> (8 * i)) as u8);
+                }
+                script.extend(data);
+            } else {
+                return Err(AsmError::UnknownToken(pos, token.to_owned()));
+            }
+        }
+        Ok(Script::from(script))
+    }
 }
 
 /// Creates a new script from an existing vector
@@ -429,6 +707,33 @@ impl From<Vec<u8>> for Script {
 
 impl_index_newtype!(Script, u8);
 
+/// The data of the last push of a push-only `script_sig`, which is empty if
+/// that push is of a number
+fn last_push(script_sig: &Script) -> Option<&[u8]> {
+    let mut last = None;
+    for instruction in script_sig.iter(false) {
+        last = match instruction {
+            Instruction::PushBytes(data) => Some(data),
+            Instruction::Op(op) if op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8() => Some(&[][..]),
+            _ => return None,
+        };
+    }
+    last
+}
+
+/// Count the signature operations of spending the witness `program`, a
+/// scriptPubKey or redeem script, with `witness`
+fn witness_program_sigops(program: &[u8], witness: &[Vec<u8>]) -> usize {
+    if program[0] != opcodes::all::OP_PUSHBYTES_0.into_u8() {
+        return 0;
+    }
+    match (program.len() - 2, witness.last()) {
+        (20, _) => 1,
+        (32, Some(witness_script)) => Script::from(witness_script.clone()).count_sigops(),
+        _ => 0,
+    }
+}
+
 /// A "parsed opcode" which allows iterating over a Script in a more sensible way
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Instruction<'a> {
@@ -443,9 +748,23 @@ pub enum Instruction<'a> {
 /// Iterator over a script returning parsed opcodes
 pub struct Instructions<'a> {
     data: &'a [u8],
+    len: usize,
     enforce_minimal: bool,
 }
 
+impl<'a> Instructions<'a> {
+    /// Byte offset into the script of the instruction which will be returned
+    /// by the next call to `next`
+    pub fn position(&self) -> usize {
+        self.len - self.data.len()
+    }
+
+    /// Skip `n` bytes, which must not go past the end of the script
+    pub(crate) fn advance(&mut self, n: usize) {
+        self.data = &self.data[n..];
+    }
+}
+
 impl<'a> Iterator for Instructions<'a> {
     type Item = Instruction<'a>;
 
@@ -955,6 +1274,63 @@ mod test {
                    "OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae");
     }
 
+    #[test]
+    fn script_from_asm() {
+        // Round trips, including the Elements peg-out with PUSHDATA1 from above
+        for hex in &[
+            "",
+            "6363636363686868686800",
+            "2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac",
+            "76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac",
+            "0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae",
+            // Non-minimal and empty PUSHDATAs
+            "4c01074d0200abcd4e0100000011",
+            "4c004d00004e0000000087",
+        ] {
+            let script = hex_script!(hex);
+            assert_eq!(Script::from_asm(&script.asm()), Ok(script));
+        }
+        // Every opcode, with push data where needed
+        for code in 0..256 {
+            let op = opcodes::All::from(code as u8);
+            let mut bytes = vec![code as u8];
+            match op {
+                opcodes::all::OP_PUSHDATA1 => bytes.extend(&[1, 0xaa]),
+                opcodes::all::OP_PUSHDATA2 => bytes.extend(&[1, 0, 0xaa]),
+                opcodes::all::OP_PUSHDATA4 => bytes.extend(&[1, 0, 0, 0, 0xaa]),
+                _ => if let opcodes::Class::PushBytes(n) = op.classify() {
+                    bytes.extend(vec![0xaa; n as usize]);
+                },
+            }
+            let script = Script::from(bytes);
+            assert_eq!(Script::from_asm(&script.asm()), Ok(script));
+        }
+
+        // Bitcoin Core's test vector syntax
+        assert_eq!(
+            Script::from_asm("DUP HASH160 0x14 0x02306a7c23f3e8010de41e9e591348bb83f11daa EQUALVERIFY CHECKSIG"),
+            Ok(hex_script!("76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac"))
+        );
+        assert_eq!(
+            Script::from_asm("0 -1 1 16 17 -2 1000 'Az' '' NOP2 CHECKSEQUENCEVERIFY OP_TRUE OP_16"),
+            Ok(hex_script!("004f51600111018202e80302417a00b1b25160"))
+        );
+        assert_eq!(Script::from_asm("  1\n\t2 "), Ok(hex_script!("5152")));
+
+        // Errors point at the offending token
+        assert_eq!(Script::from_asm("OP_DUP OP_FOO"), Err(AsmError::UnknownToken(7, "OP_FOO".to_owned())));
+        assert_eq!(Script::from_asm("1 'abc"), Err(AsmError::UnterminatedString(2)));
+        assert_eq!(Script::from_asm("1 0xabc"), Err(AsmError::InvalidHex(2)));
+        assert_eq!(Script::from_asm("99999999999999999999"), Err(AsmError::NumberOutOfRange(0)));
+        assert_eq!(Script::from_asm("OP_DUP OP_PUSHBYTES_2"), Err(AsmError::MissingPushData(7)));
+        assert_eq!(Script::from_asm("OP_DUP OP_PUSHBYTES_2 OP_DUP"), Err(AsmError::MissingPushData(7)));
+        assert_eq!(Script::from_asm("OP_PUSHBYTES_2 abcdef"), Err(AsmError::PushLengthMismatch(15, 2, 3)));
+        assert_eq!(Script::from_asm("OP_PUSHBYTES_2 abcxyz"), Err(AsmError::InvalidHex(15)));
+        let long = format!("OP_PUSHDATA1 {}", "00".repeat(256));
+        assert_eq!(Script::from_asm(&long), Err(AsmError::PushLengthMismatch(13, 255, 256)));
+        assert_eq!(Script::from_asm("0 OP_PUSHBYTES_1 1x").unwrap_err().position(), 17);
+    }
+
     #[test]
     fn script_p2sh_p2p2k_template() {
         // random outputs I picked out of the mempool
@@ -1077,6 +1453,77 @@ mod test {
         assert!(script_2 > script_1);
     }
 
+    #[test]
+    fn sigop_count_test() {
+        // Test cases from Bitcoin Core's sigopcount_tests
+        let key = PublicKey::from_str("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        assert_eq!(Script::new().count_sigops_legacy(), 0);
+        assert_eq!(Script::new().count_sigops(), 0);
+
+        let s1 = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1).push_slice(&[0; 33]).push_slice(&[0; 33])
+            .push_opcode(opcodes::all::OP_PUSHNUM_2).push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(s1.count_sigops(), 2);
+        assert_eq!(s1.count_sigops_legacy(), 20);
+        let s1 = Builder::from(s1.into_bytes())
+            .push_opcode(opcodes::all::OP_IF).push_opcode(opcodes::all::OP_CHECKSIG).push_opcode(opcodes::all::OP_ENDIF)
+            .into_script();
+        assert_eq!(s1.count_sigops(), 3);
+        assert_eq!(s1.count_sigops_legacy(), 21);
+
+        let p2sh = s1.to_p2sh();
+        let script_sig = Builder::new().push_int(0).push_slice(&s1[..]).into_script();
+        assert_eq!(p2sh.count_p2sh_sigops(&script_sig), 3);
+        assert_eq!(p2sh.count_sigops(), 0);
+        // A scriptSig that is not push only does not count
+        let bad_script_sig = Builder::new().push_slice(&s1[..]).push_opcode(opcodes::all::OP_NOP).into_script();
+        assert_eq!(p2sh.count_p2sh_sigops(&bad_script_sig), 0);
+
+        let s2 = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_2).push_key(&key).push_key(&key).push_key(&key)
+            .push_opcode(opcodes::all::OP_PUSHNUM_3).push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(s2.count_sigops(), 3);
+        assert_eq!(s2.count_sigops_legacy(), 20);
+        let script_sig = Builder::new().push_int(0).push_int(0).push_slice(&s2[..]).into_script();
+        assert_eq!(s2.to_p2sh().count_p2sh_sigops(&script_sig), 3);
+        assert_eq!(s2.count_p2sh_sigops(&script_sig), 3);
+    }
+
+    #[test]
+    fn witness_sigop_count_test() {
+        let key = PublicKey::from_str("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        let witness = vec![vec![0; 72], key.to_bytes()];
+        let p2wpkh = Builder::new().push_int(0).push_slice(&[0; 20]).into_script();
+        assert_eq!(p2wpkh.count_witness_sigops(&Script::new(), &witness), 1);
+        assert_eq!(p2wpkh.count_sigops(), 0);
+
+        // P2WPKH nested in P2SH
+        let script_sig = Builder::new().push_slice(&p2wpkh[..]).into_script();
+        assert_eq!(p2wpkh.to_p2sh().count_witness_sigops(&script_sig, &witness), 1);
+        let bad_script_sig = Builder::new().push_slice(&p2wpkh[..]).push_opcode(opcodes::all::OP_NOP).into_script();
+        assert_eq!(p2wpkh.to_p2sh().count_witness_sigops(&bad_script_sig, &witness), 0);
+        assert_eq!(p2wpkh.to_p2sh().count_witness_sigops(&Script::new(), &witness), 0);
+
+        let witness_script = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1).push_key(&key).push_key(&key)
+            .push_opcode(opcodes::all::OP_PUSHNUM_2).push_opcode(opcodes::all::OP_CHECKMULTISIGVERIFY)
+            .push_key(&key).push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let p2wsh = witness_script.to_v0_p2wsh();
+        let witness = vec![vec![], vec![0; 72], vec![0; 72], witness_script.to_bytes()];
+        assert_eq!(p2wsh.count_witness_sigops(&Script::new(), &witness), 3);
+        assert_eq!(p2wsh.count_witness_sigops(&Script::new(), &[]), 0);
+        let script_sig = Builder::new().push_slice(&p2wsh[..]).into_script();
+        assert_eq!(p2wsh.to_p2sh().count_witness_sigops(&script_sig, &witness), 3);
+
+        // Unknown witness versions and other scripts have none
+        let v1 = Builder::new().push_int(1).push_slice(&[0; 32]).into_script();
+        assert_eq!(v1.count_witness_sigops(&Script::new(), &witness), 0);
+        assert_eq!(witness_script.count_witness_sigops(&Script::new(), &witness), 0);
+    }
+
 	#[test]
 	#[cfg(feature="bitcoinconsensus")]
 	fn test_bitcoinconsensus () {
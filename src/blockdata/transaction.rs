@@ -23,24 +23,29 @@
 //! This module provides the structures and functions needed to support transactions.
 //!
 
+use std::collections::HashSet;
 use std::default::Default;
-use std::{fmt, io};
+use std::{error, fmt, io};
 
 use hashes::{self, sha256d, Hash};
 use hashes::hex::FromHex;
 
 use util::endian;
 use util::hash::BitcoinHash;
-#[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
+use hash_types::{Txid, Wtxid};
+use blockdata::constants::{MAX_BLOCK_WEIGHT, MAX_MONEY, WITNESS_SCALE_FACTOR};
+use blockdata::script::{self, Script};
+use blockdata::locktime::{LockTime, RelativeLockTime, Sequence, SequenceLocks};
+use blockdata::interpreter::{self, TransactionSignatureChecker, VerifyFlags};
 use consensus::{encode, serialize, Decodable, Encodable};
+use util::amount::Amount;
 use VarInt;
 
 /// A reference to a transaction output
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct OutPoint {
     /// The referenced transaction's txid
-    pub txid: sha256d::Hash,
+    pub txid: Txid,
     /// The index of the referenced output in its transaction's vout
     pub vout: u32,
 }
@@ -49,7 +54,7 @@ serde_struct_human_string_impl!(OutPoint, "an OutPoint", txid, vout);
 impl OutPoint {
     /// Create a new [OutPoint].
     #[inline]
-    pub fn new(txid: sha256d::Hash, vout: u32) -> OutPoint {
+    pub fn new(txid: Txid, vout: u32) -> OutPoint {
         OutPoint {
             txid: txid,
             vout: vout,
@@ -147,6 +152,110 @@ impl ::std::error::Error for ParseOutPointError {
     }
 }
 
+/// Reason a transaction fails `Transaction::check`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckTxError {
+    /// The transaction has no inputs
+    NoInputs,
+    /// The transaction has no outputs
+    NoOutputs,
+    /// The transaction without witness data would not fit in a block
+    Oversized,
+    /// An output has a value above `MAX_MONEY`
+    OutputValueTooLarge(usize),
+    /// The outputs together have a value above `MAX_MONEY`
+    TotalOutputValueTooLarge,
+    /// An outpoint is spent twice
+    DuplicateInput(OutPoint),
+    /// The coinbase scriptSig is not between 2 and 100 bytes long
+    BadCoinbaseLength(usize),
+    /// An input other than a coinbase input spends the null outpoint
+    NullPrevout(usize),
+}
+
+impl fmt::Display for CheckTxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheckTxError::OutputValueTooLarge(i) => write!(f, "value of output {} too large", i),
+            CheckTxError::DuplicateInput(ref o) => write!(f, "outpoint {} spent twice", o),
+            CheckTxError::BadCoinbaseLength(len) => write!(f, "coinbase scriptSig has length {}", len),
+            CheckTxError::NullPrevout(i) => write!(f, "input {} spends the null outpoint", i),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for CheckTxError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl CheckTxError {
+    fn reason(&self) -> &'static str {
+        match *self {
+            CheckTxError::NoInputs => "transaction has no inputs",
+            CheckTxError::NoOutputs => "transaction has no outputs",
+            CheckTxError::Oversized => "transaction too large",
+            CheckTxError::OutputValueTooLarge(..) => "output value too large",
+            CheckTxError::TotalOutputValueTooLarge => "total output value too large",
+            CheckTxError::DuplicateInput(..) => "outpoint spent twice",
+            CheckTxError::BadCoinbaseLength(..) => "bad coinbase scriptSig length",
+            CheckTxError::NullPrevout(..) => "input spends the null outpoint",
+        }
+    }
+}
+
+/// Reason `Transaction::fee` fails
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FeeError {
+    /// The output spent by an input is unknown
+    UnknownSpentOutput(OutPoint),
+    /// The values of the spent outputs or of the outputs overflow
+    ValueOverflow,
+    /// The outputs have a higher value than the outputs spent
+    NegativeFee,
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FeeError::UnknownSpentOutput(ref o) => write!(f, "unknown spent output {}", o),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for FeeError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl FeeError {
+    fn reason(&self) -> &'static str {
+        match *self {
+            FeeError::UnknownSpentOutput(..) => "unknown spent output",
+            FeeError::ValueOverflow => "value overflow",
+            FeeError::NegativeFee => "outputs exceed the outputs spent",
+        }
+    }
+}
+
+/// The virtual size of a transaction of `weight`, rounded up
+pub(crate) fn weight_to_vsize(weight: usize) -> usize {
+    let vsize = weight / WITNESS_SCALE_FACTOR;
+    if vsize * WITNESS_SCALE_FACTOR == weight { vsize } else { vsize + 1 }
+}
+
 /// Parses a string-encoded transaction index (vout).
 /// It does not permit leading zeroes or non-digit characters.
 fn parse_vout(s: &str) -> Result<u32, ParseOutPointError> {
@@ -175,7 +284,7 @@ impl ::std::str::FromStr for OutPoint {
             return Err(ParseOutPointError::Format);
         }
         Ok(OutPoint {
-            txid: sha256d::Hash::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
+            txid: Txid::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
             vout: parse_vout(&s[colon+1..])?,
         })
     }
@@ -214,6 +323,23 @@ impl Default for TxIn {
     }
 }
 
+impl TxIn {
+    /// Create the input of a coinbase transaction for the block at
+    /// `height`: its scriptSig pushes the height as BIP34 requires, then
+    /// `extra_nonce`
+    pub fn coinbase(height: u32, extra_nonce: &[u8]) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: script::Builder::new()
+                .push_int(height as i64)
+                .push_slice(extra_nonce)
+                .into_script(),
+            sequence: 0xffffffff,
+            witness: Vec::new(),
+        }
+    }
+}
+
 /// A transaction output, which defines new coins to be created from old ones.
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub struct TxOut {
@@ -286,20 +412,29 @@ impl Transaction {
             input: self.input.iter().map(|txin| TxIn { script_sig: Script::new(), witness: vec![], .. *txin }).collect(),
             output: self.output.clone(),
         };
-        cloned_tx.bitcoin_hash()
+        cloned_tx.txid().into()
     }
 
     /// Computes the txid. For non-segwit transactions this will be identical
-    /// to the output of `BitcoinHash::bitcoin_hash()`, but for segwit transactions,
-    /// this will give the correct txid (not including witnesses) while `bitcoin_hash`
+    /// to the output of `wtxid()`, but for segwit transactions,
+    /// this will give the correct txid (not including witnesses) while `wtxid`
     /// will also hash witnesses.
-    pub fn txid(&self) -> sha256d::Hash {
-        let mut enc = sha256d::Hash::engine();
+    pub fn txid(&self) -> Txid {
+        let mut enc = Txid::engine();
         self.version.consensus_encode(&mut enc).unwrap();
         self.input.consensus_encode(&mut enc).unwrap();
         self.output.consensus_encode(&mut enc).unwrap();
         self.lock_time.consensus_encode(&mut enc).unwrap();
-        sha256d::Hash::from_engine(enc)
+        Txid::from_engine(enc)
+    }
+
+    /// Computes the segwit version of the transaction id, as defined in BIP141.
+    /// This hashes the full serialization including witnesses, so it is
+    /// identical to `txid()` for transactions without witness data.
+    pub fn wtxid(&self) -> Wtxid {
+        let mut enc = Wtxid::engine();
+        self.consensus_encode(&mut enc).unwrap();
+        Wtxid::from_engine(enc)
     }
 
     /// Computes a signature hash for a given input index with a given sighash flag.
@@ -415,6 +550,100 @@ impl Transaction {
         }
     }
 
+    /// Gets the virtual size of this transaction, its weight divided by 4
+    /// and rounded up (BIP141). Fee rates are in satoshis per virtual byte.
+    pub fn vsize(&self) -> usize {
+        weight_to_vsize(self.get_weight())
+    }
+
+    /// Gets the size of this transaction in bytes, as serialized with its
+    /// witness data.
+    pub fn get_size(&self) -> usize {
+        self.consensus_encode(&mut io::sink()).expect("writing to a sink never fails")
+    }
+
+    /// Gets the size of this transaction in bytes, as serialized without
+    /// its witness data.
+    pub fn get_stripped_size(&self) -> usize {
+        // The weight counts the stripped size three more times than the full size
+        (self.get_weight() - self.get_size()) / (WITNESS_SCALE_FACTOR - 1)
+    }
+
+    /// Check the transaction for validity without any context, as Bitcoin
+    /// Core's `CheckTransaction` does: it must have inputs and outputs,
+    /// fit in a block, not create more than `MAX_MONEY` and not spend an
+    /// outpoint twice. A coinbase must have a scriptSig of 2 to 100 bytes,
+    /// other transactions must not spend the null outpoint.
+    pub fn check(&self) -> Result<(), CheckTxError> {
+        if self.input.is_empty() {
+            return Err(CheckTxError::NoInputs);
+        }
+        if self.output.is_empty() {
+            return Err(CheckTxError::NoOutputs);
+        }
+        if self.get_stripped_size() * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT {
+            return Err(CheckTxError::Oversized);
+        }
+
+        let mut total: u64 = 0;
+        for (i, output) in self.output.iter().enumerate() {
+            if output.value > MAX_MONEY {
+                return Err(CheckTxError::OutputValueTooLarge(i));
+            }
+            total += output.value;
+            if total > MAX_MONEY {
+                return Err(CheckTxError::TotalOutputValueTooLarge);
+            }
+        }
+
+        let mut spent = HashSet::with_capacity(self.input.len());
+        for input in &self.input {
+            if !spent.insert(input.previous_output) {
+                return Err(CheckTxError::DuplicateInput(input.previous_output));
+            }
+        }
+
+        if self.is_coin_base() {
+            match self.input[0].script_sig.len() {
+                2...100 => {}
+                len => return Err(CheckTxError::BadCoinbaseLength(len)),
+            }
+        } else if let Some(i) = self.input.iter().position(|i| i.previous_output.is_null()) {
+            return Err(CheckTxError::NullPrevout(i));
+        }
+        Ok(())
+    }
+
+    /// Count the signature operations of the scriptSigs and scriptPubkeys
+    /// of this transaction, as `Script::count_sigops_legacy` does
+    pub fn get_legacy_sigop_count(&self) -> usize {
+        self.input.iter().map(|i| i.script_sig.count_sigops_legacy()).sum::<usize>() +
+            self.output.iter().map(|o| o.script_pubkey.count_sigops_legacy()).sum::<usize>()
+    }
+
+    /// Gets the signature operation cost of this transaction, as defined by
+    /// BIP141: legacy and P2SH signature operations count 4 times as much as
+    /// witness ones. The lambda spent must return the output spent by each
+    /// input but that of a coinbase.
+    pub fn get_sigop_cost<S>(&self, mut spent: S) -> Result<usize, script::Error>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let mut cost = self.get_legacy_sigop_count() * WITNESS_SCALE_FACTOR;
+        if self.is_coin_base() {
+            return Ok(cost);
+        }
+        for input in &self.input {
+            let output = match spent(&input.previous_output) {
+                Some(output) => output,
+                None => return Err(script::Error::UnknownSpentOutput(input.previous_output)),
+            };
+            if output.script_pubkey.is_p2sh() {
+                cost += output.script_pubkey.count_p2sh_sigops(&input.script_sig) * WITNESS_SCALE_FACTOR;
+            }
+            cost += output.script_pubkey.count_witness_sigops(&input.script_sig, &input.witness);
+        }
+        Ok(cost)
+    }
+
     #[cfg(feature="bitcoinconsensus")]
     /// Verify that this transaction is able to spend its inputs
     /// The lambda spent should not return the same TxOut twice!
@@ -431,17 +660,94 @@ impl Transaction {
         Ok(())
     }
 
+    /// Verify that this transaction is able to spend its inputs, using the
+    /// native script interpreter with the given verification flags.
+    /// The lambda spent should not return the same TxOut twice!
+    pub fn verify_with_flags<S>(&self, mut spent: S, flags: VerifyFlags) -> Result<(), script::Error>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        for (idx, input) in self.input.iter().enumerate() {
+            if let Some(output) = spent(&input.previous_output) {
+                let checker = TransactionSignatureChecker::new(self, idx, output.value);
+                interpreter::verify_script(&input.script_sig, &output.script_pubkey, &input.witness, flags, &checker)?;
+            } else {
+                return Err(script::Error::UnknownSpentOutput(input.previous_output));
+            }
+        }
+        Ok(())
+    }
+
     /// Is this a coin base transaction?
     pub fn is_coin_base(&self) -> bool {
         self.input.len() == 1 && self.input[0].previous_output.is_null()
     }
+
+    /// Calculate the fee of this transaction, the value of the outputs its
+    /// inputs spend minus the value of its outputs. The lambda spent should
+    /// return the output each input spends.
+    pub fn fee<S>(&self, mut spent: S) -> Result<Amount, FeeError>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let mut input_value = Amount::ZERO;
+        for input in &self.input {
+            let output = spent(&input.previous_output)
+                .ok_or(FeeError::UnknownSpentOutput(input.previous_output))?;
+            input_value = input_value.checked_add(Amount::from_sat(output.value))
+                .ok_or(FeeError::ValueOverflow)?;
+        }
+        let mut output_value = Amount::ZERO;
+        for output in &self.output {
+            output_value = output_value.checked_add(Amount::from_sat(output.value))
+                .ok_or(FeeError::ValueOverflow)?;
+        }
+        input_value.checked_sub(output_value).ok_or(FeeError::NegativeFee)
+    }
+
+    /// Whether the transaction's lock time allows it in the block at
+    /// `height` whose parent has median time past `time`, as Bitcoin
+    /// Core's `IsFinalTx`. The lock time only applies if an input is not
+    /// final.
+    pub fn is_final(&self, height: u32, time: u32) -> bool {
+        if self.lock_time == 0 || LockTime::from_consensus(self.lock_time).is_satisfied_by(height, time) {
+            return true;
+        }
+        self.input.iter().all(|input| Sequence(input.sequence).is_final())
+    }
+
+    /// Compute the relative lock times of the inputs (BIP68). The outputs
+    /// spent by the inputs were confirmed at `prev_heights`, in order;
+    /// unconfirmed ones count as confirmed in the next block.
+    /// `median_time_past` must return the median time past of the block at
+    /// the given height. Relative lock times only apply from version 2.
+    ///
+    /// # Panics
+    ///
+    /// If there are not as many `prev_heights` as inputs.
+    pub fn sequence_locks<E, F>(&self, prev_heights: &[u32], mut median_time_past: F) -> Result<SequenceLocks, E>
+        where F: FnMut(u32) -> Result<u32, E>
+    {
+        assert_eq!(prev_heights.len(), self.input.len(), "one previous height per input");
+        let mut locks = SequenceLocks::default();
+        if self.version < 2 {
+            return Ok(locks);
+        }
+        for (input, &height) in self.input.iter().zip(prev_heights) {
+            match Sequence(input.sequence).relative_lock_time() {
+                Some(lock_time @ RelativeLockTime::Blocks(..)) => locks.add(lock_time, height, 0),
+                Some(lock_time @ RelativeLockTime::Time(..)) => {
+                    // Time is counted from the median time past of the
+                    // parent of the block with the output
+                    let time = median_time_past(height.saturating_sub(1))?;
+                    locks.add(lock_time, height, time);
+                }
+                None => {}
+            }
+        }
+        Ok(locks)
+    }
 }
 
-impl BitcoinHash for Transaction {
-    fn bitcoin_hash(&self) -> sha256d::Hash {
-        let mut enc = sha256d::Hash::engine();
-        self.consensus_encode(&mut enc).unwrap();
-        sha256d::Hash::from_engine(enc)
+impl BitcoinHash<Wtxid> for Transaction {
+    fn bitcoin_hash(&self) -> Wtxid {
+        self.wtxid()
     }
 }
 
@@ -585,7 +891,7 @@ pub enum SigHashType {
 
 impl SigHashType {
      /// Break the sighash flag into the "real" sighash flag and the ANYONECANPAY boolean
-     fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
+     pub fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
          match *self {
              SigHashType::All		=> (SigHashType::All, false),
              SigHashType::None		=> (SigHashType::None, false),
@@ -619,13 +925,14 @@ impl SigHashType {
 
 #[cfg(test)]
 mod tests {
-    use super::{OutPoint, ParseOutPointError, Transaction, TxIn};
+    use super::{OutPoint, ParseOutPointError, Transaction, TxIn, TxOut};
 
     use std::str::FromStr;
     use blockdata::script::Script;
     use consensus::encode::serialize;
     use consensus::encode::deserialize;
     use util::hash::BitcoinHash;
+    use hash_types::Txid;
 
     use hashes::{sha256d, Hash};
     use hashes::hex::FromHex;
@@ -645,20 +952,20 @@ mod tests {
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:+42"),
                    Err(ParseOutPointError::VoutNotCanonical));
         assert_eq!(OutPoint::from_str("i don't care:1"),
-                   Err(ParseOutPointError::Txid(sha256d::Hash::from_hex("i don't care").unwrap_err())));
+                   Err(ParseOutPointError::Txid(Txid::from_hex("i don't care").unwrap_err())));
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c945X:1"),
-                   Err(ParseOutPointError::Txid(sha256d::Hash::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c945X").unwrap_err())));
+                   Err(ParseOutPointError::Txid(Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c945X").unwrap_err())));
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:lol"),
                    Err(ParseOutPointError::Vout(u32::from_str("lol").unwrap_err())));
  
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:42"),
                    Ok(OutPoint{
-                       txid: sha256d::Hash::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 42,
                    }));
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0"),
                    Ok(OutPoint{
-                       txid: sha256d::Hash::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 0,
                    }));
     }
@@ -780,7 +1087,10 @@ mod tests {
 
         assert_eq!(format!("{:x}", tx.bitcoin_hash()), "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4");
         assert_eq!(format!("{:x}", tx.txid()), "9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec");
+        assert_eq!(format!("{:x}", tx.wtxid()), "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4");
         assert_eq!(tx.get_weight(), 2718);
+        assert_eq!(tx.get_size(), hex_tx.len());
+        assert_eq!(tx.get_stripped_size(), 516);
 
         // non-segwit tx from my mempool
         let hex_tx = Vec::<u8>::from_hex(
@@ -795,6 +1105,7 @@ mod tests {
 
         assert_eq!(format!("{:x}", tx.bitcoin_hash()), "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd");
         assert_eq!(format!("{:x}", tx.txid()), "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd");
+        assert_eq!(tx.wtxid().as_hash(), tx.txid().as_hash());
     }
 
     #[test]
@@ -805,6 +1116,123 @@ mod tests {
         serde_round_trip!(tx);
     }
 
+    #[test]
+    fn test_vsize_and_fee() {
+        use super::FeeError;
+        use util::amount::Amount;
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_slice(&[1; 32]).unwrap(), 0),
+                witness: vec![vec![0; 72], vec![2; 33]],
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut { value: 10_000, script_pubkey: Script::from(vec![0; 22]) },
+            ],
+        };
+        // 82 bytes without the witness, 192 with it
+        assert_eq!(tx.get_weight(), 438);
+        assert_eq!(tx.vsize(), 110);
+
+        let prevout = tx.input[0].previous_output;
+        let spent = |value| move |o: &OutPoint| if *o == prevout {
+            Some(TxOut { value, script_pubkey: Script::new() })
+        } else {
+            None
+        };
+        assert_eq!(tx.fee(spent(11_000)), Ok(Amount::from_sat(1000)));
+        assert_eq!(tx.fee(spent(10_000)), Ok(Amount::ZERO));
+        assert_eq!(tx.fee(spent(9_999)), Err(FeeError::NegativeFee));
+        assert_eq!(tx.fee(|_| None), Err(FeeError::UnknownSpentOutput(prevout)));
+    }
+
+    #[test]
+    fn test_lock_time() {
+        let input = |sequence| TxIn { sequence, ..TxIn::default() };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 100,
+            input: vec![input(0xffffffff), input(0xfffffffe)],
+            output: vec![],
+        };
+        assert!(!tx.is_final(100, 2_000_000_000));
+        assert!(tx.is_final(101, 0));
+        tx.lock_time = 1_600_000_000;
+        assert!(!tx.is_final(1_000_000, 1_600_000_000));
+        assert!(tx.is_final(0, 1_600_000_001));
+        tx.input[1].sequence = 0xffffffff;
+        assert!(tx.is_final(0, 0));
+        tx.lock_time = 0;
+        tx.input[1].sequence = 0;
+        assert!(tx.is_final(0, 0));
+
+        // 10 blocks, 2 * 512 seconds, disabled
+        tx.input = vec![input(10), input(0x00400002), input(0x8000ffff)];
+        let mtp = |height: u32| -> Result<u32, ()> { Ok(1_600_000_000 + height) };
+        let locks = tx.sequence_locks(&[100, 50, 0], mtp).unwrap();
+        assert_eq!(locks.min_height, Some(109));
+        assert_eq!(locks.min_time, Some(1_600_000_049 + 1023));
+        assert!(!locks.is_satisfied_by(109, 1_600_002_000));
+        assert!(!locks.is_satisfied_by(110, 1_600_001_072));
+        assert!(locks.is_satisfied_by(110, 1_600_001_073));
+
+        tx.version = 1;
+        let locks = tx.sequence_locks(&[100, 50, 0], mtp).unwrap();
+        assert!(locks.is_satisfied_by(0, 0));
+    }
+
+    #[test]
+    fn test_sigop_cost() {
+        use blockdata::opcodes::all::*;
+        use blockdata::script::{Builder, Error};
+
+        let multisig = Builder::new()
+            .push_opcode(OP_PUSHNUM_1).push_slice(&[2; 33]).push_slice(&[3; 33]).push_slice(&[2; 33])
+            .push_opcode(OP_PUSHNUM_3).push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let spent = [
+            TxOut { value: 1000, script_pubkey: multisig.to_p2sh() },
+            TxOut { value: 1000, script_pubkey: multisig.to_v0_p2wsh() },
+            TxOut { value: 1000, script_pubkey: Script::new() },
+        ];
+        let outpoint = |vout| OutPoint::new(Txid::hash(&[]), vout);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    previous_output: outpoint(0),
+                    script_sig: Builder::new().push_int(0).push_slice(&[0; 72]).push_slice(&multisig[..]).into_script(),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                },
+                TxIn {
+                    previous_output: outpoint(1),
+                    script_sig: Script::new(),
+                    sequence: 0xffffffff,
+                    witness: vec![vec![], vec![0; 72], multisig.to_bytes()],
+                },
+            ],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Builder::new().push_opcode(OP_CHECKSIG).into_script(),
+            }],
+        };
+        assert_eq!(tx.get_legacy_sigop_count(), 1);
+        // 1 legacy and 3 P2SH sigops count 4 times, 3 witness ones once
+        assert_eq!(tx.get_sigop_cost(|o| spent.get(o.vout as usize).cloned()), Ok(19));
+        // Spending the P2WSH output without a witness has no witness sigops
+        assert_eq!(tx.get_sigop_cost(|o| spent.get(o.vout as usize + 1).cloned()), Ok(4));
+        assert_eq!(tx.get_sigop_cost(|o| if o.vout == 0 { Some(spent[0].clone()) } else { None }),
+                   Err(Error::UnknownSpentOutput(outpoint(1))));
+
+        let coinbase = Transaction { input: vec![TxIn::coinbase(1, &[])], ..tx };
+        assert_eq!(coinbase.get_sigop_cost(|_| None), Ok(4));
+    }
+
     fn run_test_sighash(tx: &str, script: &str, input_index: usize, hash_type: i32, expected_result: &str) {
         let tx: Transaction = deserialize(&Vec::<u8>::from_hex(tx).unwrap()[..]).unwrap();
         let script = Script::from(Vec::<u8>::from_hex(script).unwrap());
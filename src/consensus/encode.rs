@@ -35,13 +35,15 @@ use std::io::{Cursor, Read, Write};
 use hashes::hex::ToHex;
 
 use hashes::{sha256d, Hash as HashTrait};
+use hash_types::{BlockHash, FilterHash, FilterHeader, TxMerkleNode};
 
 use util::endian;
 use util::psbt;
 
 use blockdata::transaction::{TxOut, Transaction, TxIn};
 use network::message_blockdata::Inventory;
-use network::address::Address;
+use network::message_compact_blocks::ShortId;
+use network::address::{Address, AddrV2Message};
 
 /// Encoding error
 #[derive(Debug)]
@@ -528,6 +530,7 @@ macro_rules! impl_array {
 
 impl_array!(2);
 impl_array!(4);
+impl_array!(6);
 impl_array!(8);
 impl_array!(12);
 impl_array!(16);
@@ -590,13 +593,18 @@ macro_rules! impl_vec {
         }
     }
 }
-impl_vec!(sha256d::Hash);
+impl_vec!(BlockHash);
+impl_vec!(FilterHash);
+impl_vec!(FilterHeader);
+impl_vec!(TxMerkleNode);
 impl_vec!(Transaction);
 impl_vec!(TxOut);
 impl_vec!(TxIn);
 impl_vec!(Inventory);
+impl_vec!(ShortId);
 impl_vec!(Vec<u8>);
 impl_vec!((u32, Address));
+impl_vec!(AddrV2Message);
 impl_vec!(u64);
 
 impl Encodable for Vec<u8> {
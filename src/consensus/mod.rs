@@ -20,6 +20,8 @@
 
 pub mod encode;
 pub mod params;
+pub mod pow;
+pub mod versionbits;
 
 pub use self::encode::{Encodable, Decodable, WriteExt, ReadExt};
 pub use self::encode::{serialize, deserialize, deserialize_partial};
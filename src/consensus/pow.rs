@@ -0,0 +1,295 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Proof of work
+//!
+//! This module computes the difficulty a block header must meet given the
+//! headers before it, and implements the checks of a header's timestamp
+//! against the median time of its ancestors and the current time. Together
+//! with `BlockHeader::validate_pow` they validate a headers-only chain.
+//!
+//! The functions walking back over previous headers take a closure that
+//! returns the parent of a given header, so that headers can come from any
+//! storage.
+//!
+
+use std::{cmp, error, fmt};
+
+use blockdata::block::BlockHeader;
+use consensus::params::Params;
+use util::uint::Uint256;
+
+/// Number of blocks whose median time a block's time must exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far in seconds a block's time may be ahead of the current time
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Contextual header validation error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The header's difficulty bits are not the required ones
+    BadDifficultyBits {
+        /// The bits required by the previous headers
+        required: u32,
+        /// The bits of the header
+        actual: u32,
+    },
+    /// The header's time is not after the median time past
+    TimeTooOld {
+        /// The time of the header
+        time: u32,
+        /// The median time of the previous headers
+        median_time_past: u32,
+    },
+    /// The header's time is too far in the future
+    TimeTooNew {
+        /// The time of the header
+        time: u32,
+        /// The latest acceptable time
+        max_time: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadDifficultyBits { required, actual } => write!(f,
+                "header has difficulty bits {:#010x}, required {:#010x}", actual, required),
+            Error::TimeTooOld { time, median_time_past } => write!(f,
+                "header time {} is not after median time past {}", time, median_time_past),
+            Error::TimeTooNew { time, max_time } => write!(f,
+                "header time {} is after {}", time, max_time),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::BadDifficultyBits { .. } => "header has incorrect difficulty bits",
+            Error::TimeTooOld { .. } => "header time is not after median time past",
+            Error::TimeTooNew { .. } => "header time is too far in the future",
+        }
+    }
+}
+
+/// Compute the bits of the first block of a retarget period from the
+/// `last_bits` of the previous period and the times of its first and last
+/// blocks. The adjustment is clamped to a factor of 4 either way and the
+/// target never exceeds the network's proof of work limit.
+pub fn retarget(params: &Params, last_bits: u32, first_time: u32, last_time: u32) -> u32 {
+    if params.no_pow_retargeting {
+        return last_bits;
+    }
+    let timespan = params.pow_target_timespan as i64;
+    let mut actual = last_time as i64 - first_time as i64;
+    if actual < timespan / 4 {
+        actual = timespan / 4;
+    }
+    if actual > timespan * 4 {
+        actual = timespan * 4;
+    }
+
+    let target = BlockHeader::u256_from_compact_target(last_bits);
+    let mut new_target = target.mul_u32(actual as u32) / Uint256::from_u64(timespan as u64).unwrap();
+    if new_target > params.pow_limit {
+        new_target = params.pow_limit;
+    }
+    BlockHeader::compact_target_from_u256(&new_target)
+}
+
+/// Compute the bits required of the block following `last`, which is at
+/// `last_height`, if that block has time `time`. `parent` must return the
+/// parent of the header given to it; it is called for ancestors of `last`
+/// back to the start of its retarget period at most.
+pub fn next_bits<E, F>(params: &Params, last: &BlockHeader, last_height: u32, time: u32, mut parent: F)
+    -> Result<u32, E>
+    where F: FnMut(&BlockHeader) -> Result<BlockHeader, E>
+{
+    let interval = params.difficulty_adjustment_interval() as u32;
+    let into_period = (last_height + 1) % interval;
+    if into_period != 0 {
+        if !params.allow_min_difficulty_blocks {
+            return Ok(last.bits);
+        }
+        // A block more than twice the target spacing after its parent may
+        // have the minimum difficulty
+        let pow_limit_bits = BlockHeader::compact_target_from_u256(&params.pow_limit);
+        if time as u64 > last.time as u64 + 2 * params.pow_target_spacing {
+            return Ok(pow_limit_bits);
+        }
+        // Otherwise it has the difficulty of the last block that did not
+        // use that rule
+        let mut header = *last;
+        let mut height = last_height;
+        let period_start = height - height % interval;
+        while height > period_start && header.bits == pow_limit_bits {
+            header = parent(&header)?;
+            height -= 1;
+        }
+        return Ok(header.bits);
+    }
+    if params.no_pow_retargeting {
+        return Ok(last.bits);
+    }
+
+    let mut first = *last;
+    for _ in 0..interval - 1 {
+        first = parent(&first)?;
+    }
+    Ok(retarget(params, last.bits, first.time, last.time))
+}
+
+/// Compute the median time of `last`, which is at `last_height`, and the
+/// headers before it, `MEDIAN_TIME_SPAN` headers in all. `parent` must
+/// return the parent of the header given to it.
+pub fn median_time_past<E, F>(last: &BlockHeader, last_height: u32, mut parent: F) -> Result<u32, E>
+    where F: FnMut(&BlockHeader) -> Result<BlockHeader, E>
+{
+    let count = cmp::min(MEDIAN_TIME_SPAN as u32, last_height + 1);
+    let mut times = Vec::with_capacity(count as usize);
+    let mut header = *last;
+    times.push(header.time);
+    for _ in 1..count {
+        header = parent(&header)?;
+        times.push(header.time);
+    }
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+/// Check `header` against its context: it must have the `required_bits`
+/// computed by `next_bits`, its time must be after the `median_time_past`
+/// of its parent and at most `MAX_FUTURE_BLOCK_TIME` after `adjusted_time`,
+/// the current network-adjusted time.
+pub fn check_header_context(header: &BlockHeader, required_bits: u32, median_time_past: u32, adjusted_time: u32)
+    -> Result<(), Error>
+{
+    if header.bits != required_bits {
+        return Err(Error::BadDifficultyBits { required: required_bits, actual: header.bits });
+    }
+    if header.time <= median_time_past {
+        return Err(Error::TimeTooOld { time: header.time, median_time_past });
+    }
+    let max_time = adjusted_time.saturating_add(MAX_FUTURE_BLOCK_TIME);
+    if header.time > max_time {
+        return Err(Error::TimeTooNew { time: header.time, max_time });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use consensus::params::Params;
+    use network::constants::Network;
+
+    use super::*;
+
+    /// A header standing in for the block at `height`, which it keeps in
+    /// its nonce so that `parent` can produce the previous one
+    fn header(height: u32, time: u32, bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time,
+            bits,
+            nonce: height,
+        }
+    }
+
+    #[test]
+    fn retarget_test() {
+        // Vectors from Bitcoin Core's pow_tests
+        let params = Params::new(Network::Bitcoin);
+        assert_eq!(retarget(&params, 0x1d00ffff, 1261130161, 1262152739), 0x1d00d86a);
+        // Capped at the proof of work limit
+        assert_eq!(retarget(&params, 0x1d00ffff, 1231006505, 1233061996), 0x1d00ffff);
+        // Clamped to a factor of 4
+        assert_eq!(retarget(&params, 0x1c05a3f4, 1279008237, 1279297671), 0x1c0168fd);
+        assert_eq!(retarget(&params, 0x1c387f6f, 1263163443, 1269211443), 0x1d00e1fd);
+
+        let params = Params::new(Network::Regtest);
+        assert_eq!(retarget(&params, 0x207fffff, 0, 1), 0x207fffff);
+    }
+
+    #[test]
+    fn next_bits_test() {
+        let parent = |h: &BlockHeader| -> Result<BlockHeader, ()> {
+            let height = h.nonce - 1;
+            // Blocks 30240 to 32255 as far as difficulty is concerned
+            let time = if height == 30240 { 1261130161 } else { 1262152739 };
+            Ok(header(height, time, 0x1d00ffff))
+        };
+        let params = Params::new(Network::Bitcoin);
+        let last = header(32255, 1262152739, 0x1d00ffff);
+        assert_eq!(next_bits(&params, &last, 32255, 0, parent), Ok(0x1d00d86a));
+        let last = header(32254, 1262152739, 0x1d00ffff);
+        assert_eq!(next_bits(&params, &last, 32254, 0, parent), Ok(0x1d00ffff));
+
+        // Testnet allows the minimum difficulty for a block more than 20
+        // minutes after its parent; later blocks go back to the difficulty
+        // before them
+        let params = Params::new(Network::Testnet);
+        let parent = |h: &BlockHeader| -> Result<BlockHeader, ()> {
+            let height = h.nonce - 1;
+            let bits = if height < 4040 { 0x1c00ffff } else { 0x1d00ffff };
+            Ok(header(height, 1_600_000_000 + height * 600, bits))
+        };
+        let last = header(4050, 1_600_000_000 + 4050 * 600, 0x1d00ffff);
+        assert_eq!(next_bits(&params, &last, 4050, last.time + 1200, parent), Ok(0x1c00ffff));
+        assert_eq!(next_bits(&params, &last, 4050, last.time + 1201, parent), Ok(0x1d00ffff));
+        // The walk back stops at the start of the retarget period
+        let last = header(4040, 1_600_000_000 + 4040 * 600, 0x1d00ffff);
+        let parent = |h: &BlockHeader| -> Result<BlockHeader, ()> {
+            Ok(header(h.nonce - 1, 0, 0x1d00ffff))
+        };
+        assert_eq!(next_bits(&params, &last, 4040, last.time, parent), Ok(0x1d00ffff));
+    }
+
+    #[test]
+    fn median_time_past_test() {
+        let times = [7, 1, 9, 3, 12, 5, 2, 10, 4, 11, 8, 6];
+        let parent = |h: &BlockHeader| -> Result<BlockHeader, ()> {
+            let height = h.nonce - 1;
+            Ok(header(height, times[height as usize], 0))
+        };
+        // The last 11 times are 1 to 11
+        let last = header(11, times[11], 0);
+        assert_eq!(median_time_past(&last, 11, parent), Ok(6));
+        let last = header(2, times[2], 0);
+        assert_eq!(median_time_past(&last, 2, parent), Ok(7));
+        let last = header(0, times[0], 0);
+        assert_eq!(median_time_past(&last, 0, parent), Ok(7));
+    }
+
+    #[test]
+    fn check_header_context_test() {
+        let h = header(1, 10_000, 0x1d00ffff);
+        assert_eq!(check_header_context(&h, 0x1d00ffff, 9_999, 10_000), Ok(()));
+        assert_eq!(check_header_context(&h, 0x1d00fffe, 9_999, 10_000),
+                   Err(Error::BadDifficultyBits { required: 0x1d00fffe, actual: 0x1d00ffff }));
+        assert_eq!(check_header_context(&h, 0x1d00ffff, 10_000, 10_000),
+                   Err(Error::TimeTooOld { time: 10_000, median_time_past: 10_000 }));
+        assert_eq!(check_header_context(&h, 0x1d00ffff, 0, 10_000 - MAX_FUTURE_BLOCK_TIME), Ok(()));
+        assert_eq!(check_header_context(&h, 0x1d00ffff, 0, 9_999 - MAX_FUTURE_BLOCK_TIME),
+                   Err(Error::TimeTooNew { time: 10_000, max_time: 9_999 }));
+    }
+}
@@ -0,0 +1,338 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Version bits
+//!
+//! This module implements BIP9 soft fork deployments: the deployments of
+//! each network, the state of a deployment at a given block and the
+//! signalling bits of a block header's version.
+//!
+//! The functions evaluating a chain take a closure returning the header at
+//! a given height of that chain, so that headers can come from any storage.
+//!
+
+use std::cmp;
+
+use blockdata::block::BlockHeader;
+use consensus::params::Params;
+use consensus::pow::MEDIAN_TIME_SPAN;
+use network::constants::Network;
+
+/// Mask of the top bits of a block version that signal BIP9 is used
+pub const VERSIONBITS_TOP_MASK: u32 = 0xE0000000;
+/// Value of the top bits of a block version that signal BIP9 is used
+pub const VERSIONBITS_TOP_BITS: u32 = 0x20000000;
+/// Number of bits of a block version available for signalling
+pub const VERSIONBITS_NUM_BITS: u8 = 29;
+
+/// Start time of a deployment that is always active
+pub const ALWAYS_ACTIVE: i64 = -1;
+/// Start time of a deployment that is never active
+pub const NEVER_ACTIVE: i64 = -2;
+/// Timeout of a deployment that does not time out
+pub const NO_TIMEOUT: i64 = 0x7fffffffffffffff;
+
+/// A BIP9 soft fork deployment
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Deployment {
+    /// Name of the deployment
+    pub name: &'static str,
+    /// Bit of the block version signalling for the deployment
+    pub bit: u8,
+    /// Median time past from which signalling counts, or `ALWAYS_ACTIVE`
+    /// or `NEVER_ACTIVE`
+    pub start_time: i64,
+    /// Median time past from which the deployment fails if not locked in,
+    /// or `NO_TIMEOUT`
+    pub timeout: i64,
+    /// Height before which a locked in deployment does not become active
+    pub min_activation_height: u32,
+}
+
+static BITCOIN_DEPLOYMENTS: [Deployment; 3] = [
+    Deployment { name: "csv", bit: 0, start_time: 1462060800, timeout: 1493596800, min_activation_height: 0 },
+    Deployment { name: "segwit", bit: 1, start_time: 1479168000, timeout: 1510704000, min_activation_height: 0 },
+    Deployment { name: "taproot", bit: 2, start_time: 1619222400, timeout: 1628640000, min_activation_height: 709632 },
+];
+
+static TESTNET_DEPLOYMENTS: [Deployment; 3] = [
+    Deployment { name: "csv", bit: 0, start_time: 1456790400, timeout: 1493596800, min_activation_height: 0 },
+    Deployment { name: "segwit", bit: 1, start_time: 1462060800, timeout: 1493596800, min_activation_height: 0 },
+    Deployment { name: "taproot", bit: 2, start_time: 1619222400, timeout: 1628640000, min_activation_height: 0 },
+];
+
+static REGTEST_DEPLOYMENTS: [Deployment; 3] = [
+    Deployment { name: "csv", bit: 0, start_time: ALWAYS_ACTIVE, timeout: NO_TIMEOUT, min_activation_height: 0 },
+    Deployment { name: "segwit", bit: 1, start_time: ALWAYS_ACTIVE, timeout: NO_TIMEOUT, min_activation_height: 0 },
+    Deployment { name: "taproot", bit: 2, start_time: ALWAYS_ACTIVE, timeout: NO_TIMEOUT, min_activation_height: 0 },
+];
+
+/// The BIP9 deployments of `network`
+pub fn deployments(network: Network) -> &'static [Deployment] {
+    match network {
+        Network::Bitcoin => &BITCOIN_DEPLOYMENTS,
+        Network::Testnet => &TESTNET_DEPLOYMENTS,
+        Network::Regtest => &REGTEST_DEPLOYMENTS,
+    }
+}
+
+/// Whether a block `version` uses BIP9 signalling
+pub fn is_versionbits(version: u32) -> bool {
+    version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS
+}
+
+/// The bits a block `version` signals for, in increasing order; none if
+/// it does not use BIP9 signalling
+pub fn signalled_bits(version: u32) -> Vec<u8> {
+    if !is_versionbits(version) {
+        return vec![];
+    }
+    (0..VERSIONBITS_NUM_BITS).filter(|bit| version & (1 << bit) != 0).collect()
+}
+
+/// State of a deployment
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ThresholdState {
+    /// The start time has not been reached
+    Defined,
+    /// Blocks signalling for the deployment are counted
+    Started,
+    /// Enough blocks signalled, the deployment will become active
+    LockedIn,
+    /// The deployment is active
+    Active,
+    /// The deployment timed out without being locked in
+    Failed,
+}
+
+/// Signalling for a deployment in a retarget period
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Statistics {
+    /// Number of blocks in a period
+    pub period: u32,
+    /// Number of signalling blocks needed in a period
+    pub threshold: u32,
+    /// Number of blocks of the period so far
+    pub elapsed: u32,
+    /// Number of signalling blocks of the period so far
+    pub count: u32,
+    /// Whether the threshold can still be reached in the period
+    pub possible: bool,
+}
+
+impl Deployment {
+    /// Whether a block `version` signals for the deployment
+    pub fn signals(&self, version: u32) -> bool {
+        is_versionbits(version) && version & (1 << self.bit) != 0
+    }
+
+    /// Compute the state of the deployment for the block at `height`.
+    /// `header_at` must return the header at the given height of the
+    /// chain the block extends; it is called for heights below `height`.
+    /// The state is the same for all blocks of a retarget period.
+    pub fn state<E, F>(&self, params: &Params, height: u32, mut header_at: F) -> Result<ThresholdState, E>
+        where F: FnMut(u32) -> Result<BlockHeader, E>
+    {
+        if self.start_time == ALWAYS_ACTIVE {
+            return Ok(ThresholdState::Active);
+        }
+        if self.start_time == NEVER_ACTIVE {
+            return Ok(ThresholdState::Failed);
+        }
+        let period = params.miner_confirmation_window;
+
+        // Walk back over the ends of previous periods to one before the
+        // start time, where the deployment is defined
+        let mut ends = vec![];
+        let mut periods = height / period;
+        while periods > 0 {
+            let end = periods * period - 1;
+            let mtp = median_time_past(end, &mut header_at)?;
+            if (mtp as i64) < self.start_time {
+                break;
+            }
+            ends.push((end, mtp));
+            periods -= 1;
+        }
+
+        let mut state = ThresholdState::Defined;
+        for (end, mtp) in ends.into_iter().rev() {
+            state = match state {
+                ThresholdState::Defined => ThresholdState::Started,
+                ThresholdState::Started => {
+                    let mut count = 0;
+                    for height in end + 1 - period..end + 1 {
+                        if self.signals(header_at(height)?.version) {
+                            count += 1;
+                        }
+                    }
+                    if count >= params.rule_change_activation_threshold {
+                        ThresholdState::LockedIn
+                    } else if mtp as i64 >= self.timeout {
+                        ThresholdState::Failed
+                    } else {
+                        ThresholdState::Started
+                    }
+                }
+                ThresholdState::LockedIn => {
+                    if end + 1 >= self.min_activation_height {
+                        ThresholdState::Active
+                    } else {
+                        ThresholdState::LockedIn
+                    }
+                }
+                state => state,
+            };
+        }
+        Ok(state)
+    }
+
+    /// Count the blocks signalling for the deployment in the retarget
+    /// period of the block at `height`, up to and including that block.
+    /// `header_at` must return the header at the given height of the chain.
+    pub fn statistics<E, F>(&self, params: &Params, height: u32, mut header_at: F) -> Result<Statistics, E>
+        where F: FnMut(u32) -> Result<BlockHeader, E>
+    {
+        let period = params.miner_confirmation_window;
+        let threshold = params.rule_change_activation_threshold;
+        let start = height - height % period;
+        let mut count = 0;
+        for height in start..height + 1 {
+            if self.signals(header_at(height)?.version) {
+                count += 1;
+            }
+        }
+        let elapsed = height + 1 - start;
+        Ok(Statistics {
+            period,
+            threshold,
+            elapsed,
+            count,
+            possible: period - elapsed >= threshold.saturating_sub(count),
+        })
+    }
+}
+
+/// The median time of the block at `height` and the blocks before it,
+/// `MEDIAN_TIME_SPAN` blocks in all
+fn median_time_past<E, F>(height: u32, header_at: &mut F) -> Result<u32, E>
+    where F: FnMut(u32) -> Result<BlockHeader, E>
+{
+    let first = height - cmp::min(height, MEDIAN_TIME_SPAN as u32 - 1);
+    let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+    for height in first..height + 1 {
+        times.push(header_at(height)?.time);
+    }
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use consensus::params::Params;
+    use network::constants::Network;
+
+    use super::*;
+
+    const TIME: u32 = 1_000_000;
+
+    /// The header at `height` of a chain whose blocks are 10 minutes apart
+    fn header(height: u32, version: u32) -> Result<BlockHeader, ()> {
+        Ok(BlockHeader {
+            version,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: TIME + 600 * height,
+            bits: 0,
+            nonce: 0,
+        })
+    }
+
+    #[test]
+    fn signalling_test() {
+        assert!(is_versionbits(0x20000000));
+        assert!(!is_versionbits(0x40000001));
+        assert_eq!(signalled_bits(0x20000005), vec![0, 2]);
+        assert_eq!(signalled_bits(0x30000000), vec![28]);
+        assert!(signalled_bits(0x00000005).is_empty());
+
+        let taproot = deployments(Network::Bitcoin)[2];
+        assert_eq!(taproot.name, "taproot");
+        assert!(taproot.signals(0x20000004));
+        assert!(!taproot.signals(0x20000003));
+        assert!(!taproot.signals(0x00000004));
+    }
+
+    #[test]
+    fn state_test() {
+        // Regtest has periods of 144 blocks with a threshold of 108
+        let params = Params::new(Network::Regtest);
+        let deployment = Deployment {
+            name: "test",
+            bit: 3,
+            // The median time past at the end of the first period
+            start_time: (TIME + 600 * 138) as i64,
+            timeout: NO_TIMEOUT,
+            min_activation_height: 0,
+        };
+        // The first `count` blocks of the second period signal
+        let signalling = |count: u32| move |h: u32| {
+            header(h, if h >= 144 && h < 144 + count { 0x20000008 } else { 0x20000000 })
+        };
+
+        assert_eq!(deployment.state(&params, 143, signalling(108)), Ok(ThresholdState::Defined));
+        assert_eq!(deployment.state(&params, 144, signalling(108)), Ok(ThresholdState::Started));
+        assert_eq!(deployment.state(&params, 287, signalling(108)), Ok(ThresholdState::Started));
+        assert_eq!(deployment.state(&params, 288, signalling(108)), Ok(ThresholdState::LockedIn));
+        assert_eq!(deployment.state(&params, 432, signalling(108)), Ok(ThresholdState::Active));
+        assert_eq!(deployment.state(&params, 10_000, signalling(108)), Ok(ThresholdState::Active));
+        assert_eq!(deployment.state(&params, 10_000, signalling(107)), Ok(ThresholdState::Started));
+
+        let late = Deployment { start_time: deployment.start_time + 1, ..deployment };
+        assert_eq!(late.state(&params, 144, signalling(108)), Ok(ThresholdState::Defined));
+        assert_eq!(late.state(&params, 288, signalling(108)), Ok(ThresholdState::Started));
+
+        let timeout = Deployment { timeout: (TIME + 600 * 282) as i64, ..deployment };
+        assert_eq!(timeout.state(&params, 288, signalling(107)), Ok(ThresholdState::Failed));
+        assert_eq!(timeout.state(&params, 1000, signalling(107)), Ok(ThresholdState::Failed));
+        // Locking in has precedence over timing out
+        assert_eq!(timeout.state(&params, 288, signalling(108)), Ok(ThresholdState::LockedIn));
+
+        let delayed = Deployment { min_activation_height: 600, ..deployment };
+        assert_eq!(delayed.state(&params, 719, signalling(108)), Ok(ThresholdState::LockedIn));
+        assert_eq!(delayed.state(&params, 720, signalling(108)), Ok(ThresholdState::Active));
+
+        let always = Deployment { start_time: ALWAYS_ACTIVE, ..deployment };
+        assert_eq!(always.state(&params, 0, signalling(0)), Ok(ThresholdState::Active));
+        let never = Deployment { start_time: NEVER_ACTIVE, ..deployment };
+        assert_eq!(never.state(&params, 1000, signalling(144)), Ok(ThresholdState::Failed));
+    }
+
+    #[test]
+    fn statistics_test() {
+        let params = Params::new(Network::Regtest);
+        let deployment = deployments(Network::Regtest)[1];
+        let chain = |h: u32| header(h, if h % 2 == 1 { 0x20000000 } else { 0x20000002 });
+        assert_eq!(deployment.statistics(&params, 144 + 49, &chain), Ok(Statistics {
+            period: 144,
+            threshold: 108,
+            elapsed: 50,
+            count: 25,
+            possible: true,
+        }));
+        let stats = deployment.statistics(&params, 144 + 99, &chain).unwrap();
+        assert_eq!((stats.elapsed, stats.count, stats.possible), (100, 50, false));
+    }
+}
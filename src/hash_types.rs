@@ -0,0 +1,88 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Hash types
+//!
+//! This module defines types for hashes used throughout the library. These
+//! types are needed in order to avoid mixing data of the same hash format
+//! (like SHA256d) but of different meaning (transaction id, block hash etc).
+//!
+
+use std::io;
+
+use hashes::{Hash, sha256d};
+
+use consensus::encode::{self, Encodable, Decodable};
+
+macro_rules! impl_hashencode {
+    ($hashtype:ident) => {
+        impl Encodable for $hashtype {
+            fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+                self.0.consensus_encode(s)
+            }
+        }
+
+        impl Decodable for $hashtype {
+            fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+                Ok(Self::from_inner(<<$hashtype as Hash>::Inner>::consensus_decode(d)?))
+            }
+        }
+    }
+}
+
+hash_newtype!(Txid, sha256d::Hash, 32, doc="A bitcoin transaction hash/transaction ID.");
+hash_newtype!(Wtxid, sha256d::Hash, 32, doc="A bitcoin witness transaction ID.");
+hash_newtype!(BlockHash, sha256d::Hash, 32, doc="A bitcoin block hash.");
+
+hash_newtype!(TxMerkleNode, sha256d::Hash, 32, doc="A hash of the Merkle tree branch or root for transactions");
+hash_newtype!(WitnessMerkleNode, sha256d::Hash, 32, doc="A hash corresponding to the Merkle tree root for witness data");
+hash_newtype!(WitnessCommitment, sha256d::Hash, 32, doc="A hash corresponding to the witness structure commitment in the coinbase transaction");
+
+hash_newtype!(FilterHash, sha256d::Hash, 32, doc="Filter hash, as defined in BIP-157");
+hash_newtype!(FilterHeader, sha256d::Hash, 32, doc="Filter header, as defined in BIP-157");
+
+impl_hashencode!(Txid);
+impl_hashencode!(Wtxid);
+impl_hashencode!(BlockHash);
+
+impl_hashencode!(TxMerkleNode);
+impl_hashencode!(WitnessMerkleNode);
+impl_hashencode!(WitnessCommitment);
+
+impl_hashencode!(FilterHash);
+impl_hashencode!(FilterHeader);
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use consensus::encode::{deserialize, serialize};
+    use super::{BlockHash, Txid};
+
+    #[test]
+    fn hash_newtype_encoding() {
+        let hex = "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000";
+        let hash = BlockHash::from_hex(hex).unwrap();
+        assert_eq!(hash.to_string(), hex);
+
+        // Consensus encoding is the same as the inner `sha256d::Hash`
+        let bytes = serialize(&hash);
+        assert_eq!(bytes, serialize(&hash.as_hash()));
+        assert_eq!(deserialize::<BlockHash>(&bytes).unwrap(), hash);
+
+        // Re-interpreting the same bytes needs an explicit conversion
+        let txid = Txid::from_hash(hash.as_hash());
+        assert_eq!(&txid[..], &hash[..]);
+    }
+}
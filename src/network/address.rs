@@ -18,12 +18,14 @@
 //! network addresses in Bitcoin messages.
 //!
 
-use std::io;
+use std::{error, io};
 use std::fmt;
-use std::net::{SocketAddr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::net::{IpAddr, SocketAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
 
 use network::constants::ServiceFlags;
-use consensus::encode::{self, Decodable, Encodable};
+use consensus::encode::{self, Decodable, Encodable, ReadExt, VarInt};
+use util::endian;
 
 /// A message which can be sent on the Bitcoin network
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -105,13 +107,417 @@ impl fmt::Debug for Address {
     }
 }
 
+/// Maximum length of an address in an `addrv2` message, as in BIP155
+pub const MAX_ADDRV2_SIZE: usize = 512;
+
+/// A network address of any of the networks supported by BIP155
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AddrV2 {
+    /// IPv4 address
+    Ipv4(Ipv4Addr),
+    /// IPv6 address
+    Ipv6(Ipv6Addr),
+    /// Tor v2 hidden service, deprecated by Tor
+    TorV2([u8; 10]),
+    /// Tor v3 hidden service, identified by its ed25519 public key
+    TorV3([u8; 32]),
+    /// I2P address, the SHA256 of its destination
+    I2p([u8; 32]),
+    /// CJDNS address, an IPv6 address in fc00::/8
+    Cjdns(Ipv6Addr),
+    /// Address of a network unknown to us, by network ID
+    Unknown(u8, Vec<u8>),
+}
+
+/// Version byte of Tor v3 onion addresses
+const TORV3_VERSION: u8 = 3;
+
+impl AddrV2 {
+    /// The BIP155 network ID of the address
+    pub fn network_id(&self) -> u8 {
+        match *self {
+            AddrV2::Ipv4(..) => 1,
+            AddrV2::Ipv6(..) => 2,
+            AddrV2::TorV2(..) => 3,
+            AddrV2::TorV3(..) => 4,
+            AddrV2::I2p(..) => 5,
+            AddrV2::Cjdns(..) => 6,
+            AddrV2::Unknown(id, _) => id,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            AddrV2::Ipv4(ref addr) => addr.octets().to_vec(),
+            AddrV2::Ipv6(ref addr) | AddrV2::Cjdns(ref addr) => addr.octets().to_vec(),
+            AddrV2::TorV2(ref addr) => addr.to_vec(),
+            AddrV2::TorV3(ref addr) | AddrV2::I2p(ref addr) => addr.to_vec(),
+            AddrV2::Unknown(_, ref addr) => addr.clone(),
+        }
+    }
+}
+
+/// The checksum of a Tor v3 onion address with public key `pubkey`
+fn torv3_checksum(pubkey: &[u8]) -> [u8; 2] {
+    let mut data = b".onion checksum".to_vec();
+    data.extend_from_slice(pubkey);
+    data.push(TORV3_VERSION);
+    let hash = sha3_256(&data);
+    [hash[0], hash[1]]
+}
+
+impl fmt::Display for AddrV2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddrV2::Ipv4(ref addr) => fmt::Display::fmt(addr, f),
+            AddrV2::Ipv6(ref addr) | AddrV2::Cjdns(ref addr) => fmt::Display::fmt(addr, f),
+            AddrV2::TorV2(ref addr) => write!(f, "{}.onion", base32_encode(addr)),
+            AddrV2::TorV3(ref pubkey) => {
+                let mut data = pubkey.to_vec();
+                data.extend_from_slice(&torv3_checksum(pubkey));
+                data.push(TORV3_VERSION);
+                write!(f, "{}.onion", base32_encode(&data))
+            }
+            AddrV2::I2p(ref addr) => write!(f, "{}.b32.i2p", base32_encode(addr)),
+            AddrV2::Unknown(id, ref addr) => {
+                write!(f, "unknown({}):", id)?;
+                for byte in addr {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An error parsing an `AddrV2` from a string
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AddrV2ParseError {
+    /// Neither an IP address, an onion address nor an I2P address
+    UnknownFormat,
+    /// The onion or I2P address is not valid base32
+    InvalidBase32,
+    /// The onion or I2P address decodes to this invalid number of bytes
+    InvalidLength(usize),
+    /// The onion address has an unknown version
+    UnknownOnionVersion(u8),
+    /// The checksum of the onion address does not match
+    InvalidOnionChecksum,
+}
+
+impl fmt::Display for AddrV2ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddrV2ParseError::InvalidLength(len) => write!(f, "invalid address length {}", len),
+            AddrV2ParseError::UnknownOnionVersion(v) => write!(f, "unknown onion address version {}", v),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for AddrV2ParseError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl AddrV2ParseError {
+    fn reason(&self) -> &'static str {
+        match *self {
+            AddrV2ParseError::UnknownFormat => "unknown address format",
+            AddrV2ParseError::InvalidBase32 => "invalid base32",
+            AddrV2ParseError::InvalidLength(..) => "invalid address length",
+            AddrV2ParseError::UnknownOnionVersion(..) => "unknown onion address version",
+            AddrV2ParseError::InvalidOnionChecksum => "invalid onion address checksum",
+        }
+    }
+}
+
+impl FromStr for AddrV2 {
+    type Err = AddrV2ParseError;
+
+    /// Parse an IP address, a Tor `.onion` address or an I2P `.b32.i2p`
+    /// address. IPv6 addresses are never parsed as CJDNS addresses.
+    fn from_str(s: &str) -> Result<AddrV2, AddrV2ParseError> {
+        if let Some(host) = strip_suffix(s, ".onion") {
+            let data = base32_decode(host).ok_or(AddrV2ParseError::InvalidBase32)?;
+            match data.len() {
+                10 => {
+                    let mut addr = [0; 10];
+                    addr.copy_from_slice(&data);
+                    Ok(AddrV2::TorV2(addr))
+                }
+                35 => {
+                    if data[34] != TORV3_VERSION {
+                        return Err(AddrV2ParseError::UnknownOnionVersion(data[34]));
+                    }
+                    if torv3_checksum(&data[0..32]) != [data[32], data[33]] {
+                        return Err(AddrV2ParseError::InvalidOnionChecksum);
+                    }
+                    let mut pubkey = [0; 32];
+                    pubkey.copy_from_slice(&data[0..32]);
+                    Ok(AddrV2::TorV3(pubkey))
+                }
+                len => Err(AddrV2ParseError::InvalidLength(len)),
+            }
+        } else if let Some(host) = strip_suffix(s, ".b32.i2p") {
+            let data = base32_decode(host).ok_or(AddrV2ParseError::InvalidBase32)?;
+            if data.len() != 32 {
+                return Err(AddrV2ParseError::InvalidLength(data.len()));
+            }
+            let mut addr = [0; 32];
+            addr.copy_from_slice(&data);
+            Ok(AddrV2::I2p(addr))
+        } else {
+            match IpAddr::from_str(s) {
+                Ok(IpAddr::V4(addr)) => Ok(AddrV2::Ipv4(addr)),
+                Ok(IpAddr::V6(addr)) => Ok(AddrV2::Ipv6(addr)),
+                Err(_) => Err(AddrV2ParseError::UnknownFormat),
+            }
+        }
+    }
+}
+
+impl Encodable for AddrV2 {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let len = self.network_id().consensus_encode(&mut s)?;
+        Ok(len + self.to_bytes().consensus_encode(&mut s)?)
+    }
+}
+
+impl Decodable for AddrV2 {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let network_id = u8::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        if len > MAX_ADDRV2_SIZE as u64 {
+            return Err(encode::Error::OversizedVectorAllocation {
+                requested: len as usize,
+                max: MAX_ADDRV2_SIZE,
+            });
+        }
+        let mut data = vec![0; len as usize];
+        d.read_slice(&mut data)?;
+
+        let expected_len = match network_id {
+            1 => 4,
+            2 | 6 => 16,
+            3 => 10,
+            4 | 5 => 32,
+            _ => return Ok(AddrV2::Unknown(network_id, data)),
+        };
+        if data.len() != expected_len {
+            return Err(encode::Error::ParseFailed("invalid addrv2 address length"));
+        }
+        Ok(match network_id {
+            1 => AddrV2::Ipv4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            2 | 6 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&data);
+                let addr = Ipv6Addr::from(octets);
+                if network_id == 2 {
+                    AddrV2::Ipv6(addr)
+                } else if octets[0] == 0xfc {
+                    AddrV2::Cjdns(addr)
+                } else {
+                    return Err(encode::Error::ParseFailed("CJDNS address outside fc00::/8"));
+                }
+            }
+            3 => {
+                let mut addr = [0; 10];
+                addr.copy_from_slice(&data);
+                AddrV2::TorV2(addr)
+            }
+            _ => {
+                let mut addr = [0; 32];
+                addr.copy_from_slice(&data);
+                if network_id == 4 { AddrV2::TorV3(addr) } else { AddrV2::I2p(addr) }
+            }
+        })
+    }
+}
+
+/// An entry of an `addrv2` message
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AddrV2Message {
+    /// Time the address was last seen
+    pub time: u32,
+    /// Services provided by the peer whose address this is
+    pub services: ServiceFlags,
+    /// Network address
+    pub addr: AddrV2,
+    /// Network port, 0 if not applicable
+    pub port: u16,
+}
+
+impl AddrV2Message {
+    /// The socket address of the entry, for the networks using IP addresses.
+    /// This will return io::Error ErrorKind::AddrNotAvailable for others.
+    pub fn socket_addr(&self) -> Result<SocketAddr, io::Error> {
+        match self.addr {
+            AddrV2::Ipv4(addr) => Ok(SocketAddr::V4(SocketAddrV4::new(addr, self.port))),
+            AddrV2::Ipv6(addr) | AddrV2::Cjdns(addr) => {
+                Ok(SocketAddr::V6(SocketAddrV6::new(addr, self.port, 0, 0)))
+            }
+            _ => Err(io::Error::from(io::ErrorKind::AddrNotAvailable)),
+        }
+    }
+}
+
+impl Encodable for AddrV2Message {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.time.consensus_encode(&mut s)?;
+        len += VarInt(self.services.as_u64()).consensus_encode(&mut s)?;
+        len += self.addr.consensus_encode(&mut s)?;
+        len += self.port.to_be().consensus_encode(&mut s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for AddrV2Message {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(AddrV2Message {
+            time: Decodable::consensus_decode(&mut d)?,
+            services: ServiceFlags::from(VarInt::consensus_decode(&mut d)?.0),
+            addr: Decodable::consensus_decode(&mut d)?,
+            port: u16::from_be(Decodable::consensus_decode(&mut d)?),
+        })
+    }
+}
+
+/// `s` without `suffix`, if it ends with it
+fn strip_suffix<'a>(s: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = s.len().checked_sub(suffix.len())?;
+    if s.get(split..) == Some(suffix) { s.get(..split) } else { None }
+}
+
+/// The RFC4648 base32 alphabet, in lower case as used by Tor and I2P
+const BASE32_CHARS: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE32_CHARS_UPPER: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encode `data` as unpadded base32
+fn base32_encode(data: &[u8]) -> String {
+    let mut ret = String::with_capacity(data.len() * 8 / 5 + 1);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for byte in data {
+        acc = (acc << 8 | u32::from(*byte)) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            ret.push(BASE32_CHARS[(acc >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        ret.push(BASE32_CHARS[(acc << (5 - bits)) as usize & 31] as char);
+    }
+    ret
+}
+
+/// Decode unpadded base32 in either case, requiring unused bits to be zero
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut ret = Vec::with_capacity(s.len() * 5 / 8);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_CHARS.iter().position(|&x| x == c)
+            .or_else(|| BASE32_CHARS_UPPER.iter().position(|&x| x == c))?;
+        acc = (acc << 5 | value as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(ret)
+}
+
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+    0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+    0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+    0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+    0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+    0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008,
+];
+
+const KECCAK_ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+const KECCAK_PI_LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// The Keccak-f[1600] permutation
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in KECCAK_ROUND_CONSTANTS.iter() {
+        // theta
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+        }
+        for (i, lane) in state.iter_mut().enumerate() {
+            let x = i % 5;
+            *lane ^= columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+        }
+        // rho and pi
+        let mut last = state[1];
+        for (&lane, &rotation) in KECCAK_PI_LANES.iter().zip(KECCAK_ROTATIONS.iter()) {
+            let next = state[lane];
+            state[lane] = last.rotate_left(rotation);
+            last = next;
+        }
+        // chi
+        for row in state.chunks_mut(5) {
+            let mut copy = [0u64; 5];
+            copy.copy_from_slice(row);
+            for (x, lane) in row.iter_mut().enumerate() {
+                *lane ^= !copy[(x + 1) % 5] & copy[(x + 2) % 5];
+            }
+        }
+        // iota
+        state[0] ^= *round_constant;
+    }
+}
+
+/// SHA3-256, as needed for the checksum of Tor v3 onion addresses
+fn sha3_256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+    let mut padded = data.to_vec();
+    padded.push(0x06);
+    padded.resize((data.len() / RATE + 1) * RATE, 0);
+    *padded.last_mut().expect("padded data is not empty") |= 0x80;
+
+    let mut state = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (lane, word) in state.iter_mut().zip(block.chunks(8)) {
+            *lane ^= endian::slice_to_u64_le(word);
+        }
+        keccak_f(&mut state);
+    }
+    let mut ret = [0; 32];
+    for (bytes, lane) in ret.chunks_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&endian::u64_to_array_le(*lane));
+    }
+    ret
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
-    use super::Address;
+    use super::{Address, AddrV2, AddrV2Message, AddrV2ParseError, base32_decode, base32_encode, sha3_256};
     use network::constants::ServiceFlags;
     use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
 
+    use hex::decode as hex_decode;
     use consensus::encode::{deserialize, serialize};
 
     #[test]
@@ -165,5 +571,98 @@ mod test {
         let addr = Address::new(&onionaddr, ServiceFlags::NONE);
         assert!(addr.socket_addr().is_err());
     }
-}
 
+    #[test]
+    fn sha3_and_base32() {
+        assert_eq!(sha3_256(b"")[..], hex_decode("a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a").unwrap()[..]);
+        assert_eq!(sha3_256(b"abc")[..], hex_decode("3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532").unwrap()[..]);
+        assert_eq!(sha3_256(&[0x61; 200])[..], hex_decode("cce34485baf2bf2aca99b94833892a4f52896d3d153f7b840cc4f9fe695f1387").unwrap()[..]);
+
+        // RFC4648 vectors, unpadded and in lower case
+        for &(data, encoded) in &[("", ""), ("f", "my"), ("fo", "mzxq"), ("foo", "mzxw6"), ("foob", "mzxw6yq"),
+                                  ("fooba", "mzxw6ytb"), ("foobar", "mzxw6ytboi")] {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), data.as_bytes());
+            assert_eq!(base32_decode(&encoded.to_uppercase()).unwrap(), data.as_bytes());
+        }
+        assert_eq!(base32_decode("mzxw6yr"), None);
+        assert_eq!(base32_decode("mzx1"), None);
+    }
+
+    #[test]
+    fn addrv2_serialize() {
+        let cases = vec![
+            ("010401020304", AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)), "1.2.3.4"),
+            ("02100102030405060708090a0b0c0d0e0f10",
+             AddrV2::Ipv6(Ipv6Addr::from_str("102:304:506:708:90a:b0c:d0e:f10").unwrap()),
+             "102:304:506:708:90a:b0c:d0e:f10"),
+            ("030af1f2f3f4f5f6f7f8f9fa", AddrV2::TorV2([0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa]),
+             "6hzph5hv6337r6p2.onion"),
+            ("042079bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f",
+             AddrV2::TorV3(hex32("79bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f")),
+             "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion"),
+            ("0520a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87",
+             AddrV2::I2p(hex32("a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87")),
+             "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p"),
+            ("0610fc000001000200030004000500060007",
+             AddrV2::Cjdns(Ipv6Addr::from_str("fc00:1:2:3:4:5:6:7").unwrap()),
+             "fc00:1:2:3:4:5:6:7"),
+            ("aa0401020304", AddrV2::Unknown(0xaa, vec![1, 2, 3, 4]), "unknown(170):01020304"),
+        ];
+        for (hex, addr, string) in cases {
+            let bytes = hex_decode(hex).unwrap();
+            assert_eq!(deserialize::<AddrV2>(&bytes).unwrap(), addr);
+            assert_eq!(serialize(&addr), bytes);
+            assert_eq!(addr.to_string(), string);
+            if let AddrV2::Cjdns(..) = addr {} else if let AddrV2::Unknown(..) = addr {} else {
+                assert_eq!(AddrV2::from_str(string).unwrap(), addr);
+            }
+        }
+
+        // Lengths must match the network
+        assert!(deserialize::<AddrV2>(&hex_decode("0105010203040506").unwrap()).is_err());
+        assert!(deserialize::<AddrV2>(&hex_decode("04030102").unwrap()).is_err());
+        // CJDNS addresses must be in fc00::/8
+        assert!(deserialize::<AddrV2>(&hex_decode("0610fd000001000200030004000500060007").unwrap()).is_err());
+        let mut oversized = hex_decode("aafd0102").unwrap();
+        oversized.extend(vec![0; 513]);
+        assert!(deserialize::<AddrV2>(&oversized).is_err());
+    }
+
+    #[test]
+    fn addrv2_parse() {
+        assert_eq!(AddrV2::from_str("PG6MMJIYJMCRSSLVYKFWNNTLARU7P5SVN6Y2YMMJU6NUBXNDF4PSCRYD.onion").unwrap(),
+                   AddrV2::TorV3(hex32("79bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f")));
+        assert_eq!(AddrV2::from_str("pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscry1.onion"),
+                   Err(AddrV2ParseError::InvalidBase32));
+        assert_eq!(AddrV2::from_str("pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion".replace("pg6", "ph6").as_str()),
+                   Err(AddrV2ParseError::InvalidOnionChecksum));
+        assert_eq!(AddrV2::from_str("pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscrya.onion"),
+                   Err(AddrV2ParseError::UnknownOnionVersion(0)));
+        assert_eq!(AddrV2::from_str("mzxw6.onion"), Err(AddrV2ParseError::InvalidLength(3)));
+        assert_eq!(AddrV2::from_str("example.com"), Err(AddrV2ParseError::UnknownFormat));
+    }
+
+    #[test]
+    fn addrv2_message() {
+        let bytes = hex_decode("6400000001010401020304208d").unwrap();
+        let msg: AddrV2Message = deserialize(&bytes).unwrap();
+        assert_eq!(msg, AddrV2Message {
+            time: 100,
+            services: ServiceFlags::NETWORK,
+            addr: AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            port: 8333,
+        });
+        assert_eq!(serialize(&msg), bytes);
+        assert_eq!(msg.socket_addr().unwrap(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 8333));
+
+        let tor = AddrV2Message { addr: AddrV2::TorV3([0; 32]), ..msg };
+        assert!(tor.socket_addr().is_err());
+    }
+
+    fn hex32(hex: &str) -> [u8; 32] {
+        let mut ret = [0; 32];
+        ret.copy_from_slice(&hex_decode(hex).unwrap());
+        ret
+    }
+}
@@ -0,0 +1,417 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Async message framing
+//!
+//! This module defines `MessageReader` and `MessageWriter`, which frame
+//! `RawNetworkMessage`s over the `AsyncRead` and `AsyncWrite` streams of the
+//! `futures` crate. Unlike `StreamReader` they never block, so any number of
+//! peers can be served from a single runtime. It is only available with the
+//! `async` feature.
+//!
+//! The reader never reads past the end of the current message, so it does
+//! many small reads; wrap the stream in a buffered reader if that matters.
+//!
+
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use consensus::encode::{self, Decodable};
+use network::message::{self, MessageHeader, RawNetworkMessage, UndecodedMessage};
+
+const HEADER_SIZE: usize = MessageHeader::SIZE;
+
+/// How much of a payload is read at once; the buffer only grows as data
+/// actually arrives, not up front to the length announced in the header
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads messages from an `AsyncRead` stream
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    reader: R,
+    /// The message read so far, header then payload
+    buffer: Vec<u8>,
+    /// How many bytes of `buffer` were read
+    filled: usize,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    /// Constructs a message reader for `reader`
+    pub fn new(reader: R) -> MessageReader<R> {
+        MessageReader {
+            reader,
+            buffer: vec![0; HEADER_SIZE],
+            filled: 0,
+        }
+    }
+
+    /// The size of the message being read, as far as it is known yet
+    fn message_size(&self) -> Result<usize, message::Error> {
+        if self.filled < HEADER_SIZE {
+            return Ok(HEADER_SIZE);
+        }
+        let header = MessageHeader::consensus_decode(&self.buffer[..HEADER_SIZE])?;
+        header.check_payload_size()?;
+        Ok(HEADER_SIZE + header.length as usize)
+    }
+
+    /// Attempts to read the next message, leaving its payload undecoded.
+    /// The payload size is checked against the limit for its command as
+    /// soon as the header is read, and the checksum once the payload is.
+    pub fn poll_read_undecoded(&mut self, cx: &mut Context) -> Poll<Result<UndecodedMessage, message::Error>> {
+        loop {
+            let size = match self.message_size() {
+                Ok(size) => size,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            if self.filled == size {
+                let result = UndecodedMessage::decode_checked(&self.buffer[..size]);
+                self.filled = 0;
+                if self.buffer.len() > READ_CHUNK_SIZE {
+                    self.buffer.truncate(HEADER_SIZE);
+                    self.buffer.shrink_to_fit();
+                }
+                return Poll::Ready(result);
+            }
+
+            let end = cmp::min(size, self.filled + READ_CHUNK_SIZE);
+            if self.buffer.len() < end {
+                self.buffer.resize(end, 0);
+            }
+            match Pin::new(&mut self.reader).poll_read(cx, &mut self.buffer[self.filled..end]) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(message::Error::Io(e))),
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(message::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof))));
+                }
+                Poll::Ready(Ok(count)) => self.filled += count,
+            }
+        }
+    }
+
+    /// Attempts to read and decode the next message
+    pub fn poll_read_message(&mut self, cx: &mut Context) -> Poll<Result<RawNetworkMessage, message::Error>> {
+        match self.poll_read_undecoded(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => Poll::Ready(result.and_then(|message| message.decode())),
+        }
+    }
+
+    /// Reads the next message, leaving its payload undecoded
+    pub fn read_undecoded(&mut self) -> ReadUndecoded<'_, R> {
+        ReadUndecoded { reader: self }
+    }
+
+    /// Reads and decodes the next message
+    pub fn read_message(&mut self) -> ReadMessage<'_, R> {
+        ReadMessage { reader: self }
+    }
+
+    /// Gets a reference to the underlying stream
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Gets a mutable reference to the underlying stream. Reading from it
+    /// directly will corrupt the framing.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying stream, dropping any partially read message
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Future returned by `MessageReader::read_message`
+#[derive(Debug)]
+pub struct ReadMessage<'a, R: 'a> {
+    reader: &'a mut MessageReader<R>,
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for ReadMessage<'a, R> {
+    type Output = Result<RawNetworkMessage, message::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.reader.poll_read_message(cx)
+    }
+}
+
+/// Future returned by `MessageReader::read_undecoded`
+#[derive(Debug)]
+pub struct ReadUndecoded<'a, R: 'a> {
+    reader: &'a mut MessageReader<R>,
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for ReadUndecoded<'a, R> {
+    type Output = Result<UndecodedMessage, message::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.reader.poll_read_undecoded(cx)
+    }
+}
+
+/// Writes messages to an `AsyncWrite` stream
+#[derive(Debug)]
+pub struct MessageWriter<W> {
+    writer: W,
+    /// Serialized messages waiting to be written
+    buffer: Vec<u8>,
+    /// How many bytes of `buffer` were written
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> MessageWriter<W> {
+    /// Constructs a message writer for `writer`
+    pub fn new(writer: W) -> MessageWriter<W> {
+        MessageWriter {
+            writer,
+            buffer: vec![],
+            written: 0,
+        }
+    }
+
+    /// Queues a message to be written on the next flush
+    pub fn queue(&mut self, message: &RawNetworkMessage) {
+        self.buffer.extend(encode::serialize(message));
+    }
+
+    /// Queues an undecoded message, e.g. one being relayed, to be written
+    /// on the next flush
+    pub fn queue_undecoded(&mut self, message: &UndecodedMessage) {
+        self.buffer.extend(encode::serialize(message));
+    }
+
+    /// Attempts to write all queued messages and flush the stream
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), encode::Error>> {
+        while self.written < self.buffer.len() {
+            match Pin::new(&mut self.writer).poll_write(cx, &self.buffer[self.written..]) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(encode::Error::Io(e))),
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(encode::Error::Io(io::Error::from(io::ErrorKind::WriteZero))));
+                }
+                Poll::Ready(Ok(count)) => self.written += count,
+            }
+        }
+        self.buffer.clear();
+        self.written = 0;
+        match Pin::new(&mut self.writer).poll_flush(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => Poll::Ready(result.map_err(encode::Error::Io)),
+        }
+    }
+
+    /// Writes all queued messages and flushes the stream
+    pub fn flush(&mut self) -> Flush<'_, W> {
+        Flush { writer: self }
+    }
+
+    /// Writes `message`, after any queued ones, and flushes the stream
+    pub fn write_message(&mut self, message: &RawNetworkMessage) -> Flush<'_, W> {
+        self.queue(message);
+        self.flush()
+    }
+
+    /// Gets a reference to the underlying stream
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Gets a mutable reference to the underlying stream
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying stream, dropping any messages not written yet
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Future returned by `MessageWriter::flush` and `MessageWriter::write_message`
+#[derive(Debug)]
+pub struct Flush<'a, W: 'a> {
+    writer: &'a mut MessageWriter<W>,
+}
+
+impl<'a, W: AsyncWrite + Unpin> Future for Flush<'a, W> {
+    type Output = Result<(), encode::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.writer.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use futures_io::{AsyncRead, AsyncWrite};
+
+    use consensus::encode::{self, serialize};
+    use network::constants::ServiceFlags;
+    use network::message::{self, NetworkMessage, RawNetworkMessage};
+    use network::message_blockdata::Inventory;
+    use network::message_network::VersionMessage;
+    use network::address::Address;
+    use hashes::Hash;
+    use hash_types::Txid;
+
+    use super::{MessageReader, MessageWriter};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Polls `future` until it is ready; our test streams wake right away
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(result) = Pin::new(&mut future).poll(&mut cx) {
+                return result;
+            }
+        }
+    }
+
+    /// A stream which moves a few bytes at a time, and is only ready every other time
+    #[derive(Default)]
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let count = [buf.len(), self.data.len() - self.pos, 7].iter().cloned().min().unwrap();
+            let pos = self.pos;
+            buf[..count].copy_from_slice(&self.data[pos..pos + count]);
+            self.pos += count;
+            Poll::Ready(Ok(count))
+        }
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let count = if buf.len() < 7 { buf.len() } else { 7 };
+            self.data.extend_from_slice(&buf[..count]);
+            Poll::Ready(Ok(count))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn messages() -> Vec<RawNetworkMessage> {
+        let addr = Address::new(&"127.0.0.1:8333".parse().unwrap(), ServiceFlags::NETWORK);
+        let version = VersionMessage::new(ServiceFlags::NETWORK, 1_500_000_000, addr.clone(), addr, 42, "/test/".to_owned(), 0);
+        let payloads = vec![
+            NetworkMessage::Version(version),
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(7),
+            NetworkMessage::Inv(vec![Inventory::transaction(Txid::hash(&[1, 2, 3]))]),
+        ];
+        payloads.into_iter().map(|payload| RawNetworkMessage { magic: 0xd9b4bef9, payload }).collect()
+    }
+
+    #[test]
+    fn write_then_read() {
+        let messages = messages();
+        let mut writer = MessageWriter::new(Trickle::default());
+        block_on(writer.write_message(&messages[0])).unwrap();
+        for msg in &messages[1..] {
+            writer.queue(msg);
+        }
+        block_on(writer.flush()).unwrap();
+
+        let data = writer.into_inner().data;
+        let expected: Vec<u8> = messages.iter().flat_map(serialize).collect();
+        assert_eq!(data, expected);
+
+        let mut reader = MessageReader::new(Trickle { data, ..Default::default() });
+        for msg in &messages {
+            let read = block_on(reader.read_message()).unwrap();
+            assert_eq!(read.magic, msg.magic);
+            assert_eq!(read.payload, msg.payload);
+        }
+        match block_on(reader.read_message()) {
+            Err(message::Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Readers for in-memory data are always ready
+        let data = serialize(&messages[2]);
+        let read = block_on(MessageReader::new(&data[..]).read_message()).unwrap();
+        assert_eq!(read.payload, messages[2].payload);
+
+        // Relaying without decoding
+        let undecoded = block_on(MessageReader::new(&data[..]).read_undecoded()).unwrap();
+        assert_eq!(undecoded.header.command.as_ref(), "ping");
+        let mut writer = MessageWriter::new(vec![]);
+        writer.queue_undecoded(&undecoded);
+        block_on(writer.flush()).unwrap();
+        assert_eq!(writer.into_inner(), data);
+    }
+
+    #[test]
+    fn invalid_messages() {
+        // The length is checked before anything of the payload is read
+        let mut data = serialize(&messages()[2]);
+        data[16..20].copy_from_slice(&[0x09, 0x00, 0x00, 0x00]);
+        match block_on(MessageReader::new(&data[..24]).read_message()) {
+            Err(ref e @ message::Error::OversizedPayload { size: 9, max: 8, .. }) => assert!(e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut data = serialize(&messages()[2]);
+        data[23] ^= 1;
+        data.extend(serialize(&messages()[1]));
+        let mut reader = MessageReader::new(&data[..]);
+        match block_on(reader.read_message()) {
+            Err(ref e @ message::Error::Malformed(encode::Error::InvalidChecksum { .. })) => assert!(!e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The framing is kept, so reading may go on
+        assert_eq!(block_on(reader.read_message()).unwrap().payload, NetworkMessage::Verack);
+    }
+}
@@ -20,19 +20,184 @@
 //!
 
 use std::borrow::Cow;
-use std::io::Cursor;
-use std::{fmt, io, iter, mem};
+use std::{error, fmt, io, iter, mem};
+
+use hashes::{sha256d, Hash};
 
 use blockdata::block;
+use blockdata::interpreter::MAX_SCRIPT_ELEMENT_SIZE;
 use blockdata::transaction;
 use consensus::encode::MAX_VEC_SIZE;
-use consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
+use consensus::encode::{CheckedData, Decodable, Encodable, ReadExt, VarInt, WriteExt};
 use consensus::{encode, serialize};
-use network::address::Address;
+use network::address::{Address, AddrV2Message, MAX_ADDRV2_SIZE};
 use network::message_blockdata;
 use network::message_bloom_filter;
+use network::message_compact_blocks;
 use network::message_filter;
 use network::message_network;
+use util::amount::FeeRate;
+use util::bloom::MAX_BLOOM_FILTER_SIZE;
+use util::merkleblock;
+
+/// Maximum number of entries in an `inv`, `getdata` or `notfound` message
+pub const MAX_INV_SIZE: u64 = 50_000;
+
+/// Maximum number of headers in a `headers` message
+pub const MAX_HEADERS_RESULTS: u64 = 2_000;
+
+/// Maximum number of addresses in an `addr` or `addrv2` message
+pub const MAX_ADDR_TO_SEND: u64 = 1_000;
+
+/// Maximum number of locator hashes in a `getblocks` or `getheaders` message
+pub const MAX_LOCATOR_SIZE: u64 = 101;
+
+/// Maximum length of the user agent in a `version` message
+pub const MAX_SUBVERSION_LENGTH: usize = 256;
+
+/// Error decoding a message received from a peer
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the message failed, e.g. because the connection was closed
+    Io(io::Error),
+    /// The announced payload size exceeds the maximum for the command
+    OversizedPayload {
+        /// The command of the message
+        command: CommandString,
+        /// The announced payload size
+        size: usize,
+        /// The maximum payload size for the command
+        max: usize,
+    },
+    /// The payload has more entries than allowed for the command
+    TooManyEntries {
+        /// The command of the message
+        command: CommandString,
+        /// The number of entries
+        count: u64,
+        /// The maximum number of entries for the command
+        max: u64,
+    },
+    /// The message could not be decoded, or its checksum does not match
+    Malformed(encode::Error),
+}
+
+impl Error {
+    /// Whether the peer sent a message it should have known to be invalid,
+    /// and so is to be banned. I/O errors are not the peer's fault, and
+    /// checksum mismatches may be caused by corruption in transit, so
+    /// Bitcoin Core merely skips such messages.
+    pub fn should_ban(&self) -> bool {
+        match *self {
+            Error::Io(..) | Error::Malformed(encode::Error::InvalidChecksum { .. }) => false,
+            Error::OversizedPayload { .. } | Error::TooManyEntries { .. } | Error::Malformed(..) => true,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => fmt::Display::fmt(e, f),
+            Error::OversizedPayload { ref command, size, max } => write!(f,
+                "{} payload of {} bytes exceeds the maximum of {}", command, size, max),
+            Error::TooManyEntries { ref command, count, max } => write!(f,
+                "{} message with {} entries exceeds the maximum of {}", command, count, max),
+            Error::Malformed(ref e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Malformed(ref e) => Some(e),
+            Error::OversizedPayload { .. } | Error::TooManyEntries { .. } => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(..) => "error reading message",
+            Error::OversizedPayload { .. } => "oversized message payload",
+            Error::TooManyEntries { .. } => "too many entries in message",
+            Error::Malformed(..) => "malformed message",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        match e {
+            encode::Error::Io(e) => Error::Io(e),
+            e => Error::Malformed(e),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<Error> for encode::Error {
+    fn from(e: Error) -> encode::Error {
+        match e {
+            Error::Io(e) => encode::Error::Io(e),
+            Error::OversizedPayload { size, max, .. } => encode::Error::OversizedVectorAllocation {
+                requested: size,
+                max,
+            },
+            Error::TooManyEntries { .. } => encode::Error::ParseFailed("too many entries in message"),
+            // The payload was read completely, so running out of it is no
+            // reason to wait for more data
+            Error::Malformed(encode::Error::Io(..)) => encode::Error::ParseFailed("message payload too short"),
+            Error::Malformed(e) => e,
+        }
+    }
+}
+
+/// Size of a list of `count` entries of `entry_size` bytes each, when
+/// `count` fits a 3-byte `VarInt`
+fn list_size(count: u64, entry_size: usize) -> usize {
+    3 + count as usize * entry_size
+}
+
+/// The maximum payload size of messages with `command`, following the
+/// limits Bitcoin Core enforces. Messages without a tighter limit are
+/// allowed up to `MAX_VEC_SIZE`.
+pub fn max_payload_size(command: &str) -> usize {
+    match command {
+        "verack" | "sendheaders" | "getaddr" | "mempool" | "filterclear" | "sendaddrv2"
+        | "wtxidrelay" => 0,
+        "ping" | "pong" | "feefilter" => 8,
+        "sendcmpct" => 9,
+        // version, services, timestamp, two addresses, nonce, user agent,
+        // start height and relay flag
+        "version" => 4 + 8 + 8 + 26 + 26 + 8 + 3 + MAX_SUBVERSION_LENGTH + 4 + 1,
+        "getblocks" | "getheaders" => 4 + 1 + MAX_LOCATOR_SIZE as usize * 32 + 32,
+        "inv" | "getdata" | "notfound" => list_size(MAX_INV_SIZE, 36),
+        "headers" => list_size(MAX_HEADERS_RESULTS, 81),
+        "addr" => list_size(MAX_ADDR_TO_SEND, 30),
+        // time, services, network id, address and port
+        "addrv2" => list_size(MAX_ADDR_TO_SEND, 4 + 9 + 1 + 3 + MAX_ADDRV2_SIZE + 2),
+        "filterload" => 3 + MAX_BLOOM_FILTER_SIZE + 4 + 4 + 1,
+        "filteradd" => 3 + MAX_SCRIPT_ELEMENT_SIZE,
+        "getcfilters" | "getcfheaders" => 1 + 4 + 32,
+        "getcfcheckpt" => 1 + 32,
+        "cfheaders" => 1 + 32 + 32 + list_size(MAX_HEADERS_RESULTS, 32),
+        _ => MAX_VEC_SIZE,
+    }
+}
+
+/// The maximum number of entries in messages with `command`, for commands
+/// whose payload is a list
+pub fn max_entries(command: &str) -> Option<u64> {
+    match command {
+        "inv" | "getdata" | "notfound" => Some(MAX_INV_SIZE),
+        "headers" => Some(MAX_HEADERS_RESULTS),
+        "addr" | "addrv2" => Some(MAX_ADDR_TO_SEND),
+        _ => None,
+    }
+}
 
 /// Serializer for command string
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -80,16 +245,19 @@ impl Encodable for CommandString {
 }
 
 impl Decodable for CommandString {
+    /// Commands must be printable ASCII padded with zero bytes, as Bitcoin
+    /// Core requires, so that any command decoded encodes to the same bytes
     #[inline]
     fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
         let rawbytes: [u8; 12] = Decodable::consensus_decode(d)?;
-        let rv = iter::FromIterator::from_iter(rawbytes.iter().filter_map(|&u| {
-            if u > 0 {
-                Some(u as char)
-            } else {
-                None
-            }
-        }));
+        let len = rawbytes.iter().position(|&u| u == 0).unwrap_or(12);
+        if rawbytes[len..].iter().any(|&u| u != 0) {
+            return Err(encode::Error::ParseFailed("command string not zero padded"));
+        }
+        if rawbytes[..len].iter().any(|&u| u > 0x7e || (u as char).is_control()) {
+            return Err(encode::Error::ParseFailed("command string not printable ASCII"));
+        }
+        let rv = iter::FromIterator::from_iter(rawbytes[..len].iter().map(|&u| u as char));
         Ok(CommandString(rv))
     }
 }
@@ -169,11 +337,40 @@ pub enum NetworkMessage {
     /// The filterload message
     ///
     FilterLoad(message_bloom_filter::FilterLoadMessage),
+    /// BIP37 filteradd
+    FilterAdd(message_bloom_filter::FilterAddMessage),
+    /// BIP37 filterclear
+    FilterClear,
+    /// BIP37 merkleblock
+    MerkleBlock(merkleblock::MerkleBlock),
+    /// BIP152 sendcmpct
+    SendCmpct(message_compact_blocks::SendCmpct),
+    /// BIP152 cmpctblock
+    CmpctBlock(message_compact_blocks::HeaderAndShortIds),
+    /// BIP152 getblocktxn
+    GetBlockTxn(message_compact_blocks::BlockTransactionsRequest),
+    /// BIP152 blocktxn
+    BlockTxn(message_compact_blocks::BlockTransactions),
+    /// BIP155 addrv2
+    AddrV2(Vec<AddrV2Message>),
+    /// BIP155 sendaddrv2
+    SendAddrV2,
+    /// BIP133 feefilter
+    FeeFilter(FeeRate),
+    /// BIP339 wtxidrelay
+    WtxidRelay,
+    /// Any message with a command not known to this library, kept as is
+    Unknown {
+        /// The command of the message
+        command: CommandString,
+        /// The raw payload of the message
+        payload: Vec<u8>,
+    },
 }
 
 impl NetworkMessage {
     /// Return the message command. This is useful for debug outputs.
-    pub fn cmd(&self) -> &'static str {
+    pub fn cmd(&self) -> &str {
         match *self {
             NetworkMessage::Version(_) => "version",
             NetworkMessage::Verack => "verack",
@@ -195,23 +392,38 @@ impl NetworkMessage {
             NetworkMessage::CFilter(_) => "cfilter",
             NetworkMessage::GetCFHeaders(_) => "getcfheaders",
             NetworkMessage::CFHeaders(_) => "cfheaders",
-            NetworkMessage::GetCFCheckpt(_) => "getcfckpt",
+            NetworkMessage::GetCFCheckpt(_) => "getcfcheckpt",
             NetworkMessage::CFCheckpt(_) => "cfcheckpt",
             NetworkMessage::Alert(_) => "alert",
             NetworkMessage::Reject(_) => "reject",
             NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
+            NetworkMessage::SendCmpct(_) => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
+            NetworkMessage::AddrV2(_) => "addrv2",
+            NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::FeeFilter(_) => "feefilter",
+            NetworkMessage::WtxidRelay => "wtxidrelay",
+            NetworkMessage::Unknown { ref command, .. } => command.as_ref(),
         }
     }
 
     /// Return the CommandString for the message command.
     pub fn command(&self) -> CommandString {
-        self.cmd().into()
+        match *self {
+            NetworkMessage::Unknown { ref command, .. } => command.clone(),
+            _ => self.cmd().to_owned().into(),
+        }
     }
 }
 
 impl RawNetworkMessage {
     /// Return the message command. This is useful for debug outputs.
-    pub fn cmd(&self) -> &'static str {
+    pub fn cmd(&self) -> &str {
         self.payload.cmd()
     }
 
@@ -221,6 +433,130 @@ impl RawNetworkMessage {
     }
 }
 
+/// The header of a network message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MessageHeader {
+    /// Magic bytes to identify the network the message is meant for
+    pub magic: u32,
+    /// The command of the message
+    pub command: CommandString,
+    /// The size of the payload
+    pub length: u32,
+    /// The first 4 bytes of the double SHA256 of the payload
+    pub checksum: [u8; 4],
+}
+
+impl_consensus_encoding!(MessageHeader, magic, command, length, checksum);
+
+impl MessageHeader {
+    /// Size of an encoded header
+    pub const SIZE: usize = 24;
+
+    /// Check the payload size against the maximum for the command, see
+    /// `max_payload_size`
+    pub fn check_payload_size(&self) -> Result<(), Error> {
+        let max = max_payload_size(self.command.as_ref());
+        if self.length as usize > max {
+            return Err(Error::OversizedPayload {
+                command: self.command.clone(),
+                size: self.length as usize,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A network message whose payload is kept as raw bytes, and only decoded
+/// on demand. This spares relays decoding the messages they just forward.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UndecodedMessage {
+    /// The header of the message
+    pub header: MessageHeader,
+    /// The encoded payload of the message
+    pub payload: Vec<u8>,
+}
+
+impl UndecodedMessage {
+    /// Construct a message with `payload`, filling in its length and checksum
+    pub fn new(magic: u32, command: CommandString, payload: Vec<u8>) -> UndecodedMessage {
+        UndecodedMessage {
+            header: MessageHeader {
+                magic,
+                command,
+                length: payload.len() as u32,
+                checksum: payload_checksum(&payload),
+            },
+            payload,
+        }
+    }
+
+    /// Read a message, checking the payload size announced in its header
+    /// before reading the payload and verifying its checksum after
+    pub fn decode_checked<D: io::Read>(mut d: D) -> Result<UndecodedMessage, Error> {
+        let header = MessageHeader::consensus_decode(&mut d)?;
+        header.check_payload_size()?;
+        let mut payload = vec![0u8; header.length as usize];
+        d.read_slice(&mut payload)?;
+
+        let checksum = payload_checksum(&payload);
+        if checksum != header.checksum {
+            return Err(Error::Malformed(encode::Error::InvalidChecksum {
+                expected: checksum,
+                actual: header.checksum,
+            }));
+        }
+        Ok(UndecodedMessage { header, payload })
+    }
+
+    /// Decode the payload, checking the number of entries of list payloads
+    /// against the maximum for the command, see `max_entries`
+    pub fn decode(&self) -> Result<RawNetworkMessage, Error> {
+        let command = &self.header.command;
+        if let Some(max) = max_entries(command.as_ref()) {
+            let count = VarInt::consensus_decode(&self.payload[..]).map_err(Error::Malformed)?.0;
+            if count > max {
+                return Err(Error::TooManyEntries { command: command.clone(), count, max });
+            }
+        }
+        Ok(RawNetworkMessage {
+            magic: self.header.magic,
+            payload: decode_payload(command, &self.payload).map_err(Error::Malformed)?,
+        })
+    }
+}
+
+impl Encodable for UndecodedMessage {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let len = self.header.consensus_encode(&mut s)?;
+        s.emit_slice(&self.payload)?;
+        Ok(len + self.payload.len())
+    }
+}
+
+impl Decodable for UndecodedMessage {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        UndecodedMessage::decode_checked(d).map_err(encode::Error::from)
+    }
+}
+
+impl<'a> From<&'a RawNetworkMessage> for UndecodedMessage {
+    fn from(message: &'a RawNetworkMessage) -> UndecodedMessage {
+        let mut data = serialize(message);
+        let payload = data.split_off(MessageHeader::SIZE);
+        UndecodedMessage {
+            header: encode::deserialize(&data).expect("header of a serialized message"),
+            payload,
+        }
+    }
+}
+
+/// The first 4 bytes of the double SHA256 of `payload`
+fn payload_checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(payload);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
 struct HeaderSerializationWrapper<'a>(&'a Vec<block::BlockHeader>);
 
 impl<'a> Encodable for HeaderSerializationWrapper<'a> {
@@ -265,8 +601,21 @@ impl Encodable for RawNetworkMessage {
             NetworkMessage::Verack
             | NetworkMessage::SendHeaders
             | NetworkMessage::MemPool
-            | NetworkMessage::GetAddr => vec![],
+            | NetworkMessage::GetAddr
+            | NetworkMessage::SendAddrV2
+            | NetworkMessage::WtxidRelay
+            | NetworkMessage::FilterClear => vec![],
             NetworkMessage::FilterLoad(ref dat) => serialize(dat),
+            NetworkMessage::FilterAdd(ref dat) => serialize(dat),
+            NetworkMessage::MerkleBlock(ref dat) => serialize(dat),
+            NetworkMessage::SendCmpct(ref dat) => serialize(dat),
+            NetworkMessage::CmpctBlock(ref dat) => serialize(dat),
+            NetworkMessage::GetBlockTxn(ref dat) => serialize(dat),
+            NetworkMessage::BlockTxn(ref dat) => serialize(dat),
+            NetworkMessage::AddrV2(ref dat) => serialize(dat),
+            // Fee filters are signed on the wire, see `decode_fee_filter`
+            NetworkMessage::FeeFilter(ref dat) => serialize(&(dat.as_sat_per_kvb() as i64)),
+            NetworkMessage::Unknown { ref payload, .. } => payload.clone(),
         })
             .consensus_encode(&mut s)?;
         Ok(len)
@@ -301,63 +650,102 @@ impl Decodable for HeaderDeserializationWrapper {
     }
 }
 
+/// Decode the payload of a `feefilter` message, which Bitcoin Core sends as a
+/// signed 64-bit amount of satoshis per 1000 virtual bytes. Negative values
+/// are out of Bitcoin Core's money range, and rejected.
+fn decode_fee_filter<D: io::Read>(d: D) -> Result<FeeRate, encode::Error> {
+    let sat_per_kvb = i64::consensus_decode(d)?;
+    if sat_per_kvb < 0 {
+        return Err(encode::Error::ParseFailed("negative fee filter"));
+    }
+    Ok(FeeRate::from_sat_per_kvb(sat_per_kvb as u64))
+}
+
+impl RawNetworkMessage {
+    /// Decode a message, rejecting payloads beyond the limits for their
+    /// command (see `max_payload_size` and `max_entries`) before anything
+    /// of them is read. Unlike `consensus_decode`, the error tells whether
+    /// the peer is to be banned.
+    pub fn decode_checked<D: io::Read>(d: D) -> Result<RawNetworkMessage, Error> {
+        UndecodedMessage::decode_checked(d)?.decode()
+    }
+}
+
 impl Decodable for RawNetworkMessage {
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
-        let magic = Decodable::consensus_decode(&mut d)?;
-        let cmd = CommandString::consensus_decode(&mut d)?.0;
-        let raw_payload = CheckedData::consensus_decode(&mut d)?.0;
-
-        let mut mem_d = Cursor::new(raw_payload);
-        let payload = match &cmd[..] {
-            "version" => NetworkMessage::Version(Decodable::consensus_decode(&mut mem_d)?),
-            "verack" => NetworkMessage::Verack,
-            "addr" => NetworkMessage::Addr(Decodable::consensus_decode(&mut mem_d)?),
-            "inv" => NetworkMessage::Inv(Decodable::consensus_decode(&mut mem_d)?),
-            "getdata" => NetworkMessage::GetData(Decodable::consensus_decode(&mut mem_d)?),
-            "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode(&mut mem_d)?),
-            "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut mem_d)?),
-            "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut mem_d)?),
-            "mempool" => NetworkMessage::MemPool,
-            "block" => NetworkMessage::Block(Decodable::consensus_decode(&mut mem_d)?),
-            "headers" => NetworkMessage::Headers(
-                HeaderDeserializationWrapper::consensus_decode(&mut mem_d)?.0,
-            ),
-            "sendheaders" => NetworkMessage::SendHeaders,
-            "getaddr" => NetworkMessage::GetAddr,
-            "ping" => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
-            "pong" => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
-            "tx" => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
-            "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfheaders" => {
-                NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut mem_d)?)
-            }
-            "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
-            "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
-            "reject" => NetworkMessage::Reject(Decodable::consensus_decode(&mut mem_d)?),
-            "alert" => NetworkMessage::Alert(Decodable::consensus_decode(&mut mem_d)?),
-            //自定义filterload
-            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut mem_d)?),
-            _ => return Err(encode::Error::UnrecognizedNetworkCommand(cmd.into_owned())),
-        };
-        Ok(RawNetworkMessage {
-            magic: magic,
-            payload: payload,
-        })
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        RawNetworkMessage::decode_checked(d).map_err(encode::Error::from)
     }
 }
 
+/// Decode the payload of a message with `command`
+fn decode_payload(command: &CommandString, payload: &[u8]) -> Result<NetworkMessage, encode::Error> {
+    let mut mem_d = payload;
+    Ok(match command.as_ref() {
+        "version" => NetworkMessage::Version(Decodable::consensus_decode(&mut mem_d)?),
+        "verack" => NetworkMessage::Verack,
+        "addr" => NetworkMessage::Addr(Decodable::consensus_decode(&mut mem_d)?),
+        "inv" => NetworkMessage::Inv(Decodable::consensus_decode(&mut mem_d)?),
+        "getdata" => NetworkMessage::GetData(Decodable::consensus_decode(&mut mem_d)?),
+        "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode(&mut mem_d)?),
+        "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut mem_d)?),
+        "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut mem_d)?),
+        "mempool" => NetworkMessage::MemPool,
+        "block" => NetworkMessage::Block(Decodable::consensus_decode(&mut mem_d)?),
+        "headers" => NetworkMessage::Headers(
+            HeaderDeserializationWrapper::consensus_decode(&mut mem_d)?.0,
+        ),
+        "sendheaders" => NetworkMessage::SendHeaders,
+        "getaddr" => NetworkMessage::GetAddr,
+        "ping" => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
+        "pong" => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
+        "tx" => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
+        "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
+        "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
+        "getcfheaders" => {
+            NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut mem_d)?)
+        }
+        "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut mem_d)?),
+        "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
+        "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
+        "reject" => NetworkMessage::Reject(Decodable::consensus_decode(&mut mem_d)?),
+        "alert" => NetworkMessage::Alert(Decodable::consensus_decode(&mut mem_d)?),
+        //自定义filterload
+        "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut mem_d)?),
+        "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode(&mut mem_d)?),
+        "filterclear" => NetworkMessage::FilterClear,
+        "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode(&mut mem_d)?),
+        "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode(&mut mem_d)?),
+        "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode(&mut mem_d)?),
+        "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode(&mut mem_d)?),
+        "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode(&mut mem_d)?),
+        "addrv2" => NetworkMessage::AddrV2(Decodable::consensus_decode(&mut mem_d)?),
+        "sendaddrv2" => NetworkMessage::SendAddrV2,
+        "feefilter" => NetworkMessage::FeeFilter(decode_fee_filter(&mut mem_d)?),
+        "wtxidrelay" => NetworkMessage::WtxidRelay,
+        _ => NetworkMessage::Unknown {
+            command: command.clone(),
+            payload: payload.to_vec(),
+        },
+    })
+}
+
 #[cfg(test)]
 mod test {
-    use super::{CommandString, NetworkMessage, RawNetworkMessage};
-    use consensus::encode::{deserialize, deserialize_partial, serialize, Encodable};
+    use super::{max_entries, max_payload_size, CommandString, Error, MessageHeader, NetworkMessage};
+    use super::{RawNetworkMessage, UndecodedMessage};
+    use consensus::encode::{self, deserialize, deserialize_partial, serialize, Encodable};
+    use network::address::{AddrV2, AddrV2Message};
+    use std::net::Ipv4Addr;
     use network::constants::ServiceFlags;
     use std::io;
     use network::message_blockdata::Inventory;
     use network::message_blockdata::InvType;
-    use hashes::sha256d;
+    use network::message_filter::GetCFCheckpt;
+    use hash_types::BlockHash;
+    use hashes::{sha256d, Hash};
     use hashes::hex::FromHex;
+    use hex::decode as hex_decode;
+    use util::amount::FeeRate;
 //    use hex::decode as hex_decode;
 //    use hex::{FromHex, ToHex};
 //    use hashes::Hash;
@@ -389,6 +777,41 @@ mod test {
         let short_cs: Result<CommandString, _> =
             deserialize(&[0x41u8, 0x6e, 0x64, 0x72, 0x65, 0x77, 0, 0, 0, 0, 0]);
         assert!(short_cs.is_err());
+
+        // Only zero padding and printable ASCII, so that decoding then encoding is lossless
+        let embedded_zero: Result<CommandString, _> =
+            deserialize(&[0x41u8, 0x6e, 0x64, 0, 0x72, 0x65, 0x77, 0, 0, 0, 0, 0]);
+        assert!(embedded_zero.is_err());
+        let non_ascii: Result<CommandString, _> =
+            deserialize(&[0x41u8, 0x6e, 0x64, 0xe9, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(non_ascii.is_err());
+        let full: CommandString = deserialize(b"abcdefghijkl").unwrap();
+        assert_eq!(serialize(&full), b"abcdefghijkl");
+    }
+
+    #[test]
+    fn unknown_message_test() {
+        let payload = vec![0xde, 0xad, 0xbe, 0xef, 0x00];
+        let mut data = vec![0xf9, 0xbe, 0xb4, 0xd9];
+        data.extend_from_slice(b"futurecmd\0\0\0");
+        data.extend_from_slice(&[payload.len() as u8, 0, 0, 0]);
+        data.extend_from_slice(&sha256d::Hash::hash(&payload)[0..4]);
+        data.extend_from_slice(&payload);
+        // A known message follows, as it would on a stream
+        data.extend(serialize(&RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::Ping(7) }));
+
+        let (msg, consumed) = deserialize_partial::<RawNetworkMessage>(&data).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Unknown { command: "futurecmd".into(), payload });
+        assert_eq!(msg.cmd(), "futurecmd");
+        assert_eq!(serialize(&msg), &data[..consumed]);
+
+        let msg: RawNetworkMessage = deserialize(&data[consumed..]).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(7));
+
+        // Commands must be printable ASCII
+        assert!(deserialize::<CommandString>(b"futurecmd\0\0\0").is_ok());
+        assert!(deserialize::<CommandString>(b"future\x1fcmd\0\0").is_err());
+        assert!(deserialize::<CommandString>(b"future\x7fcmd\0\0").is_err());
     }
 
     #[test]
@@ -574,4 +997,152 @@ mod test {
         }
         assert_eq!(&ser, &data)
     }
+
+    #[test]
+    fn feefilter_wtxidrelay_test() {
+        // feefilter of 1000 sat/kvB, Bitcoin Core's default minimum relay fee
+        let feefilter = hex_decode("f9beb4d966656566696c746572000000080000\
+                                    00e80fd19fe803000000000000").unwrap();
+        let msg: RawNetworkMessage = deserialize(&feefilter).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::FeeFilter(FeeRate::from_sat_per_kvb(1000)));
+        assert_eq!(msg.cmd(), "feefilter");
+        assert_eq!(serialize(&msg), feefilter);
+
+        let negative = hex_decode("f9beb4d966656566696c74657200000008000000752adad0ffffffffffffffff").unwrap();
+        match deserialize::<RawNetworkMessage>(&negative) {
+            Err(encode::Error::ParseFailed(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let wtxidrelay = hex_decode("f9beb4d9777478696472656c61790000000000005df6e0e2").unwrap();
+        let msg: RawNetworkMessage = deserialize(&wtxidrelay).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::WtxidRelay);
+        assert_eq!(serialize(&msg), wtxidrelay);
+    }
+
+    #[test]
+    fn payload_limits_test() {
+        let ping = serialize(&RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::Ping(7) });
+
+        // Oversized payloads are refused from the header alone
+        let mut oversized = ping.clone();
+        oversized[16] = 9;
+        match RawNetworkMessage::decode_checked(&oversized[..24]) {
+            Err(ref e @ Error::OversizedPayload { size: 9, max: 8, .. }) => assert!(e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match deserialize_partial::<RawNetworkMessage>(&oversized[..24]) {
+            Err(encode::Error::OversizedVectorAllocation { requested: 9, max: 8 }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Incomplete messages are not the peer's fault
+        match RawNetworkMessage::decode_checked(&ping[..30]) {
+            Err(ref e @ Error::Io(..)) => assert!(!e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut bad_checksum = ping.clone();
+        bad_checksum[20] ^= 1;
+        match RawNetworkMessage::decode_checked(&bad_checksum[..]) {
+            Err(ref e @ Error::Malformed(encode::Error::InvalidChecksum { .. })) => assert!(!e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // A payload too short for its command is malformed, not incomplete
+        let mut short = vec![0xf9, 0xbe, 0xb4, 0xd9];
+        short.extend_from_slice(b"ping\0\0\0\0\0\0\0\0");
+        short.extend_from_slice(&[4, 0, 0, 0]);
+        short.extend_from_slice(&sha256d::Hash::hash(&[7, 0, 0, 0])[0..4]);
+        short.extend_from_slice(&[7, 0, 0, 0]);
+        match RawNetworkMessage::decode_checked(&short[..]) {
+            Err(ref e @ Error::Malformed(encode::Error::Io(..))) => assert!(e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match deserialize::<RawNetworkMessage>(&short) {
+            Err(encode::Error::ParseFailed(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // addrv2 entries are small enough for the entry count to matter
+        let addr = AddrV2Message {
+            time: 0,
+            services: ServiceFlags::NETWORK,
+            addr: AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            port: 8333,
+        };
+        let addrv2 = |count| serialize(&RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::AddrV2(vec![addr.clone(); count]),
+        });
+        assert!(RawNetworkMessage::decode_checked(&addrv2(1000)[..]).is_ok());
+        match RawNetworkMessage::decode_checked(&addrv2(1001)[..]) {
+            Err(ref e @ Error::TooManyEntries { count: 1001, max: 1000, .. }) => assert!(e.should_ban()),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(max_payload_size("inv"), 1_800_003);
+        assert_eq!(max_payload_size("headers"), 162_003);
+        assert_eq!(max_payload_size("block"), 4_000_000);
+        assert_eq!(max_payload_size("futurecmd"), 4_000_000);
+        assert_eq!(max_payload_size("getcfcheckpt"), 33);
+        assert_eq!(max_entries("getdata"), Some(50_000));
+        assert_eq!(max_entries("tx"), None);
+
+        // The BIP157 command name, which is longer than most
+        let getcfcheckpt = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::GetCFCheckpt(GetCFCheckpt { filter_type: 0, stop_hash: BlockHash::hash(&[1]) }),
+        };
+        let data = serialize(&getcfcheckpt);
+        assert_eq!(&data[4..16], b"getcfcheckpt");
+        assert_eq!(RawNetworkMessage::decode_checked(&data[..]).unwrap().payload, getcfcheckpt.payload);
+
+        // Unknown inventory types are kept rather than failing the message
+        let mut payload = vec![1, 3, 0, 0, 0x40];
+        payload.extend_from_slice(&[0; 32]);
+        let inv = serialize(&UndecodedMessage::new(0xd9b4bef9, "inv".into(), payload));
+        assert_eq!(
+            RawNetworkMessage::decode_checked(&inv[..]).unwrap().payload,
+            NetworkMessage::Inv(vec![Inventory { inv_type: InvType::Unknown(0x40000003), hash: Default::default() }]),
+        );
+    }
+
+    #[test]
+    fn undecoded_message_test() {
+        let verack = [
+            0xf9, 0xbe, 0xb4, 0xd9, 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2,
+        ];
+        let header: MessageHeader = deserialize(&verack).unwrap();
+        assert_eq!(header, MessageHeader {
+            magic: 0xd9b4bef9,
+            command: "verack".into(),
+            length: 0,
+            checksum: [0x5d, 0xf6, 0xe0, 0xe2],
+        });
+
+        let ping = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::Ping(100) };
+        let data = serialize(&ping);
+        let undecoded: UndecodedMessage = deserialize(&data).unwrap();
+        assert_eq!(undecoded.header.command.as_ref(), "ping");
+        assert_eq!(undecoded.payload, vec![0x64, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(undecoded, UndecodedMessage::from(&ping));
+        assert_eq!(undecoded, UndecodedMessage::new(0xd9b4bef9, "ping".into(), vec![0x64, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(serialize(&undecoded), data);
+        assert_eq!(undecoded.decode().unwrap().payload, NetworkMessage::Ping(100));
+
+        // The payload is checked without decoding it
+        let mut bad_checksum = data.clone();
+        bad_checksum[24] = 0x65;
+        match UndecodedMessage::decode_checked(&bad_checksum[..]) {
+            Err(Error::Malformed(encode::Error::InvalidChecksum { .. })) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let truncated = UndecodedMessage::new(0xd9b4bef9, "ping".into(), vec![0x64]);
+        assert!(UndecodedMessage::decode_checked(&serialize(&truncated)[..]).is_ok());
+        match truncated.decode() {
+            Err(Error::Malformed(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
 }
@@ -21,6 +21,8 @@
 use network::constants;
 use consensus::encode::{self, Decodable, Encodable};
 use hashes::sha256d;
+use blockdata::transaction::Transaction;
+use hash_types::{BlockHash, Txid, Wtxid};
 
 use std::io;
 
@@ -40,6 +42,12 @@ pub enum InvType {
     /// 新添加类型
     /// filtered block
     FilteredBlock,
+    /// BIP152 compact block
+    CompactBlock,
+    /// BIP339 transaction, referenced by its wtxid
+    WTx,
+    /// A type this library does not know, kept so that it can be encoded back
+    Unknown(u32),
 }
 
 // Some simple messages
@@ -52,9 +60,9 @@ pub struct GetBlocksMessage {
     /// Locator hashes --- ordered newest to oldest. The remote peer will
     /// reply with its longest known chain, starting from a locator hash
     /// if possible and block 1 otherwise.
-    pub locator_hashes: Vec<sha256d::Hash>,
+    pub locator_hashes: Vec<BlockHash>,
     /// References the block to stop at, or zero to just fetch the maximum 500 blocks
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
 }
 
 /// The `getheaders` message
@@ -65,9 +73,9 @@ pub struct GetHeadersMessage {
     /// Locator hashes --- ordered newest to oldest. The remote peer will
     /// reply with its longest known chain, starting from a locator hash
     /// if possible and block 1 otherwise.
-    pub locator_hashes: Vec<sha256d::Hash>,
+    pub locator_hashes: Vec<BlockHash>,
     /// References the header to stop at, or zero to just fetch the maximum 2000 headers
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
 }
 
 /// An inventory object --- a reference to a Bitcoin object
@@ -75,7 +83,8 @@ pub struct GetHeadersMessage {
 pub struct Inventory {
     /// The type of object that is referenced
     pub inv_type: InvType,
-    /// The object's hash
+    /// The object's hash. Its meaning depends on `inv_type`, so prefer the
+    /// typed constructors and accessors below to reading it directly.
     pub hash: sha256d::Hash,
 }
 
@@ -85,9 +94,95 @@ impl ::std::hash::Hash for Inventory {
     }
 }
 
+impl Inventory {
+    /// Construct an inventory entry referencing a transaction
+    pub fn transaction(txid: Txid) -> Inventory {
+        Inventory {
+            inv_type: InvType::Transaction,
+            hash: txid.as_hash(),
+        }
+    }
+
+    /// Construct an inventory entry referencing a block
+    pub fn block(block_hash: BlockHash) -> Inventory {
+        Inventory {
+            inv_type: InvType::Block,
+            hash: block_hash.as_hash(),
+        }
+    }
+
+    /// Construct an inventory entry requesting a transaction together with
+    /// its witness data. Note that the entry is still keyed by txid.
+    pub fn witness_transaction(txid: Txid) -> Inventory {
+        Inventory {
+            inv_type: InvType::WitnessTransaction,
+            hash: txid.as_hash(),
+        }
+    }
+
+    /// Construct an inventory entry referencing a transaction by its wtxid.
+    /// Only peers which negotiated wtxid relay (BIP339) understand these.
+    pub fn wtx(wtxid: Wtxid) -> Inventory {
+        Inventory {
+            inv_type: InvType::WTx,
+            hash: wtxid.as_hash(),
+        }
+    }
+
+    /// Construct the inventory entry announcing `tx` to a peer: by wtxid if
+    /// wtxid relay was negotiated with it, by txid otherwise
+    pub fn announce_transaction(tx: &Transaction, wtxid_relay: bool) -> Inventory {
+        if wtxid_relay {
+            Inventory::wtx(tx.wtxid())
+        } else {
+            Inventory::transaction(tx.txid())
+        }
+    }
+
+    /// Construct an inventory entry requesting a block together with the
+    /// witness data of its transactions
+    pub fn witness_block(block_hash: BlockHash) -> Inventory {
+        Inventory {
+            inv_type: InvType::WitnessBlock,
+            hash: block_hash.as_hash(),
+        }
+    }
+
+    /// Whether the peer is asked to serialize the referenced object with witness data
+    pub fn is_witness(&self) -> bool {
+        let inv_type = self.inv_type;
+        inv_type == InvType::WitnessTransaction || inv_type == InvType::WitnessBlock || inv_type == InvType::WTx
+    }
+
+    /// The referenced transaction's txid, if this entry references a transaction
+    pub fn txid(&self) -> Option<Txid> {
+        match self.inv_type {
+            InvType::Transaction | InvType::WitnessTransaction => Some(Txid::from_hash(self.hash)),
+            _ => None,
+        }
+    }
+
+    /// The referenced transaction's wtxid, if this entry references a
+    /// transaction by wtxid
+    pub fn wtxid(&self) -> Option<Wtxid> {
+        match self.inv_type {
+            InvType::WTx => Some(Wtxid::from_hash(self.hash)),
+            _ => None,
+        }
+    }
+
+    /// The referenced block's hash, if this entry references a block
+    pub fn block_hash(&self) -> Option<BlockHash> {
+        match self.inv_type {
+            InvType::Block | InvType::WitnessBlock | InvType::FilteredBlock | InvType::CompactBlock => Some(BlockHash::from_hash(self.hash)),
+            _ => None,
+        }
+    }
+}
+
 impl GetBlocksMessage {
     /// Construct a new `getblocks` message
-    pub fn new(locator_hashes: Vec<sha256d::Hash>, stop_hash: sha256d::Hash) -> GetBlocksMessage {
+    pub fn new(locator_hashes: Vec<BlockHash>, stop_hash: BlockHash) -> GetBlocksMessage {
         GetBlocksMessage {
             version: constants::PROTOCOL_VERSION,
             locator_hashes: locator_hashes.clone(),
@@ -100,7 +195,7 @@ impl_consensus_encoding!(GetBlocksMessage, version, locator_hashes, stop_hash);
 
 impl GetHeadersMessage {
     /// Construct a new `getheaders` message
-    pub fn new(locator_hashes: Vec<sha256d::Hash>, stop_hash: sha256d::Hash) -> GetHeadersMessage {
+    pub fn new(locator_hashes: Vec<BlockHash>, stop_hash: BlockHash) -> GetHeadersMessage {
         GetHeadersMessage {
             version: constants::PROTOCOL_VERSION,
             locator_hashes: locator_hashes,
@@ -123,7 +218,10 @@ impl Encodable for Inventory {
             InvType::Block => 2,
             InvType::WitnessBlock => 0x40000002,
             InvType::WitnessTransaction => 0x40000001,
-            InvType::FilteredBlock => 3
+            InvType::FilteredBlock => 3,
+            InvType::CompactBlock => 4,
+            InvType::WTx => 5,
+            InvType::Unknown(t) => t,
         }.consensus_encode(&mut s)?;
         Ok(inv_len + self.hash.consensus_encode(&mut s)?)
     }
@@ -140,8 +238,11 @@ impl Decodable for Inventory {
                 2 => InvType::Block,
                 // add new type
                 3 => InvType::FilteredBlock,
-                // TODO do not fail here
-                _ => { panic!("bad inventory type field") }
+                4 => InvType::CompactBlock,
+                5 => InvType::WTx,
+                0x40000001 => InvType::WitnessTransaction,
+                0x40000002 => InvType::WitnessBlock,
+                t => InvType::Unknown(t),
             },
             hash: Decodable::consensus_decode(d)?,
         })
@@ -150,7 +251,11 @@ impl Decodable for Inventory {
 
 #[cfg(test)]
 mod tests {
-    use super::{GetHeadersMessage, GetBlocksMessage};
+    use super::{GetHeadersMessage, GetBlocksMessage, Inventory, InvType};
+
+    use hashes::hex::FromHex;
+    use blockdata::transaction::Transaction;
+    use hash_types::{BlockHash, Txid, Wtxid};
 
     use hex::decode as hex_decode;
 
@@ -192,5 +297,51 @@ mod tests {
         assert_eq!(real_decode.stop_hash, Default::default());
         assert_eq!(serialize(&real_decode), from_sat);
     }
-}
 
+    #[test]
+    fn witness_inventory_test() {
+        let txid = Txid::from_hex("9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec").unwrap();
+        let inv = Inventory::witness_transaction(txid);
+        let bytes = serialize(&inv);
+        assert_eq!(&bytes[..4], &[0x01, 0x00, 0x00, 0x40]);
+
+        let decoded: Inventory = deserialize(&bytes).unwrap();
+        assert_eq!(decoded.inv_type, InvType::WitnessTransaction);
+        assert!(decoded.is_witness());
+        assert_eq!(decoded.txid(), Some(txid));
+        assert_eq!(decoded.block_hash(), None);
+
+        let block_hash = BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap();
+        let decoded: Inventory = deserialize(&serialize(&Inventory::witness_block(block_hash))).unwrap();
+        assert_eq!(decoded.inv_type, InvType::WitnessBlock);
+        assert_eq!(decoded.block_hash(), Some(block_hash));
+        assert!(!Inventory::block(block_hash).is_witness());
+    }
+
+    #[test]
+    fn wtx_inventory_test() {
+        // inv entry for a wtxid as sent by Bitcoin Core to a wtxidrelay peer
+        let from_sat = hex_decode("05000000ecc6a112f924cad17d44fe3dcc352479c617bcb74c0cc4eeca48e7b062aa5296").unwrap();
+        let wtxid = Wtxid::from_hex("9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec").unwrap();
+
+        let decoded: Inventory = deserialize(&from_sat).unwrap();
+        assert_eq!(decoded.inv_type, InvType::WTx);
+        assert_eq!(decoded, Inventory::wtx(wtxid));
+        assert_eq!(decoded.wtxid(), Some(wtxid));
+        assert_eq!(decoded.txid(), None);
+        assert!(decoded.is_witness());
+        assert_eq!(serialize(&decoded), from_sat);
+
+        let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        assert_eq!(Inventory::announce_transaction(&tx, true), Inventory::wtx(tx.wtxid()));
+        assert_eq!(Inventory::announce_transaction(&tx, false), Inventory::transaction(tx.txid()));
+
+        // MSG_FILTERED_WITNESS_BLOCK is not supported, but is kept as is
+        let filtered_witness = hex_decode("03000040ecc6a112f924cad17d44fe3dcc352479c617bcb74c0cc4eeca48e7b062aa5296").unwrap();
+        let decoded: Inventory = deserialize(&filtered_witness).unwrap();
+        assert_eq!(decoded.inv_type, InvType::Unknown(0x40000003));
+        assert_eq!(decoded.block_hash(), None);
+        assert!(!decoded.is_witness());
+        assert_eq!(serialize(&decoded), filtered_witness);
+    }
+}
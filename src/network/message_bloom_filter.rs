@@ -1,6 +1,48 @@
 //! mod for bloom filter message
+
+use std::io;
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// The bits of the `nFlags` field of `filterload` which select how the
+/// filter is updated; the others are ignored
+pub const BLOOM_UPDATE_MASK: u8 = 3;
+
+///How a bloom filter is updated when an output of a transaction matches it (BIP37)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BloomFlags {
+    ///The filter is never updated
+    None,
+    ///The outpoint of every matching output is added to the filter
+    All,
+    ///The outpoint of matching pay-to-pubkey and bare multisig outputs is added to the filter
+    PubkeyOnly,
+}
+
+impl Encodable for BloomFlags {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        match *self {
+            BloomFlags::None => 0u8,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        }.consensus_encode(s)
+    }
+}
+
+impl Decodable for BloomFlags {
+    /// As Bitcoin Core does, bits outside `BLOOM_UPDATE_MASK` are ignored and
+    /// the undefined update mode 3 never updates the filter
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(match u8::consensus_decode(d)? & BLOOM_UPDATE_MASK {
+            1 => BloomFlags::All,
+            2 => BloomFlags::PubkeyOnly,
+            _ => BloomFlags::None,
+        })
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
-///the message filterload
+///the message filterload, usually built from a `util::bloom::BloomFilter`
 pub struct FilterLoadMessage {
     ///The filter itself is simply a bit field of arbitrary byte-aligned size. The maximum size is 36,000 bytes.
     pub  filter: Vec<u8>,
@@ -9,14 +51,23 @@ pub struct FilterLoadMessage {
     ///A random value to add to the seed value in the hash function used by the bloom filter.
     pub n_tweak: u32,
     ///A set of flags that control how matched items are added to the filter.
-    pub n_flags: bool,
+    pub n_flags: BloomFlags,
 }
 
 impl_consensus_encoding!(FilterLoadMessage, filter, n_hash_functions, n_tweak, n_flags);
 
+#[derive(PartialEq, Eq, Clone, Debug)]
+///the message filteradd
+pub struct FilterAddMessage {
+    ///The data element to add to the current filter. The maximum size is 520 bytes.
+    pub data: Vec<u8>,
+}
+
+impl_consensus_encoding!(FilterAddMessage, data);
+
 #[cfg(test)]
 mod test {
-    use network::message_bloom_filter::FilterLoadMessage;
+    use network::message_bloom_filter::{BloomFlags, FilterAddMessage, FilterLoadMessage};
     use consensus::{deserialize, serialize};
     use network::message::{RawNetworkMessage, NetworkMessage};
 
@@ -32,7 +83,7 @@ mod test {
             filter: vec![0xb5, 0x0f],
             n_hash_functions: 11,
             n_tweak: 0,
-            n_flags: false,
+            n_flags: BloomFlags::None,
         };
 
         let raw_filterload = RawNetworkMessage {
@@ -44,4 +95,26 @@ mod test {
         assert!(&raw_data.is_ok());
         assert_eq!(data, serialize(&raw_filterload));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn filteradd_filterclear_test() {
+        let filteradd = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::FilterAdd(FilterAddMessage { data: vec![0xab; 20] }),
+        };
+        let data = serialize(&filteradd);
+        assert_eq!(&data[4..16], b"filteradd\0\0\0");
+        assert_eq!(&data[24..], &[&[20u8][..], &[0xab; 20][..]].concat()[..]);
+        assert_eq!(deserialize::<RawNetworkMessage>(&data).unwrap().payload, filteradd.payload);
+
+        let filterclear = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::FilterClear };
+        let data = serialize(&filterclear);
+        assert_eq!(data.len(), 24);
+        assert_eq!(deserialize::<RawNetworkMessage>(&data).unwrap().payload, NetworkMessage::FilterClear);
+
+        assert_eq!(deserialize::<BloomFlags>(&[2]).unwrap(), BloomFlags::PubkeyOnly);
+        assert_eq!(deserialize::<BloomFlags>(&[3]).unwrap(), BloomFlags::None);
+        assert_eq!(deserialize::<BloomFlags>(&[0x81]).unwrap(), BloomFlags::All);
+        assert_eq!(deserialize::<BloomFlags>(&[0xfe]).unwrap(), BloomFlags::PubkeyOnly);
+    }
+}
@@ -0,0 +1,586 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP152 compact block network messages
+//!
+//! This module defines the messages of compact block relay, in which
+//! blocks are announced as a header plus short IDs of their transactions,
+//! and `PartialBlock`, which rebuilds the full block from such an
+//! announcement and the transactions a node already has in its mempool.
+//!
+
+use std::{cmp, error, fmt, io};
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+use hashes::{sha256, siphash24, Hash};
+use hash_types::BlockHash;
+use util::endian;
+use util::hash::{BitcoinHash, MerkleRoot};
+
+/// Compact block relay error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A compact block version other than 1 (txids) or 2 (wtxids) was requested
+    UnknownVersion(u64),
+    /// The compact block has no transactions, or more than can be indexed
+    InvalidLength,
+    /// A prefilled transaction index is out of range or not increasing
+    InvalidPrefilledIndex(u16),
+    /// Two transactions of the block have the same short ID; the full block
+    /// has to be requested instead
+    ShortIdCollision,
+    /// The `blocktxn` message is for another block or does not have one
+    /// transaction for each missing index
+    UnexpectedTransactions,
+    /// Some transactions of the block are still missing
+    MissingTransactions,
+    /// The rebuilt block does not match the merkle root of its header, which
+    /// happens when a mempool transaction collides with a short ID; the full
+    /// block has to be requested instead
+    InvalidMerkleRoot,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownVersion(v) => write!(f, "unknown compact block version {}", v),
+            Error::InvalidPrefilledIndex(i) => write!(f, "invalid prefilled transaction index {}", i),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl Error {
+    fn reason(&self) -> &'static str {
+        match *self {
+            Error::UnknownVersion(..) => "unknown compact block version",
+            Error::InvalidLength => "invalid compact block transaction count",
+            Error::InvalidPrefilledIndex(..) => "invalid prefilled transaction index",
+            Error::ShortIdCollision => "short ID collision in compact block",
+            Error::UnexpectedTransactions => "unexpected block transactions",
+            Error::MissingTransactions => "block transactions missing",
+            Error::InvalidMerkleRoot => "reconstructed block has an invalid merkle root",
+        }
+    }
+}
+
+/// The highest transaction index compact block messages can carry
+const MAX_TX_INDEX: usize = 0xffff;
+
+/// The `sendcmpct` message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SendCmpct {
+    /// Whether new blocks should be announced with `cmpctblock` rather than
+    /// `inv` or `headers`
+    pub send_compact: bool,
+    /// The compact block version: 1 for short IDs of txids, 2 for short IDs of wtxids
+    pub version: u64,
+}
+impl_consensus_encoding!(SendCmpct, send_compact, version);
+
+/// A short transaction ID: the lower 6 bytes of the SipHash-2-4 of the
+/// transaction's txid or wtxid, keyed by the block header and a nonce
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug, Default)]
+pub struct ShortId(pub [u8; 6]);
+
+impl ShortId {
+    /// Calculate the SipHash keys of short IDs for the block with `header`,
+    /// announced with `nonce`
+    pub fn calculate_siphash_keys(header: &BlockHeader, nonce: u64) -> (u64, u64) {
+        let mut engine = sha256::Hash::engine();
+        header.consensus_encode(&mut engine).expect("engines don't error");
+        nonce.consensus_encode(&mut engine).expect("engines don't error");
+        let hash = sha256::Hash::from_engine(engine);
+        (endian::slice_to_u64_le(&hash[0..8]), endian::slice_to_u64_le(&hash[8..16]))
+    }
+
+    /// Calculate the short ID of the transaction with txid or wtxid `hash`
+    pub fn with_siphash_keys(hash: &[u8], keys: (u64, u64)) -> ShortId {
+        let id = siphash24::Hash::hash_to_u64_with_keys(keys.0, keys.1, hash);
+        let bytes = endian::u64_to_array_le(id);
+        ShortId([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]])
+    }
+
+    /// Calculate the short ID of `tx` for the given compact block version
+    pub fn from_transaction(tx: &Transaction, version: u64, keys: (u64, u64)) -> Result<ShortId, Error> {
+        match version {
+            1 => Ok(ShortId::with_siphash_keys(&tx.txid()[..], keys)),
+            2 => Ok(ShortId::with_siphash_keys(&tx.wtxid()[..], keys)),
+            v => Err(Error::UnknownVersion(v)),
+        }
+    }
+}
+
+impl Encodable for ShortId {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for ShortId {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(ShortId(Decodable::consensus_decode(d)?))
+    }
+}
+
+/// A transaction sent in full in a compact block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PrefilledTransaction {
+    /// The index of the transaction in the block. On the wire it is encoded
+    /// relative to the index of the previous prefilled transaction.
+    pub idx: u16,
+    /// The transaction
+    pub tx: Transaction,
+}
+
+/// Encode the next index of a list of increasing indexes relative to the
+/// previous one, `next` being one more than the previous index
+fn encode_differential<S: io::Write>(s: S, idx: u16, next: &mut u64) -> Result<usize, encode::Error> {
+    let idx = u64::from(idx);
+    if idx < *next {
+        return Err(encode::Error::ParseFailed("indexes not increasing"));
+    }
+    let len = VarInt(idx - *next).consensus_encode(s)?;
+    *next = idx + 1;
+    Ok(len)
+}
+
+/// Decode the next index of a list encoded with `encode_differential`
+fn decode_differential<D: io::Read>(d: D, next: &mut u64) -> Result<u16, encode::Error> {
+    let idx = VarInt::consensus_decode(d)?.0.checked_add(*next)
+        .ok_or(encode::Error::ParseFailed("indexes overflowed 16 bits"))?;
+    if idx > MAX_TX_INDEX as u64 {
+        return Err(encode::Error::ParseFailed("indexes overflowed 16 bits"));
+    }
+    *next = idx + 1;
+    Ok(idx as u16)
+}
+
+/// The `cmpctblock` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HeaderAndShortIds {
+    /// The header of the block
+    pub header: BlockHeader,
+    /// Nonce keying the short IDs together with the header
+    pub nonce: u64,
+    /// Short IDs of the transactions not prefilled, in block order
+    pub short_ids: Vec<ShortId>,
+    /// Transactions sent in full, ordered by index. The coinbase always is.
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl HeaderAndShortIds {
+    /// Construct a compact block announcing `block`, sending the coinbase
+    /// and the transactions at the `prefill` indexes in full
+    pub fn from_block(block: &Block, nonce: u64, version: u64, prefill: &[usize]) -> Result<HeaderAndShortIds, Error> {
+        if block.txdata.is_empty() || block.txdata.len() > MAX_TX_INDEX {
+            return Err(Error::InvalidLength);
+        }
+        let keys = ShortId::calculate_siphash_keys(&block.header, nonce);
+        let mut short_ids = Vec::with_capacity(block.txdata.len());
+        let mut prefilled_txs = vec![];
+        for (idx, tx) in block.txdata.iter().enumerate() {
+            if idx == 0 || prefill.contains(&idx) {
+                prefilled_txs.push(PrefilledTransaction {
+                    idx: idx as u16,
+                    tx: tx.clone(),
+                });
+            } else {
+                short_ids.push(ShortId::from_transaction(tx, version, keys)?);
+            }
+        }
+        Ok(HeaderAndShortIds {
+            header: block.header,
+            nonce,
+            short_ids,
+            prefilled_txs,
+        })
+    }
+
+    /// The SipHash keys of the short IDs of this compact block
+    pub fn siphash_keys(&self) -> (u64, u64) {
+        ShortId::calculate_siphash_keys(&self.header, self.nonce)
+    }
+
+    /// The number of transactions in the block
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled_txs.len()
+    }
+}
+
+impl Encodable for HeaderAndShortIds {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.header.consensus_encode(&mut s)?;
+        len += self.nonce.consensus_encode(&mut s)?;
+        len += self.short_ids.consensus_encode(&mut s)?;
+        len += VarInt(self.prefilled_txs.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0;
+        for prefilled in &self.prefilled_txs {
+            len += encode_differential(&mut s, prefilled.idx, &mut next)?;
+            len += prefilled.tx.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for HeaderAndShortIds {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        let nonce = Decodable::consensus_decode(&mut d)?;
+        let short_ids: Vec<ShortId> = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        match (short_ids.len() as u64).checked_add(count) {
+            Some(total) if total <= MAX_TX_INDEX as u64 => {}
+            _ => return Err(encode::Error::ParseFailed("indexes overflowed 16 bits")),
+        }
+        let mut prefilled_txs = Vec::with_capacity(cmp::min(count, MAX_TX_INDEX as u64) as usize);
+        let mut next = 0;
+        for _ in 0..count {
+            prefilled_txs.push(PrefilledTransaction {
+                idx: decode_differential(&mut d, &mut next)?,
+                tx: Decodable::consensus_decode(&mut d)?,
+            });
+        }
+        Ok(HeaderAndShortIds {
+            header,
+            nonce,
+            short_ids,
+            prefilled_txs,
+        })
+    }
+}
+
+/// The `getblocktxn` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactionsRequest {
+    /// The block whose transactions are requested
+    pub block_hash: BlockHash,
+    /// The increasing indexes of the requested transactions in the block.
+    /// On the wire each is encoded relative to the previous one.
+    pub indexes: Vec<u16>,
+}
+
+impl Encodable for BlockTransactionsRequest {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(&mut s)?;
+        len += VarInt(self.indexes.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0;
+        for idx in &self.indexes {
+            len += encode_differential(&mut s, *idx, &mut next)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockTransactionsRequest {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        if count > MAX_TX_INDEX as u64 + 1 {
+            return Err(encode::Error::ParseFailed("indexes overflowed 16 bits"));
+        }
+        let mut indexes = Vec::with_capacity(count as usize);
+        let mut next = 0;
+        for _ in 0..count {
+            indexes.push(decode_differential(&mut d, &mut next)?);
+        }
+        Ok(BlockTransactionsRequest {
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+/// The `blocktxn` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTransactions {
+    /// The block the transactions belong to
+    pub block_hash: BlockHash,
+    /// The requested transactions, in the order of the request
+    pub transactions: Vec<Transaction>,
+}
+impl_consensus_encoding!(BlockTransactions, block_hash, transactions);
+
+/// A block being rebuilt from a compact block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txdata: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Start rebuilding the block announced by `compact`. Transactions which
+    /// are not prefilled are looked up by short ID with `lookup`, typically
+    /// in the mempool; the short IDs of candidate transactions are computed
+    /// with `ShortId::from_transaction` and `HeaderAndShortIds::siphash_keys`.
+    pub fn new<F>(compact: &HeaderAndShortIds, mut lookup: F) -> Result<PartialBlock, Error>
+        where F: FnMut(&ShortId) -> Option<Transaction>
+    {
+        let count = compact.tx_count();
+        if count == 0 || count > MAX_TX_INDEX {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut txdata = vec![None; count];
+        let mut next = 0;
+        for prefilled in &compact.prefilled_txs {
+            let idx = prefilled.idx as usize;
+            if idx < next || idx >= count {
+                return Err(Error::InvalidPrefilledIndex(prefilled.idx));
+            }
+            txdata[idx] = Some(prefilled.tx.clone());
+            next = idx + 1;
+        }
+
+        let mut sorted = compact.short_ids.clone();
+        sorted.sort();
+        if sorted.windows(2).any(|w| w[0] == w[1]) {
+            return Err(Error::ShortIdCollision);
+        }
+
+        let mut short_ids = compact.short_ids.iter();
+        for slot in txdata.iter_mut().filter(|tx| tx.is_none()) {
+            let id = short_ids.next().expect("one short ID per missing transaction");
+            *slot = lookup(id);
+        }
+
+        Ok(PartialBlock {
+            header: compact.header,
+            txdata,
+        })
+    }
+
+    /// The header of the block
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// The indexes of the transactions still missing
+    pub fn missing(&self) -> Vec<u16> {
+        self.txdata.iter().enumerate().filter(|&(_, tx)| tx.is_none()).map(|(idx, _)| idx as u16).collect()
+    }
+
+    /// Whether all transactions of the block are known
+    pub fn is_complete(&self) -> bool {
+        self.txdata.iter().all(Option::is_some)
+    }
+
+    /// The `getblocktxn` message requesting the missing transactions
+    pub fn request(&self) -> BlockTransactionsRequest {
+        BlockTransactionsRequest {
+            block_hash: self.header.bitcoin_hash(),
+            indexes: self.missing(),
+        }
+    }
+
+    /// Fill in the missing transactions from the answer to `request`
+    pub fn fill(&mut self, response: BlockTransactions) -> Result<(), Error> {
+        let missing = self.txdata.iter().filter(|tx| tx.is_none()).count();
+        if response.block_hash != self.header.bitcoin_hash() || response.transactions.len() != missing {
+            return Err(Error::UnexpectedTransactions);
+        }
+        let mut transactions = response.transactions.into_iter();
+        for slot in self.txdata.iter_mut().filter(|tx| tx.is_none()) {
+            *slot = transactions.next();
+        }
+        Ok(())
+    }
+
+    /// The rebuilt block, checked against the merkle root of its header
+    pub fn into_block(self) -> Result<Block, Error> {
+        if !self.is_complete() {
+            return Err(Error::MissingTransactions);
+        }
+        let block = Block {
+            header: self.header,
+            txdata: self.txdata.into_iter().map(Option::unwrap).collect(),
+        };
+        if block.merkle_root() != block.header.merkle_root {
+            return Err(Error::InvalidMerkleRoot);
+        }
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hashes::hex::FromHex;
+    use hex::decode as hex_decode;
+
+    use blockdata::block::Block;
+    use consensus::encode::{deserialize, serialize};
+    use hash_types::BlockHash;
+    use network::message::{NetworkMessage, RawNetworkMessage};
+    use util::hash::BitcoinHash;
+
+    use super::*;
+
+    // The testnet segwit block of `block::tests::segwit_block_test`
+    fn test_block() -> Block {
+        let hex = include_str!("../../test_data/segwit_block.hex");
+        deserialize(&hex_decode(hex.trim()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn sendcmpct() {
+        let msg = SendCmpct { send_compact: true, version: 2 };
+        let raw = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::SendCmpct(msg) };
+        let bytes = serialize(&raw);
+        assert_eq!(&bytes[4..16], b"sendcmpct\0\0\0");
+        assert_eq!(&bytes[24..], &[1, 2, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert_eq!(deserialize::<RawNetworkMessage>(&bytes).unwrap().payload, raw.payload);
+    }
+
+    #[test]
+    fn short_ids() {
+        let block = test_block();
+        let keys = ShortId::calculate_siphash_keys(&block.header, 0x0102030405060708);
+        assert_eq!(keys, (0x5569aa2b8edb76af, 0xbad3d97bff7c99fd));
+        // Only the coinbase has a witness, so only its short IDs differ between versions
+        assert_eq!(
+            ShortId::from_transaction(&block.txdata[0], 1, keys).unwrap(),
+            ShortId([0x7d, 0xfe, 0x0d, 0xc1, 0x1c, 0x65]),
+        );
+        assert_eq!(
+            ShortId::from_transaction(&block.txdata[0], 2, keys).unwrap(),
+            ShortId([0xff, 0xd7, 0x92, 0xe2, 0x70, 0x78]),
+        );
+        assert_eq!(
+            ShortId::from_transaction(&block.txdata[1], 2, keys).unwrap(),
+            ShortId([0xaa, 0x10, 0x9a, 0x5c, 0x80, 0xec]),
+        );
+        assert_eq!(ShortId::from_transaction(&block.txdata[1], 3, keys), Err(Error::UnknownVersion(3)));
+    }
+
+    #[test]
+    fn differential_indexes() {
+        let block_hash = BlockHash::from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f").unwrap();
+        let request = BlockTransactionsRequest { block_hash, indexes: vec![0, 1, 3, 300, 65535] };
+        let bytes = serialize(&request);
+        assert_eq!(&bytes[32..], &[5, 0, 0, 1, 0xfd, 0x28, 0x01, 0xfd, 0xd2, 0xfe][..]);
+        assert_eq!(deserialize::<BlockTransactionsRequest>(&bytes).unwrap(), request);
+
+        // Decreasing indexes can't be encoded, and indexes must fit 16 bits
+        let request = BlockTransactionsRequest { block_hash, indexes: vec![3, 1] };
+        assert!(request.consensus_encode(&mut Vec::new()).is_err());
+        let mut bytes = serialize(&block_hash);
+        bytes.extend(&[2, 0xfd, 0xff, 0xff, 0]);
+        assert!(deserialize::<BlockTransactionsRequest>(&bytes).is_err());
+    }
+
+    #[test]
+    fn compact_block_roundtrip() {
+        let block = test_block();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[2]).unwrap();
+        assert_eq!(compact.short_ids.len(), 13);
+        assert_eq!(compact.prefilled_txs.iter().map(|p| p.idx).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(compact.tx_count(), 15);
+
+        let raw = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::CmpctBlock(compact.clone()) };
+        let bytes = serialize(&raw);
+        assert_eq!(&bytes[4..16], b"cmpctblock\0\0");
+        // Header, nonce and short IDs, then the coinbase at index 0 and tx 2 at 2 - 0 - 1
+        let prefilled = 24 + 80 + 8 + 1 + 13 * 6;
+        assert_eq!(bytes[24 + 80 + 8], 13);
+        assert_eq!(bytes[prefilled], 2);
+        assert_eq!(bytes[prefilled + 1], 0);
+        let coinbase_len = serialize(&block.txdata[0]).len();
+        assert_eq!(bytes[prefilled + 2 + coinbase_len], 1);
+        assert_eq!(deserialize::<RawNetworkMessage>(&bytes).unwrap().payload, raw.payload);
+
+        // A prefilled count that overflows with the short ID count
+        let mut bytes = serialize(&compact)[..80 + 8 + 1 + 13 * 6].to_vec();
+        bytes.extend(&[0xff; 9]);
+        match deserialize::<HeaderAndShortIds>(&bytes) {
+            Err(encode::Error::ParseFailed(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reconstruct() {
+        let block = test_block();
+        let compact = HeaderAndShortIds::from_block(&block, 7, 2, &[]).unwrap();
+
+        // The mempool knows all transactions but 1 and 3
+        let keys = compact.siphash_keys();
+        let mut mempool = HashMap::new();
+        for (idx, tx) in block.txdata.iter().enumerate().skip(1) {
+            if idx != 1 && idx != 3 {
+                mempool.insert(ShortId::from_transaction(tx, 2, keys).unwrap(), tx.clone());
+            }
+        }
+
+        let mut partial = PartialBlock::new(&compact, |id| mempool.get(id).cloned()).unwrap();
+        assert_eq!(partial.missing(), vec![1, 3]);
+        assert!(!partial.is_complete());
+        let request = partial.request();
+        assert_eq!(request.block_hash, block.bitcoin_hash());
+        assert_eq!(partial.clone().into_block(), Err(Error::MissingTransactions));
+
+        let wrong = BlockTransactions { block_hash: request.block_hash, transactions: vec![block.txdata[1].clone()] };
+        assert_eq!(partial.fill(wrong), Err(Error::UnexpectedTransactions));
+
+        // Swapping transactions breaks the merkle root
+        let mut swapped = partial.clone();
+        swapped.fill(BlockTransactions {
+            block_hash: request.block_hash,
+            transactions: vec![block.txdata[3].clone(), block.txdata[1].clone()],
+        }).unwrap();
+        assert_eq!(swapped.into_block(), Err(Error::InvalidMerkleRoot));
+
+        partial.fill(BlockTransactions {
+            block_hash: request.block_hash,
+            transactions: vec![block.txdata[1].clone(), block.txdata[3].clone()],
+        }).unwrap();
+        assert!(partial.is_complete());
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn reconstruct_invalid() {
+        let block = test_block();
+        let mut compact = HeaderAndShortIds::from_block(&block, 7, 1, &[]).unwrap();
+
+        let mut collision = compact.clone();
+        collision.short_ids[1] = collision.short_ids[0];
+        assert_eq!(PartialBlock::new(&collision, |_| None), Err(Error::ShortIdCollision));
+
+        let mut out_of_range = compact.clone();
+        out_of_range.prefilled_txs[0].idx = 15;
+        assert_eq!(PartialBlock::new(&out_of_range, |_| None), Err(Error::InvalidPrefilledIndex(15)));
+
+        compact.short_ids.clear();
+        compact.prefilled_txs.clear();
+        assert_eq!(PartialBlock::new(&compact, |_| None), Err(Error::InvalidLength));
+    }
+}
@@ -1,7 +1,7 @@
 //!
 //! BIP157  Client Side Block Filtering network messages
 //!
-use hashes::sha256d;
+use hash_types::{BlockHash, FilterHash, FilterHeader};
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// getcfilters message
@@ -11,7 +11,7 @@ pub struct GetCFilters {
     /// The height of the first block in the requested range
     pub start_height: u32,
     /// The hash of the last block in the requested range
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFilters, filter_type, start_height, stop_hash);
 
@@ -21,7 +21,7 @@ pub struct CFilter {
     /// Byte identifying the type of filter being returned
     pub filter_type: u8,
     /// Block hash of the Bitcoin block for which the filter is being returned
-    pub block_hash: sha256d::Hash,
+    pub block_hash: BlockHash,
     /// The serialized compact filter for this block
     pub filter: Vec<u8>,
 }
@@ -35,7 +35,7 @@ pub struct GetCFHeaders {
     /// The height of the first block in the requested range
     pub start_height: u32,
     /// The hash of the last block in the requested range
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFHeaders, filter_type, start_height, stop_hash);
 
@@ -45,11 +45,11 @@ pub struct CFHeaders {
     /// Filter type for which headers are requested
     pub filter_type: u8,
     /// The hash of the last block in the requested range
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
     /// The filter header preceding the first block in the requested range
-    pub previous_filter: sha256d::Hash,
+    pub previous_filter: FilterHeader,
     /// The filter hashes for each block in the requested range
-    pub filter_hashes: Vec<sha256d::Hash>,
+    pub filter_hashes: Vec<FilterHash>,
 }
 impl_consensus_encoding!(CFHeaders, filter_type, stop_hash, previous_filter, filter_hashes);
 
@@ -59,7 +59,7 @@ pub struct GetCFCheckpt {
     /// Filter type for which headers are requested
     pub filter_type: u8,
     /// The hash of the last block in the requested range
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFCheckpt, filter_type, stop_hash);
 
@@ -69,8 +69,8 @@ pub struct CFCheckpt {
     /// Filter type for which headers are requested
     pub filter_type: u8,
     /// The hash of the last block in the requested range
-    pub stop_hash: sha256d::Hash,
+    pub stop_hash: BlockHash,
     /// The filter headers at intervals of 1,000
-    pub filter_headers: Vec<sha256d::Hash>,
+    pub filter_headers: Vec<FilterHeader>,
 }
 impl_consensus_encoding!(CFCheckpt, filter_type, stop_hash, filter_headers);
\ No newline at end of file
@@ -29,9 +29,13 @@ pub use self::address::Address;
 pub mod message;
 pub mod message_blockdata;
 pub mod message_bloom_filter;
+pub mod message_compact_blocks;
 pub mod message_filter;
 pub mod message_network;
+pub mod peer;
 pub mod stream_reader;
+#[cfg(feature = "async")]
+pub mod async_codec;
 
 /// Network error
 #[derive(Debug)]
@@ -0,0 +1,745 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Peer connections
+//!
+//! This module defines `Peer`, a state machine for a connection to another
+//! node on the network. It performs the `version`/`verack` handshake,
+//! validates what the peer announces, answers pings and keeps track of the
+//! features negotiated with the peer. It does no I/O of its own: received
+//! bytes are fed to it with `Peer::feed`, or decoded messages with
+//! `Peer::handle_message`, and the messages it wants to send are queued
+//! until taken with `Peer::pop_outgoing`. For convenience, `Peer::handshake`
+//! and `Peer::receive` drive it over a blocking stream.
+//!
+
+use std::collections::VecDeque;
+use std::{cmp, error, fmt};
+use std::io::{self, Read, Write};
+
+use blockdata::constants::max_money;
+use consensus::encode::{self, Encodable};
+use network::constants::{Network, ServiceFlags};
+use network::message::{self, MessageHeader, NetworkMessage, RawNetworkMessage, UndecodedMessage};
+use network::message_network::VersionMessage;
+use util::amount::FeeRate;
+
+/// Lowest protocol version of peers we talk to, as in Bitcoin Core
+pub const MIN_PEER_PROTO_VERSION: u32 = 31800;
+
+/// First protocol version with `sendheaders` support (BIP130)
+pub const SENDHEADERS_VERSION: u32 = 70012;
+
+/// First protocol version with `feefilter` support (BIP133)
+pub const FEEFILTER_VERSION: u32 = 70013;
+
+/// First protocol version to which Bitcoin Core sends `sendaddrv2` (BIP155)
+pub const ADDRV2_VERSION: u32 = 70016;
+
+/// First protocol version with `wtxidrelay` support (BIP339)
+pub const WTXID_RELAY_VERSION: u32 = 70016;
+
+/// Peer connection error
+#[derive(Debug)]
+pub enum Error {
+    /// Error reading, decoding or writing a message. This includes messages
+    /// for a different network.
+    Encode(encode::Error),
+    /// A message fed to the peer could not be decoded; see `message::Error::should_ban`
+    Message(message::Error),
+    /// The peer's protocol version is older than `MIN_PEER_PROTO_VERSION`
+    ObsoleteVersion(u32),
+    /// The peer does not offer the services we require; these are the ones it does offer
+    MissingServices(ServiceFlags),
+    /// The peer's `version` carries our own nonce, so we are connected to ourselves
+    SelfConnection,
+    /// The peer sent a message which is not allowed at this point of the connection
+    UnexpectedMessage(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Encode(ref e) => fmt::Display::fmt(e, f),
+            Error::Message(ref e) => fmt::Display::fmt(e, f),
+            Error::ObsoleteVersion(v) => write!(f, "obsolete peer protocol version {}", v),
+            Error::MissingServices(s) => write!(f, "peer is missing required services, it has {}", s),
+            Error::UnexpectedMessage(cmd) => write!(f, "unexpected {} message", cmd),
+            Error::SelfConnection => f.write_str("connected to self"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Encode(ref e) => Some(e),
+            Error::Message(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Encode(..) => "error reading, decoding or writing a message",
+            Error::Message(..) => "message could not be decoded",
+            Error::ObsoleteVersion(..) => "obsolete peer protocol version",
+            Error::MissingServices(..) => "peer is missing required services",
+            Error::SelfConnection => "connected to self",
+            Error::UnexpectedMessage(..) => "unexpected message",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        Error::Encode(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<message::Error> for Error {
+    fn from(e: message::Error) -> Error {
+        Error::Message(e)
+    }
+}
+
+/// Which side opened the connection
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// We connected to the peer, and speak first
+    Outbound,
+    /// The peer connected to us, and we wait for its `version`
+    Inbound,
+}
+
+/// The stage a connection is at
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Waiting for the peer's `version`
+    AwaitingVersion,
+    /// The peer's `version` was received and answered, waiting for its `verack`
+    AwaitingVerack,
+    /// The handshake is complete
+    Established,
+}
+
+/// Features a side of the connection asks the other for
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Features {
+    /// New blocks are to be announced with `headers` rather than `inv` (BIP130)
+    pub send_headers: bool,
+    /// Addresses are to be relayed with `addrv2` rather than `addr` (BIP155)
+    pub send_addr_v2: bool,
+    /// Transactions are to be announced by wtxid rather than txid (BIP339).
+    /// This only takes effect if both sides ask for it, see `Peer::wtxid_relay`.
+    pub wtxid_relay: bool,
+    /// Transactions paying a lower fee rate are not to be announced (BIP133)
+    pub fee_filter: Option<FeeRate>,
+}
+
+/// A connection to a peer, as a state machine driven by received messages
+#[derive(Debug)]
+pub struct Peer {
+    network: Network,
+    direction: Direction,
+    state: State,
+    our_version: VersionMessage,
+    their_version: Option<VersionMessage>,
+    required_services: ServiceFlags,
+    announced: Features,
+    features: Features,
+    outgoing: VecDeque<RawNetworkMessage>,
+    /// Bytes fed to the peer which do not form a complete message yet
+    received: Vec<u8>,
+    /// Messages received for the application
+    incoming: VecDeque<NetworkMessage>,
+}
+
+impl Peer {
+    /// Start a connection on `network`, announcing ourselves with `version`.
+    /// For outbound connections, our `version` is queued right away.
+    pub fn new(network: Network, direction: Direction, version: VersionMessage) -> Peer {
+        let mut peer = Peer {
+            network,
+            direction,
+            state: State::AwaitingVersion,
+            our_version: version,
+            their_version: None,
+            required_services: ServiceFlags::NONE,
+            announced: Features::default(),
+            features: Features::default(),
+            outgoing: VecDeque::new(),
+            received: vec![],
+            incoming: VecDeque::new(),
+        };
+        if direction == Direction::Outbound {
+            let version = peer.our_version.clone();
+            peer.send(NetworkMessage::Version(version));
+        }
+        peer
+    }
+
+    /// Refuse peers which do not offer all of `services`
+    pub fn require_services(mut self, services: ServiceFlags) -> Peer {
+        self.required_services = services;
+        self
+    }
+
+    /// Ask the peer for `features` during the handshake, as far as its
+    /// protocol version supports them
+    pub fn announce(mut self, features: Features) -> Peer {
+        self.announced = features;
+        self
+    }
+
+    /// The stage the connection is at
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether the handshake is complete
+    pub fn is_established(&self) -> bool {
+        self.state == State::Established
+    }
+
+    /// Which side opened the connection
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The `version` message received from the peer, if any
+    pub fn their_version(&self) -> Option<&VersionMessage> {
+        self.their_version.as_ref()
+    }
+
+    /// The protocol version used on the connection, the lower of ours and
+    /// the peer's, once the peer's `version` is known
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.their_version.as_ref().map(|v| cmp::min(v.version, self.our_version.version))
+    }
+
+    /// The features the peer asked for so far
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// Whether transactions are announced by wtxid on this connection, which
+    /// is the case once both sides sent `wtxidrelay`
+    pub fn wtxid_relay(&self) -> bool {
+        self.announced.wtxid_relay && self.features.wtxid_relay
+    }
+
+    /// Queue a message to be sent to the peer
+    pub fn send(&mut self, message: NetworkMessage) {
+        self.outgoing.push_back(RawNetworkMessage {
+            magic: self.network.magic(),
+            payload: message,
+        });
+    }
+
+    /// Take the next message to be sent to the peer
+    pub fn pop_outgoing(&mut self) -> Option<RawNetworkMessage> {
+        self.outgoing.pop_front()
+    }
+
+    /// Process bytes received from the peer. Every complete message is
+    /// decoded, checking its size and checksum, and handled as by
+    /// `handle_message`; messages for the application are queued until taken
+    /// with `pop_incoming`. Incomplete messages are kept until the rest of
+    /// their bytes are fed.
+    ///
+    /// A message with a bad checksum is skipped, so feeding may go on after
+    /// such an error. After any other error the stream cannot be trusted
+    /// and the buffered bytes are dropped.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.received.extend_from_slice(data);
+        loop {
+            let message = match UndecodedMessage::decode_checked(&self.received[..]) {
+                Ok(message) => message,
+                // Wait for the rest of the message
+                Err(message::Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => {
+                    match e {
+                        message::Error::Malformed(encode::Error::InvalidChecksum { .. }) => {
+                            let (header, _): (MessageHeader, _) = encode::deserialize_partial(&self.received)?;
+                            self.received.drain(..MessageHeader::SIZE + header.length as usize);
+                        }
+                        _ => self.received.clear(),
+                    }
+                    return Err(Error::Message(e));
+                }
+            };
+            self.received.drain(..MessageHeader::SIZE + message.payload.len());
+            let handled = message.decode().map_err(Error::from).and_then(|m| self.handle_message(m));
+            match handled {
+                Ok(Some(payload)) => self.incoming.push_back(payload),
+                Ok(None) => {}
+                Err(e) => {
+                    self.received.clear();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Take the next message received for the application
+    pub fn pop_incoming(&mut self) -> Option<NetworkMessage> {
+        self.incoming.pop_front()
+    }
+
+    /// Write all queued messages to `writer`
+    pub fn flush<W: Write>(&mut self, mut writer: W) -> Result<(), Error> {
+        while let Some(message) = self.outgoing.pop_front() {
+            message.consensus_encode(&mut writer)?;
+        }
+        writer.flush().map_err(encode::Error::Io)?;
+        Ok(())
+    }
+
+    /// Process a message received from the peer. Messages dealing with the
+    /// connection itself (`version`, `verack`, `ping`, feature negotiation)
+    /// are handled here and any answers queued; others are returned for the
+    /// application to process. As Bitcoin Core does, other messages received
+    /// before the handshake is complete are ignored.
+    pub fn handle_message(&mut self, message: RawNetworkMessage) -> Result<Option<NetworkMessage>, Error> {
+        let magic = self.network.magic();
+        if message.magic != magic {
+            return Err(Error::Encode(encode::Error::UnexpectedNetworkMagic {
+                expected: magic,
+                actual: message.magic,
+            }));
+        }
+
+        match message.payload {
+            NetworkMessage::Version(version) => {
+                if self.state != State::AwaitingVersion {
+                    return Err(Error::UnexpectedMessage("version"));
+                }
+                if version.nonce == self.our_version.nonce {
+                    return Err(Error::SelfConnection);
+                }
+                if version.version < MIN_PEER_PROTO_VERSION {
+                    return Err(Error::ObsoleteVersion(version.version));
+                }
+                if !version.services.has(self.required_services) {
+                    return Err(Error::MissingServices(version.services));
+                }
+                if self.direction == Direction::Inbound {
+                    let ours = self.our_version.clone();
+                    self.send(NetworkMessage::Version(ours));
+                }
+                self.their_version = Some(version);
+                if self.announced.wtxid_relay && self.protocol_version() >= Some(WTXID_RELAY_VERSION) {
+                    self.send(NetworkMessage::WtxidRelay);
+                }
+                if self.announced.send_addr_v2 && self.protocol_version() >= Some(ADDRV2_VERSION) {
+                    self.send(NetworkMessage::SendAddrV2);
+                }
+                self.send(NetworkMessage::Verack);
+                self.state = State::AwaitingVerack;
+                Ok(None)
+            }
+            NetworkMessage::Verack => {
+                match self.state {
+                    State::AwaitingVersion => return Err(Error::UnexpectedMessage("verack")),
+                    State::AwaitingVerack => {
+                        self.state = State::Established;
+                        if self.announced.send_headers && self.protocol_version() >= Some(SENDHEADERS_VERSION) {
+                            self.send(NetworkMessage::SendHeaders);
+                        }
+                        if let Some(fee_rate) = self.announced.fee_filter {
+                            if self.protocol_version() >= Some(FEEFILTER_VERSION) {
+                                self.send(NetworkMessage::FeeFilter(fee_rate));
+                            }
+                        }
+                    }
+                    // Redundant, ignore
+                    State::Established => {}
+                }
+                Ok(None)
+            }
+            NetworkMessage::SendHeaders => {
+                if self.protocol_version() >= Some(SENDHEADERS_VERSION) {
+                    self.features.send_headers = true;
+                }
+                Ok(None)
+            }
+            NetworkMessage::SendAddrV2 => {
+                // Only allowed between version and verack
+                match self.state {
+                    State::AwaitingVersion => {}
+                    State::AwaitingVerack => self.features.send_addr_v2 = true,
+                    State::Established => return Err(Error::UnexpectedMessage("sendaddrv2")),
+                }
+                Ok(None)
+            }
+            NetworkMessage::WtxidRelay => {
+                // Only allowed between version and verack, from peers supporting it
+                match self.state {
+                    State::AwaitingVersion => {}
+                    State::AwaitingVerack => if self.protocol_version() >= Some(WTXID_RELAY_VERSION) {
+                        self.features.wtxid_relay = true;
+                    },
+                    State::Established => return Err(Error::UnexpectedMessage("wtxidrelay")),
+                }
+                Ok(None)
+            }
+            _ if !self.is_established() => Ok(None),
+            NetworkMessage::FeeFilter(fee_rate) => {
+                // Out of range filters are ignored, as Bitcoin Core does
+                if fee_rate.as_sat_per_kvb() <= max_money(self.network) {
+                    self.features.fee_filter = Some(fee_rate);
+                }
+                Ok(None)
+            }
+            NetworkMessage::Ping(nonce) => {
+                self.send(NetworkMessage::Pong(nonce));
+                Ok(None)
+            }
+            payload => Ok(Some(payload)),
+        }
+    }
+
+    /// Read from `stream` once and feed what was read
+    fn read_from<S: Read>(&mut self, stream: &mut S) -> Result<(), Error> {
+        let mut buf = [0u8; 4096];
+        let count = stream.read(&mut buf).map_err(encode::Error::Io)?;
+        if count == 0 {
+            return Err(Error::Encode(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof))));
+        }
+        self.feed(&buf[..count])
+    }
+
+    /// Perform the handshake over `stream`, returning once it is complete.
+    /// Messages for the application received along the way are kept for
+    /// `receive`.
+    pub fn handshake<S: Read + Write>(&mut self, stream: &mut S) -> Result<(), Error> {
+        self.flush(&mut *stream)?;
+        while !self.is_established() {
+            self.read_from(stream)?;
+            self.flush(&mut *stream)?;
+        }
+        Ok(())
+    }
+
+    /// Read messages from `stream` until one for the application arrives,
+    /// answering the peer along the way
+    pub fn receive<S: Read + Write>(&mut self, stream: &mut S) -> Result<NetworkMessage, Error> {
+        loop {
+            if let Some(message) = self.pop_incoming() {
+                return Ok(message);
+            }
+            self.read_from(stream)?;
+            self.flush(&mut *stream)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use consensus::encode::{self, deserialize_partial, serialize};
+    use network::address::Address;
+    use network::constants::{Network, ServiceFlags};
+    use network::message::{self, NetworkMessage, RawNetworkMessage, UndecodedMessage};
+    use network::message_network::VersionMessage;
+    use util::amount::FeeRate;
+
+    use super::*;
+
+    fn version(nonce: u64, version: u32, services: ServiceFlags) -> VersionMessage {
+        let addr = Address::new(&SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8333), services);
+        let mut msg = VersionMessage::new(services, 1_500_000_000, addr.clone(), addr, nonce, "/test/".to_owned(), 0);
+        msg.version = version;
+        msg
+    }
+
+    fn raw(payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage {
+            magic: Network::Bitcoin.magic(),
+            payload,
+        }
+    }
+
+    fn outgoing(peer: &mut Peer) -> Vec<NetworkMessage> {
+        let mut ret = vec![];
+        while let Some(msg) = peer.pop_outgoing() {
+            assert_eq!(msg.magic, Network::Bitcoin.magic());
+            ret.push(msg.payload);
+        }
+        ret
+    }
+
+    #[test]
+    fn outbound_handshake() {
+        let ours = version(1, 70015, ServiceFlags::NONE);
+        let theirs = version(2, 70015, ServiceFlags::NETWORK | ServiceFlags::WITNESS);
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Outbound, ours.clone());
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::Version(ours)]);
+        assert_eq!(peer.state(), State::AwaitingVersion);
+
+        // Messages before the handshake are ignored, and verack must follow version
+        assert_eq!(peer.handle_message(raw(NetworkMessage::GetAddr)).unwrap(), None);
+        match peer.handle_message(raw(NetworkMessage::Verack)) {
+            Err(Error::UnexpectedMessage("verack")) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(peer.handle_message(raw(NetworkMessage::Version(theirs.clone()))).unwrap(), None);
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::Verack]);
+        assert_eq!(peer.state(), State::AwaitingVerack);
+        assert_eq!(peer.their_version(), Some(&theirs));
+        assert_eq!(peer.protocol_version(), Some(70015));
+
+        assert_eq!(peer.handle_message(raw(NetworkMessage::SendHeaders)).unwrap(), None);
+        assert_eq!(peer.handle_message(raw(NetworkMessage::Verack)).unwrap(), None);
+        assert!(peer.is_established());
+        assert!(peer.features().send_headers);
+
+        assert_eq!(peer.handle_message(raw(NetworkMessage::Ping(42))).unwrap(), None);
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::Pong(42)]);
+        assert_eq!(peer.handle_message(raw(NetworkMessage::GetAddr)).unwrap(), Some(NetworkMessage::GetAddr));
+        match peer.handle_message(raw(NetworkMessage::Version(theirs))) {
+            Err(Error::UnexpectedMessage("version")) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn inbound_handshake() {
+        let ours = version(1, 70015, ServiceFlags::NETWORK);
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Inbound, ours.clone());
+        assert_eq!(outgoing(&mut peer), vec![]);
+
+        // Old peers don't get sendheaders, even if they ask for it
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70001, ServiceFlags::NONE)))).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::Version(ours), NetworkMessage::Verack]);
+        assert_eq!(peer.protocol_version(), Some(70001));
+        peer.handle_message(raw(NetworkMessage::Verack)).unwrap();
+        peer.handle_message(raw(NetworkMessage::SendHeaders)).unwrap();
+        assert!(peer.is_established());
+        assert!(!peer.features().send_headers);
+    }
+
+    #[test]
+    fn announced_features() {
+        let ours = version(1, 70016, ServiceFlags::NONE);
+        let features = Features { send_headers: true, send_addr_v2: true, ..Default::default() };
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Outbound, ours).announce(features);
+        outgoing(&mut peer);
+
+        // sendaddrv2 goes before verack, sendheaders after the handshake
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70016, ServiceFlags::NONE)))).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::SendAddrV2, NetworkMessage::Verack]);
+        peer.handle_message(raw(NetworkMessage::SendAddrV2)).unwrap();
+        assert!(peer.features().send_addr_v2);
+        peer.handle_message(raw(NetworkMessage::Verack)).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::SendHeaders]);
+        match peer.handle_message(raw(NetworkMessage::SendAddrV2)) {
+            Err(Error::UnexpectedMessage("sendaddrv2")) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Older peers are only asked for what they support
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Inbound, version(1, 70016, ServiceFlags::NONE))
+            .announce(features);
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70015, ServiceFlags::NONE)))).unwrap();
+        assert_eq!(outgoing(&mut peer).len(), 2);
+        peer.handle_message(raw(NetworkMessage::Verack)).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::SendHeaders]);
+    }
+
+    #[test]
+    fn rejected_peers() {
+        let new_peer = || {
+            Peer::new(Network::Bitcoin, Direction::Outbound, version(1, 70015, ServiceFlags::NONE))
+                .require_services(ServiceFlags::WITNESS)
+        };
+
+        match new_peer().handle_message(raw(NetworkMessage::Version(version(1, 70015, ServiceFlags::WITNESS)))) {
+            Err(Error::SelfConnection) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match new_peer().handle_message(raw(NetworkMessage::Version(version(2, 31799, ServiceFlags::WITNESS)))) {
+            Err(Error::ObsoleteVersion(31799)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match new_peer().handle_message(raw(NetworkMessage::Version(version(2, 70015, ServiceFlags::NETWORK)))) {
+            Err(Error::MissingServices(ServiceFlags::NETWORK)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let testnet = RawNetworkMessage {
+            magic: Network::Testnet.magic(),
+            payload: NetworkMessage::Verack,
+        };
+        match new_peer().handle_message(testnet) {
+            Err(Error::Encode(encode::Error::UnexpectedNetworkMagic { expected: 0xD9B4BEF9, actual: 0x0709110B })) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn wtxid_relay_and_fee_filter() {
+        let ours = version(1, 70016, ServiceFlags::NONE);
+        let features = Features {
+            wtxid_relay: true,
+            fee_filter: Some(FeeRate::from_sat_per_kvb(1000)),
+            ..Default::default()
+        };
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Outbound, ours).announce(features);
+        outgoing(&mut peer);
+
+        // wtxidrelay goes before verack, feefilter after the handshake
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70016, ServiceFlags::NONE)))).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::WtxidRelay, NetworkMessage::Verack]);
+        peer.handle_message(raw(NetworkMessage::WtxidRelay)).unwrap();
+        assert!(peer.wtxid_relay());
+        peer.handle_message(raw(NetworkMessage::Verack)).unwrap();
+        assert_eq!(outgoing(&mut peer), vec![NetworkMessage::FeeFilter(FeeRate::from_sat_per_kvb(1000))]);
+        match peer.handle_message(raw(NetworkMessage::WtxidRelay)) {
+            Err(Error::UnexpectedMessage("wtxidrelay")) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(peer.features().fee_filter, None);
+        peer.handle_message(raw(NetworkMessage::FeeFilter(FeeRate::from_sat_per_kvb(4923)))).unwrap();
+        assert_eq!(peer.features().fee_filter, Some(FeeRate::from_sat_per_kvb(4923)));
+        peer.handle_message(raw(NetworkMessage::FeeFilter(FeeRate::from_sat_per_kvb(0xffff_ffff_ffff_ffff)))).unwrap();
+        assert_eq!(peer.features().fee_filter, Some(FeeRate::from_sat_per_kvb(4923)));
+
+        // Wtxid relay needs both sides, and a recent enough peer
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Inbound, version(1, 70016, ServiceFlags::NONE));
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70016, ServiceFlags::NONE)))).unwrap();
+        peer.handle_message(raw(NetworkMessage::WtxidRelay)).unwrap();
+        assert!(peer.features().wtxid_relay);
+        assert!(!peer.wtxid_relay());
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Inbound, version(1, 70016, ServiceFlags::NONE))
+            .announce(features);
+        peer.handle_message(raw(NetworkMessage::Version(version(2, 70015, ServiceFlags::NONE)))).unwrap();
+        assert_eq!(outgoing(&mut peer).len(), 2);
+        peer.handle_message(raw(NetworkMessage::WtxidRelay)).unwrap();
+        assert!(!peer.wtxid_relay());
+    }
+
+    /// A fake peer: reads come from a script of its messages, writes are collected
+    struct FakeStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_handshake() {
+        let ours = version(1, 70015, ServiceFlags::NONE);
+        let mut input = vec![];
+        let script = [
+            NetworkMessage::Version(version(2, 70015, ServiceFlags::NETWORK)),
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(7),
+            NetworkMessage::Inv(vec![]),
+        ];
+        for msg in script.iter().cloned() {
+            input.extend(serialize(&raw(msg)));
+        }
+        let mut stream = FakeStream { input: Cursor::new(input), output: vec![] };
+
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Outbound, ours.clone());
+        peer.handshake(&mut stream).unwrap();
+        assert_eq!(peer.receive(&mut stream).unwrap(), NetworkMessage::Inv(vec![]));
+        match peer.receive(&mut stream) {
+            Err(Error::Encode(encode::Error::Io(ref e))) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut sent = vec![];
+        let mut data = &stream.output[..];
+        while !data.is_empty() {
+            let (msg, len): (RawNetworkMessage, _) = deserialize_partial(data).unwrap();
+            sent.push(msg.payload);
+            data = &data[len..];
+        }
+        assert_eq!(sent, vec![NetworkMessage::Version(ours), NetworkMessage::Verack, NetworkMessage::Pong(7)]);
+    }
+
+    #[test]
+    fn feed() {
+        let mut peer = Peer::new(Network::Bitcoin, Direction::Inbound, version(1, 70015, ServiceFlags::NONE));
+        let mut data = vec![];
+        for msg in [
+            NetworkMessage::Version(version(2, 70015, ServiceFlags::NETWORK)),
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(7),
+            NetworkMessage::Inv(vec![]),
+        ].iter().cloned() {
+            data.extend(serialize(&raw(msg)));
+        }
+
+        // Messages may be split anywhere
+        for byte in &data[..data.len() - 1] {
+            peer.feed(&[*byte]).unwrap();
+        }
+        assert!(peer.is_established());
+        assert_eq!(peer.pop_incoming(), None);
+        peer.feed(&data[data.len() - 1..]).unwrap();
+        assert_eq!(peer.pop_incoming(), Some(NetworkMessage::Inv(vec![])));
+        assert_eq!(outgoing(&mut peer).last(), Some(&NetworkMessage::Pong(7)));
+
+        // A bad checksum only skips the message
+        let mut bad = serialize(&raw(NetworkMessage::Ping(8)));
+        bad[20] ^= 1;
+        bad.extend(serialize(&raw(NetworkMessage::GetAddr)));
+        match peer.feed(&bad) {
+            Err(Error::Message(ref e)) if !e.should_ban() => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        peer.feed(&[]).unwrap();
+        assert_eq!(peer.pop_incoming(), Some(NetworkMessage::GetAddr));
+
+        // Undecodable messages are reported rather than handled
+        let truncated = UndecodedMessage::new(Network::Bitcoin.magic(), "ping".into(), vec![0x64]);
+        match peer.feed(&serialize(&truncated)) {
+            Err(Error::Message(message::Error::Malformed(..))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(peer.pop_incoming(), None);
+
+        // The rest of the bytes fed with it go too
+        let mut data = serialize(&truncated);
+        data.extend(serialize(&raw(NetworkMessage::GetAddr)));
+        assert!(peer.feed(&data).is_err());
+        peer.feed(&[]).unwrap();
+        assert_eq!(peer.pop_incoming(), None);
+        peer.feed(&serialize(&raw(NetworkMessage::GetAddr))).unwrap();
+        assert_eq!(peer.pop_incoming(), Some(NetworkMessage::GetAddr));
+    }
+}
@@ -0,0 +1,499 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Standardness policy
+//!
+//! This module implements the checks Bitcoin Core makes before relaying a
+//! transaction beyond consensus validity: output script templates, sizes
+//! and dust limits. A transaction failing them is valid but will not be
+//! relayed or mined by nodes using the default policy, and is rejected
+//! with one of the reasons given here.
+//!
+
+use std::{error, fmt};
+
+use blockdata::interpreter::{MAX_PUBKEYS_PER_MULTISIG, MAX_SCRIPT_SIZE};
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::encode::Encodable;
+use network::message_network::RejectReason;
+use util::amount::{Amount, FeeRate};
+
+/// The highest transaction version that is standard
+pub const TX_MAX_STANDARD_VERSION: u32 = 2;
+/// The maximum weight of a standard transaction
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// The maximum size of a standard scriptSig, enough for a 15-of-15
+/// P2SH multisig with compressed keys
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// The maximum number of keys of a standard bare multisig output
+pub const MAX_STANDARD_BARE_MULTISIG_KEYS: u8 = 3;
+/// The default maximum size of a standard OP_RETURN output script: 80
+/// bytes of data, the OP_RETURN and the push opcodes
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+/// The default fee rate defining dust outputs
+pub const DUST_RELAY_TX_FEE: u64 = 3000;
+
+/// The template an output script follows, as recognized by Bitcoin Core's
+/// `Solver`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputType {
+    /// Pay to a public key
+    PubKey,
+    /// Pay to a public key hash
+    PubKeyHash,
+    /// Pay to a script hash
+    ScriptHash,
+    /// Bare multisig
+    Multisig {
+        /// Number of signatures required
+        required: u8,
+        /// Number of public keys
+        keys: u8,
+    },
+    /// OP_RETURN followed by pushes only
+    NullData,
+    /// Pay to a witness v0 public key hash
+    WitnessV0KeyHash,
+    /// Pay to a witness v0 script hash
+    WitnessV0ScriptHash,
+    /// Pay to a witness v1 taproot output
+    WitnessV1Taproot,
+    /// A witness program of a version without defined meaning
+    WitnessUnknown,
+    /// Any other script
+    NonStandard,
+}
+
+/// Whether `key` has the size its first byte calls for
+fn is_valid_pubkey_size(key: &[u8]) -> bool {
+    match key.first() {
+        Some(&0x02) | Some(&0x03) => key.len() == 33,
+        Some(&0x04) | Some(&0x06) | Some(&0x07) => key.len() == 65,
+        _ => false,
+    }
+}
+
+/// The number pushed by `op`, if it is OP_1 to OP_16
+fn small_integer(op: opcodes::All) -> Option<u8> {
+    match op.classify() {
+        opcodes::Class::PushNum(n) if n >= 1 => Some(n as u8),
+        _ => None,
+    }
+}
+
+/// The numbers of required signatures and keys of a bare multisig script
+fn match_multisig(script: &Script) -> Option<(u8, u8)> {
+    let mut instructions = script.iter(false);
+    let required = match instructions.next() {
+        Some(Instruction::Op(op)) => small_integer(op)?,
+        _ => return None,
+    };
+    let mut keys = 0usize;
+    loop {
+        match instructions.next() {
+            Some(Instruction::PushBytes(key)) if is_valid_pubkey_size(key) => {
+                keys += 1;
+                if keys > MAX_PUBKEYS_PER_MULTISIG as usize {
+                    return None;
+                }
+            }
+            Some(Instruction::Op(op)) => {
+                if small_integer(op).map(|n| n as usize) != Some(keys) {
+                    return None;
+                }
+                break;
+            }
+            _ => return None,
+        }
+    }
+    if required == 0 || required as usize > keys {
+        return None;
+    }
+    match (instructions.next(), instructions.next()) {
+        (Some(Instruction::Op(opcodes::all::OP_CHECKMULTISIG)), None) => Some((required, keys as u8)),
+        _ => None,
+    }
+}
+
+/// Recognize the template `script` follows
+pub fn classify(script: &Script) -> OutputType {
+    let bytes = script.as_bytes();
+    if script.is_p2sh() {
+        return OutputType::ScriptHash;
+    }
+    if script.is_witness_program() {
+        let version = bytes[0];
+        let program_len = script.len() - 2;
+        return if version == opcodes::all::OP_PUSHBYTES_0.into_u8() {
+            match program_len {
+                20 => OutputType::WitnessV0KeyHash,
+                32 => OutputType::WitnessV0ScriptHash,
+                _ => OutputType::NonStandard,
+            }
+        } else if version == opcodes::all::OP_PUSHNUM_1.into_u8() && program_len == 32 {
+            OutputType::WitnessV1Taproot
+        } else {
+            OutputType::WitnessUnknown
+        };
+    }
+    if script.is_op_return() {
+        return if Script::from(bytes[1..].to_vec()).is_push_only() {
+            OutputType::NullData
+        } else {
+            OutputType::NonStandard
+        };
+    }
+    if script.is_p2pk() && is_valid_pubkey_size(&bytes[1..bytes.len() - 1]) {
+        return OutputType::PubKey;
+    }
+    if script.is_p2pkh() {
+        return OutputType::PubKeyHash;
+    }
+    match match_multisig(script) {
+        Some((required, keys)) => OutputType::Multisig { required, keys },
+        None => OutputType::NonStandard,
+    }
+}
+
+/// Reason a transaction is not standard. `Policy::check_transaction`
+/// stops at the first reason found, in the order Bitcoin Core checks them.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The transaction version is not between 1 and
+    /// `TX_MAX_STANDARD_VERSION`
+    Version(u32),
+    /// The transaction weighs more than `MAX_STANDARD_TX_WEIGHT`
+    TxSize(usize),
+    /// The scriptSig of the input at this index is larger than
+    /// `MAX_STANDARD_SCRIPTSIG_SIZE`
+    ScriptSigSize(usize),
+    /// The scriptSig of the input at this index is not push only
+    ScriptSigNotPushOnly(usize),
+    /// The script of the output at this index follows no standard
+    /// template
+    ScriptPubKey(usize),
+    /// The output at this index is bare multisig, which the policy does
+    /// not permit
+    BareMultisig(usize),
+    /// The output at this index is dust
+    Dust(usize),
+    /// The transaction has more than one OP_RETURN output
+    MultiOpReturn,
+}
+
+impl Error {
+    /// The reason Bitcoin Core gives for rejecting the transaction
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            Error::Version(..) => "version",
+            Error::TxSize(..) => "tx-size",
+            Error::ScriptSigSize(..) => "scriptsig-size",
+            Error::ScriptSigNotPushOnly(..) => "scriptsig-not-pushonly",
+            Error::ScriptPubKey(..) => "scriptpubkey",
+            Error::BareMultisig(..) => "bare-multisig",
+            Error::Dust(..) => "dust",
+            Error::MultiOpReturn => "multi-op-return",
+        }
+    }
+
+    /// The code of a `reject` message for the transaction
+    pub fn reject_reason(&self) -> RejectReason {
+        match *self {
+            Error::Dust(..) => RejectReason::Dust,
+            _ => RejectReason::NonStandard,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Version(v) => write!(f, "{}: {}", self.reason(), v),
+            Error::TxSize(w) => write!(f, "{}: weight {}", self.reason(), w),
+            Error::ScriptSigSize(i) |
+            Error::ScriptSigNotPushOnly(i) => write!(f, "{}: input {}", self.reason(), i),
+            Error::ScriptPubKey(i) |
+            Error::BareMultisig(i) |
+            Error::Dust(i) => write!(f, "{}: output {}", self.reason(), i),
+            Error::MultiOpReturn => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+/// Settings of the standardness checks, matching Bitcoin Core's options
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Policy {
+    /// Fee rate at which spending an output costs more than its value
+    /// makes it dust (`-dustrelayfee`)
+    pub dust_relay_fee: FeeRate,
+    /// Maximum size of an OP_RETURN output script, or `None` if no
+    /// OP_RETURN output is standard (`-datacarriersize`, `-datacarrier`)
+    pub max_datacarrier_bytes: Option<usize>,
+    /// Whether bare multisig outputs are standard (`-permitbaremultisig`)
+    pub permit_bare_multisig: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            dust_relay_fee: FeeRate::from_sat_per_kvb(DUST_RELAY_TX_FEE),
+            max_datacarrier_bytes: Some(MAX_OP_RETURN_RELAY),
+            permit_bare_multisig: true,
+        }
+    }
+}
+
+impl Policy {
+    /// Whether `script` is a standard output script: it follows a known
+    /// template, bare multisig has at most 3 keys and OP_RETURN outputs
+    /// are within the data carrier size
+    pub fn is_standard_script(&self, script: &Script) -> bool {
+        match classify(script) {
+            OutputType::NonStandard => false,
+            OutputType::Multisig { keys, .. } => keys <= MAX_STANDARD_BARE_MULTISIG_KEYS,
+            OutputType::NullData => match self.max_datacarrier_bytes {
+                Some(max) => script.len() <= max,
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    /// The lowest value of `output` that is not dust: the fee, at the dust
+    /// relay fee rate, of the output and an input spending it. Outputs that
+    /// can never be spent have no dust threshold.
+    pub fn dust_threshold(&self, output: &TxOut) -> u64 {
+        let script = &output.script_pubkey;
+        if script.is_op_return() || script.len() > MAX_SCRIPT_SIZE {
+            return 0;
+        }
+        let mut size = output.consensus_encode(&mut ::std::io::sink()).expect("writing to a sink never fails");
+        // An input is an outpoint, a scriptSig length, a sequence and a
+        // scriptSig of 107 bytes for a signature and a public key, which
+        // is discounted in the witness
+        size += 32 + 4 + 1 + 4;
+        size += if script.is_witness_program() { 107 / 4 } else { 107 };
+        self.dust_relay_fee.fee_vb(size).unwrap_or_else(Amount::max_value).as_sat()
+    }
+
+    /// Whether `output` is dust: its value is below its dust threshold
+    pub fn is_dust(&self, output: &TxOut) -> bool {
+        output.value < self.dust_threshold(output)
+    }
+
+    /// Check that `tx` is standard, as Bitcoin Core's `IsStandardTx` does.
+    /// The inputs are not checked against the outputs they spend.
+    pub fn check_transaction(&self, tx: &Transaction) -> Result<(), Error> {
+        match tx.version {
+            1...TX_MAX_STANDARD_VERSION => {}
+            version => return Err(Error::Version(version)),
+        }
+        let weight = tx.get_weight();
+        if weight > MAX_STANDARD_TX_WEIGHT {
+            return Err(Error::TxSize(weight));
+        }
+
+        for (i, input) in tx.input.iter().enumerate() {
+            if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+                return Err(Error::ScriptSigSize(i));
+            }
+            if !input.script_sig.is_push_only() {
+                return Err(Error::ScriptSigNotPushOnly(i));
+            }
+        }
+
+        let mut data_outputs = 0;
+        for (i, output) in tx.output.iter().enumerate() {
+            if !self.is_standard_script(&output.script_pubkey) {
+                return Err(Error::ScriptPubKey(i));
+            }
+            match classify(&output.script_pubkey) {
+                OutputType::NullData => data_outputs += 1,
+                OutputType::Multisig { .. } if !self.permit_bare_multisig => return Err(Error::BareMultisig(i)),
+                _ => if self.is_dust(output) {
+                    return Err(Error::Dust(i));
+                },
+            }
+        }
+        if data_outputs > 1 {
+            return Err(Error::MultiOpReturn);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use network::message_network::RejectReason;
+    use util::amount::FeeRate;
+    use hex::decode as hex_decode;
+
+    use super::*;
+
+    fn script(hex: &str) -> Script {
+        Script::from(hex_decode(hex).unwrap())
+    }
+
+    fn multisig(required: i64, keys: usize) -> Script {
+        let mut builder = Builder::new().push_int(required);
+        for _ in 0..keys {
+            builder = builder.push_slice(&[0x02; 33]);
+        }
+        builder.push_int(keys as i64).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script()
+    }
+
+    fn p2pkh() -> Script {
+        script("76a914000000000000000000000000000000000000000088ac")
+    }
+
+    fn tx(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+        Transaction { version: 2, lock_time: 0, input, output }
+    }
+
+    fn txin(script_sig: Script) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::default(),
+            script_sig,
+            sequence: 0xffffffff,
+            witness: Vec::new(),
+        }
+    }
+
+    fn txout(value: u64, script_pubkey: Script) -> TxOut {
+        TxOut { value, script_pubkey }
+    }
+
+    #[test]
+    fn classify_test() {
+        assert_eq!(classify(&p2pkh()), OutputType::PubKeyHash);
+        assert_eq!(classify(&script("a914000000000000000000000000000000000000000087")), OutputType::ScriptHash);
+        assert_eq!(classify(&script("21020000000000000000000000000000000000000000000000000000000000000000ac")),
+                   OutputType::PubKey);
+        // A 33 byte key with the prefix of an uncompressed one
+        assert_eq!(classify(&script("21040000000000000000000000000000000000000000000000000000000000000000ac")),
+                   OutputType::NonStandard);
+        assert_eq!(classify(&script("00140000000000000000000000000000000000000000")), OutputType::WitnessV0KeyHash);
+        assert_eq!(classify(&script("00200000000000000000000000000000000000000000000000000000000000000000")),
+                   OutputType::WitnessV0ScriptHash);
+        assert_eq!(classify(&script("00100000000000000000000000000000")), OutputType::NonStandard);
+        assert_eq!(classify(&script("51200000000000000000000000000000000000000000000000000000000000000000")),
+                   OutputType::WitnessV1Taproot);
+        assert_eq!(classify(&script("52020000")), OutputType::WitnessUnknown);
+        assert_eq!(classify(&script("6a")), OutputType::NullData);
+        assert_eq!(classify(&script("6a04deadbeef")), OutputType::NullData);
+        assert_eq!(classify(&script("6a76")), OutputType::NonStandard);
+        assert_eq!(classify(&multisig(1, 2)), OutputType::Multisig { required: 1, keys: 2 });
+        // More signatures required than keys, or more keys than a multisig may have
+        assert_eq!(classify(&multisig(2, 1)), OutputType::NonStandard);
+        assert_eq!(classify(&multisig(1, 300)), OutputType::NonStandard);
+        // Keys pushed with a non-minimal opcode still count
+        assert_eq!(classify(&script("514c21020000000000000000000000000000000000000000000000000000000000000000\
+                                     51ae")),
+                   OutputType::Multisig { required: 1, keys: 1 });
+        // Key count not matching the keys
+        assert_eq!(classify(&script("512102000000000000000000000000000000000000000000000000000000000000000052ae")),
+                   OutputType::NonStandard);
+        assert_eq!(classify(&Script::new()), OutputType::NonStandard);
+    }
+
+    #[test]
+    fn standard_script_test() {
+        let policy = Policy::default();
+        assert!(policy.is_standard_script(&multisig(3, 3)));
+        assert!(!policy.is_standard_script(&multisig(1, 4)));
+        assert!(!policy.is_standard_script(&multisig(2, 1)));
+        assert!(!policy.is_standard_script(&script("6a76")));
+
+        let data = Builder::new().push_opcode(opcodes::all::OP_RETURN).push_slice(&[0; 80]).into_script();
+        assert_eq!(data.len(), MAX_OP_RETURN_RELAY);
+        assert!(policy.is_standard_script(&data));
+        let data = Builder::new().push_opcode(opcodes::all::OP_RETURN).push_slice(&[0; 81]).into_script();
+        assert!(!policy.is_standard_script(&data));
+        let policy = Policy { max_datacarrier_bytes: None, ..Policy::default() };
+        assert!(!policy.is_standard_script(&script("6a")));
+    }
+
+    #[test]
+    fn dust_test() {
+        let policy = Policy::default();
+        assert_eq!(policy.dust_threshold(&txout(0, p2pkh())), 546);
+        let p2wpkh = script("00140000000000000000000000000000000000000000");
+        assert_eq!(policy.dust_threshold(&txout(0, p2wpkh.clone())), 294);
+        assert_eq!(policy.dust_threshold(&txout(0, script("6a"))), 0);
+        assert!(policy.is_dust(&txout(545, p2pkh())));
+        assert!(!policy.is_dust(&txout(546, p2pkh())));
+        assert!(!policy.is_dust(&txout(294, p2wpkh.clone())));
+
+        let policy = Policy { dust_relay_fee: FeeRate::from_sat_per_kvb(1), ..Policy::default() };
+        assert_eq!(policy.dust_threshold(&txout(0, p2wpkh.clone())), 1);
+        let policy = Policy { dust_relay_fee: FeeRate::ZERO, ..Policy::default() };
+        assert_eq!(policy.dust_threshold(&txout(0, p2wpkh)), 0);
+    }
+
+    #[test]
+    fn check_transaction_test() {
+        let policy = Policy::default();
+        let good = tx(vec![txin(Script::new())], vec![txout(1000, p2pkh())]);
+        assert_eq!(policy.check_transaction(&good), Ok(()));
+
+        let mut t = good.clone();
+        t.version = 3;
+        assert_eq!(policy.check_transaction(&t), Err(Error::Version(3)));
+        t.version = 0;
+        assert_eq!(policy.check_transaction(&t), Err(Error::Version(0)));
+
+        let mut t = good.clone();
+        t.output.push(txout(1000, Script::from(vec![0x6a; 100_000])));
+        assert_eq!(policy.check_transaction(&t).map_err(|e| e.reason()), Err("tx-size"));
+
+        let t = tx(vec![txin(good.input[0].script_sig.clone()), txin(Script::from(vec![0; 1651]))], good.output.clone());
+        assert_eq!(policy.check_transaction(&t), Err(Error::ScriptSigSize(1)));
+        let t = tx(vec![txin(script("76"))], good.output.clone());
+        assert_eq!(policy.check_transaction(&t), Err(Error::ScriptSigNotPushOnly(0)));
+
+        let t = tx(good.input.clone(), vec![txout(1000, script("6a76"))]);
+        assert_eq!(policy.check_transaction(&t), Err(Error::ScriptPubKey(0)));
+        let t = tx(good.input.clone(), vec![txout(1000, multisig(1, 2))]);
+        assert_eq!(policy.check_transaction(&t), Ok(()));
+        let no_multisig = Policy { permit_bare_multisig: false, ..Policy::default() };
+        assert_eq!(no_multisig.check_transaction(&t), Err(Error::BareMultisig(0)));
+
+        let t = tx(good.input.clone(), vec![txout(1000, p2pkh()), txout(545, p2pkh())]);
+        let err = policy.check_transaction(&t).unwrap_err();
+        assert_eq!(err, Error::Dust(1));
+        assert_eq!(err.reason(), "dust");
+        assert_eq!(err.reject_reason(), RejectReason::Dust);
+
+        let t = tx(good.input.clone(), vec![txout(0, script("6a")), txout(0, script("6a01ff"))]);
+        let err = policy.check_transaction(&t).unwrap_err();
+        assert_eq!(err, Error::MultiOpReturn);
+        assert_eq!(err.reason(), "multi-op-return");
+        assert_eq!(err.reject_reason(), RejectReason::NonStandard);
+    }
+}
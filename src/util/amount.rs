@@ -137,8 +137,17 @@ fn is_too_precise(s: &str, precision: usize) -> bool {
 /// Parse decimal string in the given denomination into a satoshi value and a
 /// bool indicator for a negative amount.
 fn parse_signed_to_satoshi(
-    mut s: &str,
+    s: &str,
     denom: Denomination,
+) -> Result<(bool, u64), ParseAmountError> {
+    parse_signed_scaled(s, -denom.precision())
+}
+
+/// Parse decimal string into an integer value `10^precision_diff` times
+/// larger and a bool indicator for a negative amount.
+fn parse_signed_scaled(
+    mut s: &str,
+    precision_diff: i32,
 ) -> Result<(bool, u64), ParseAmountError> {
     if s.len() == 0 {
         return Err(ParseAmountError::InvalidFormat);
@@ -156,9 +165,6 @@ fn parse_signed_to_satoshi(
     }
 
     let max_decimals = {
-        // The difference in precision between native (satoshi)
-        // and desired denomination.
-        let precision_diff = -denom.precision();
         if precision_diff < 0 {
             // If precision diff is negative, this means we are parsing
             // into a less precise amount. That is not allowed unless
@@ -887,6 +893,145 @@ impl FromStr for SignedAmount {
     }
 }
 
+/// A fee rate, in satoshis per 1000 virtual bytes of transaction data.
+///
+/// This is the unit Bitcoin Core uses for its relay policy and which peers
+/// exchange in `feefilter` messages (BIP133).
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// The zero fee rate.
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    /// Create a [FeeRate] from the given number of satoshis per 1000 virtual bytes.
+    pub fn from_sat_per_kvb(sat_per_kvb: u64) -> FeeRate {
+        FeeRate(sat_per_kvb)
+    }
+
+    /// Get the number of satoshis per 1000 virtual bytes of this [FeeRate].
+    pub fn as_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// Create a [FeeRate] from the given number of satoshis per virtual byte.
+    /// Returns [None] if overflow occurred.
+    pub fn from_sat_per_vb(sat_per_vb: u64) -> Option<FeeRate> {
+        sat_per_vb.checked_mul(1000).map(FeeRate)
+    }
+
+    /// Create a [FeeRate] from the given number of satoshis per 1000 weight
+    /// units, four of which make a virtual byte.
+    /// Returns [None] if overflow occurred.
+    pub fn from_sat_per_kwu(sat_per_kwu: u64) -> Option<FeeRate> {
+        sat_per_kwu.checked_mul(4).map(FeeRate)
+    }
+
+    /// Get the number of satoshis per virtual byte, rounded down.
+    pub fn as_sat_per_vb_floor(self) -> u64 {
+        self.0 / 1000
+    }
+
+    /// Get the number of satoshis per virtual byte, rounded up.
+    pub fn as_sat_per_vb_ceil(self) -> u64 {
+        let floor = self.as_sat_per_vb_floor();
+        if floor * 1000 == self.0 { floor } else { floor + 1 }
+    }
+
+    /// Get the number of satoshis per 1000 weight units, rounded down.
+    pub fn as_sat_per_kwu(self) -> u64 {
+        self.0 / 4
+    }
+
+    /// Calculate the fee of `vsize` virtual bytes at this rate, rounded up
+    /// so that the rate is met.
+    /// Returns [None] if overflow occurred.
+    pub fn fee_vb(self, vsize: usize) -> Option<Amount> {
+        let fee = self.0.checked_mul(vsize as u64)?.checked_add(999)?;
+        Some(Amount::from_sat(fee / 1000))
+    }
+
+    /// Calculate the fee of `weight` weight units at this rate, rounded up
+    /// so that the rate is met.
+    /// Returns [None] if overflow occurred.
+    pub fn fee_wu(self, weight: usize) -> Option<Amount> {
+        let fee = self.0.checked_mul(weight as u64)?.checked_add(3999)?;
+        Some(Amount::from_sat(fee / 4000))
+    }
+
+    // Some arithmetic that doesn't fit in `std::ops` traits.
+
+    /// Checked addition.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+
+    /// Checked subtraction.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_sub(rhs.0).map(FeeRate)
+    }
+
+    /// Checked multiplication.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+
+    /// Checked integer division.
+    /// Be aware that integer division loses the remainder if no exact division
+    /// can be made.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_div(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_div(rhs).map(FeeRate)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fraction = self.0 % 1000;
+        if fraction == 0 {
+            write!(f, "{} sat/vB", self.0 / 1000)
+        } else {
+            // Drop the trailing zeros of the fraction
+            let digits = format!("{:03}", fraction);
+            let len = digits.rfind(|c| c != '0').map_or(0, |i| i + 1);
+            write!(f, "{}.{} sat/vB", self.0 / 1000, &digits[..len])
+        }
+    }
+}
+
+impl FromStr for FeeRate {
+    type Err = ParseAmountError;
+
+    /// Parses fee rates like "12.5 sat/vB", "1000 sat/kvB" or "253 sat/kWU",
+    /// as they are produced with [fmt::Display].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(3, " ");
+        let rate_str = split.next().unwrap();
+        let unit_str = split.next().ok_or(ParseAmountError::InvalidFormat)?;
+        if split.next().is_some() {
+            return Err(ParseAmountError::InvalidFormat);
+        }
+
+        // Parse into satoshis per 1000 virtual bytes
+        let (negative, rate) = match unit_str {
+            "sat/vB" => parse_signed_scaled(rate_str, 3)?,
+            "sat/kvB" => parse_signed_scaled(rate_str, 0)?,
+            "sat/kWU" => {
+                let (negative, rate) = parse_signed_scaled(rate_str, 0)?;
+                (negative, rate.checked_mul(4).ok_or(ParseAmountError::TooBig)?)
+            }
+            u => return Err(ParseAmountError::UnknownDenomination(u.to_owned())),
+        };
+        if negative {
+            return Err(ParseAmountError::Negative);
+        }
+        Ok(FeeRate(rate))
+    }
+}
+
 #[cfg(feature = "serde")]
 pub mod serde {
     // methods are implementation of a standardized serde-specific signature
@@ -1244,6 +1389,48 @@ mod tests {
         assert_eq!(Amount::from_str(&denom(&amt, D::MilliSatoshi)), Ok(amt));
     }
 
+    #[test]
+    fn fee_rate() {
+        let rate = FeeRate::from_sat_per_vb(12).unwrap();
+        assert_eq!(rate.as_sat_per_kvb(), 12_000);
+        assert_eq!(rate.as_sat_per_kwu(), 3_000);
+        assert_eq!(FeeRate::from_sat_per_kwu(253), Some(FeeRate::from_sat_per_kvb(1012)));
+        assert_eq!(FeeRate::from_sat_per_vb(0xffff_ffff_ffff_ffff), None);
+        assert_eq!(FeeRate::from_sat_per_kvb(1001).as_sat_per_vb_floor(), 1);
+        assert_eq!(FeeRate::from_sat_per_kvb(1001).as_sat_per_vb_ceil(), 2);
+        assert_eq!(FeeRate::from_sat_per_kvb(1000).as_sat_per_vb_ceil(), 1);
+
+        // Fees are rounded up
+        assert_eq!(rate.fee_vb(141), Some(Amount::from_sat(1692)));
+        assert_eq!(FeeRate::from_sat_per_kvb(1).fee_vb(1), Some(Amount::from_sat(1)));
+        assert_eq!(FeeRate::ZERO.fee_vb(1000), Some(Amount::ZERO));
+        assert_eq!(rate.fee_wu(561), Some(Amount::from_sat(1683)));
+        assert_eq!(FeeRate::from_sat_per_kvb(0xffff_ffff_ffff_ffff).fee_vb(2), None);
+
+        assert_eq!(rate.checked_add(FeeRate::from_sat_per_kvb(500)), Some(FeeRate::from_sat_per_kvb(12_500)));
+        assert_eq!(FeeRate::ZERO.checked_sub(rate), None);
+        assert_eq!(rate.checked_mul(2), Some(FeeRate::from_sat_per_kvb(24_000)));
+        assert_eq!(rate.checked_div(0), None);
+    }
+
+    #[test]
+    fn fee_rate_from_str() {
+        let p = FeeRate::from_str;
+        assert_eq!(p("12.5 sat/vB"), Ok(FeeRate::from_sat_per_kvb(12_500)));
+        assert_eq!(p("0.001 sat/vB"), Ok(FeeRate::from_sat_per_kvb(1)));
+        assert_eq!(p("1000 sat/kvB"), Ok(FeeRate::from_sat_per_kvb(1000)));
+        assert_eq!(p("253 sat/kWU"), Ok(FeeRate::from_sat_per_kvb(1012)));
+        assert_eq!(p("0.0001 sat/vB"), Err(ParseAmountError::TooPrecise));
+        assert_eq!(p("1.5 sat/kvB"), Err(ParseAmountError::TooPrecise));
+        assert_eq!(p("-1 sat/vB"), Err(ParseAmountError::Negative));
+        assert_eq!(p("12.5"), Err(ParseAmountError::InvalidFormat));
+        assert_eq!(p("12.5 sat/b"), Err(ParseAmountError::UnknownDenomination("sat/b".into())));
+
+        for &s in &["12.5 sat/vB", "1 sat/vB", "0.001 sat/vB", "2.25 sat/vB"] {
+            assert_eq!(p(s).unwrap().to_string(), s);
+        }
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn serde_as_sat() {
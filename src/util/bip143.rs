@@ -21,12 +21,17 @@
 
 use hashes::{sha256d, Hash};
 
+use std::io;
+use std::ops::Deref;
+
 use blockdata::script::Script;
-use blockdata::transaction::{Transaction, TxIn};
-use consensus::encode::Encodable;
+use blockdata::transaction::{Transaction, TxIn, SigHashType};
+use consensus::encode::{self, Encodable};
 
 /// Parts of a sighash which are common across inputs or signatures, and which are
-/// sufficient (in conjunction with a private key) to sign the transaction
+/// sufficient (in conjunction with a private key) to sign the transaction.
+///
+/// This only supports `SIGHASH_ALL`; use `SigHashCache` for other sighash types.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct SighashComponents {
     tx_version: u32,
@@ -99,6 +104,162 @@ impl SighashComponents {
     }
 }
 
+/// A replacement for `SighashComponents` which supports all sighash modes.
+///
+/// The intermediate hashes shared between inputs are computed lazily, the
+/// first time a signature hash needing them is requested, and reused for all
+/// further inputs of the same transaction.
+#[derive(Clone, Debug)]
+pub struct SigHashCache<R: Deref<Target = Transaction>> {
+    /// Access to transaction required for various introspection
+    tx: R,
+    /// Hash of all the previous outputs, computed as required
+    hash_prevouts: Option<sha256d::Hash>,
+    /// Hash of all the input sequence nos, computed as required
+    hash_sequence: Option<sha256d::Hash>,
+    /// Hash of all the outputs in this transaction, computed as required
+    hash_outputs: Option<sha256d::Hash>,
+}
+
+impl<R: Deref<Target = Transaction>> SigHashCache<R> {
+    /// Compute the sighash components from an unsigned transaction and auxiliary
+    /// in a lazy manner when required.
+    /// For the generated sighashes to be valid, no fields in the transaction may change except for
+    /// script_sig and witnesses.
+    pub fn new(tx: R) -> Self {
+        SigHashCache {
+            tx,
+            hash_prevouts: None,
+            hash_sequence: None,
+            hash_outputs: None,
+        }
+    }
+
+    /// Calculate hash for prevouts
+    pub fn hash_prevouts(&mut self) -> sha256d::Hash {
+        let hash_prevout = &mut self.hash_prevouts;
+        let input = &self.tx.input;
+        *hash_prevout.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txin in input {
+                txin.previous_output.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Calculate hash for input sequence values
+    pub fn hash_sequence(&mut self) -> sha256d::Hash {
+        let hash_sequence = &mut self.hash_sequence;
+        let input = &self.tx.input;
+        *hash_sequence.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txin in input {
+                txin.sequence.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Calculate hash for outputs
+    pub fn hash_outputs(&mut self) -> sha256d::Hash {
+        let hash_output = &mut self.hash_outputs;
+        let output = &self.tx.output;
+        *hash_output.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for txout in output {
+                txout.consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
+
+    /// Encode the BIP143 signing data for any flag type into a given object implementing a
+    /// std::io::Write trait. Errors are only those of the underlying writer.
+    ///
+    /// # Panics
+    /// Panics if `input_index` is out of range for the transaction's inputs.
+    pub fn encode_signing_data_to<Write: io::Write>(
+        &mut self,
+        writer: Write,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> Result<(), encode::Error> {
+        self.encode_signing_data_to_u32(writer, input_index, script_code, value, sighash_type.as_u32())
+    }
+
+    /// Like `encode_signing_data_to`, but committing to a raw sighash type as
+    /// found at the end of a signature, which need not be a defined `SigHashType`.
+    pub fn encode_signing_data_to_u32<Write: io::Write>(
+        &mut self,
+        mut writer: Write,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_u32: u32,
+    ) -> Result<(), encode::Error> {
+        let zero_hash = sha256d::Hash::default();
+
+        let (sighash, anyone_can_pay) = SigHashType::from_u32(sighash_u32).split_anyonecanpay_flag();
+
+        self.tx.version.consensus_encode(&mut writer)?;
+
+        if !anyone_can_pay {
+            self.hash_prevouts().consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        if !anyone_can_pay && sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_sequence().consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        {
+            let txin = &self.tx.input[input_index];
+
+            txin.previous_output.consensus_encode(&mut writer)?;
+            script_code.consensus_encode(&mut writer)?;
+            value.consensus_encode(&mut writer)?;
+            txin.sequence.consensus_encode(&mut writer)?;
+        }
+
+        if sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_outputs().consensus_encode(&mut writer)?;
+        } else if sighash == SigHashType::Single && input_index < self.tx.output.len() {
+            let mut single_enc = sha256d::Hash::engine();
+            self.tx.output[input_index].consensus_encode(&mut single_enc)?;
+            sha256d::Hash::from_engine(single_enc).consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        self.tx.lock_time.consensus_encode(&mut writer)?;
+        sighash_u32.consensus_encode(&mut writer)?;
+        Ok(())
+    }
+
+    /// Compute the BIP143 sighash for any flag type
+    ///
+    /// # Panics
+    /// Panics if `input_index` is out of range for the transaction's inputs.
+    pub fn signature_hash(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType
+    ) -> sha256d::Hash {
+        let mut enc = sha256d::Hash::engine();
+        self.encode_signing_data_to(&mut enc, input_index, script_code, value, sighash_type)
+            .expect("engines don't error");
+        sha256d::Hash::from_engine(enc)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use blockdata::script::Script;
@@ -155,6 +316,15 @@ mod tests {
             comp.sighash_all(&tx.input[1], &witness_script, value),
             hex_hash!("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
         );
+
+        let mut cache = SigHashCache::new(&tx);
+        assert_eq!(cache.hash_prevouts(), comp.hash_prevouts);
+        assert_eq!(cache.hash_sequence(), comp.hash_sequence);
+        assert_eq!(cache.hash_outputs(), comp.hash_outputs);
+        assert_eq!(
+            cache.signature_hash(1, &witness_script, value, SigHashType::All),
+            hex_hash!("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+        );
     }
 
     #[test]
@@ -236,4 +406,68 @@ mod tests {
             hex_hash!("185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c")
         );
     }
+
+    #[test]
+    fn bip143_sighash_flags() {
+        // BIP143 example "P2SH-P2WSH", signed once with each sighash type
+        let tx = deserialize::<Transaction>(
+            &Vec::<u8>::from_hex(
+            "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000\
+             ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f\
+             05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000").unwrap()[..],
+        ).unwrap();
+
+        let witness_script = hex_script!(
+            "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28\
+             bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b\
+             9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58\
+             c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b1486\
+             2c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b\
+             56ae"
+        );
+        let value = 987654321;
+
+        let mut cache = SigHashCache::new(&tx);
+        let vectors = [
+            (SigHashType::All, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (SigHashType::None, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (SigHashType::Single, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (SigHashType::AllPlusAnyoneCanPay, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (SigHashType::NonePlusAnyoneCanPay, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (SigHashType::SinglePlusAnyoneCanPay, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b"),
+        ];
+        for &(sighash_type, expected) in vectors.iter() {
+            assert_eq!(
+                cache.signature_hash(0, &witness_script, value, sighash_type),
+                hex_hash!(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn bip143_sighash_single_without_output() {
+        // With SIGHASH_SINGLE and no output at the input's index, the
+        // outputs hash is committed to as all-zeroes rather than panicking
+        let mut tx = deserialize::<Transaction>(
+            &Vec::<u8>::from_hex(
+                "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000\
+                0000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac00\
+                08af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000",
+            ).unwrap()[..],
+        ).unwrap();
+        let script = p2pkh_hex("03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873");
+
+        let single = SigHashCache::new(&tx).signature_hash(0, &script, 1_000_000_000, SigHashType::Single);
+        let none = SigHashCache::new(&tx).signature_hash(0, &script, 1_000_000_000, SigHashType::None);
+        assert!(single != none);
+
+        tx.output.clear();
+        let mut data_single = vec![];
+        let mut data_none = vec![];
+        SigHashCache::new(&tx).encode_signing_data_to(&mut data_single, 0, &script, 1_000_000_000, SigHashType::Single).unwrap();
+        SigHashCache::new(&tx).encode_signing_data_to(&mut data_none, 0, &script, 1_000_000_000, SigHashType::None).unwrap();
+        // Only the trailing sighash type differs
+        let len = data_single.len();
+        assert_eq!(data_single[..len - 4], data_none[..len - 4]);
+    }
 }
@@ -0,0 +1,496 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+//
+// This code was translated from bloom.h, bloom.cpp and hash.cpp
+// Copyright (c) 2012-2018 The Bitcoin Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! BIP37 Bloom Filters
+//!
+//! Bloom filters let a light client ask its peers to relay only the
+//! transactions and merkle blocks it may be interested in, without telling
+//! exactly which ones.
+//!
+//! # Examples
+//!
+//! ```rust
+//! extern crate bitcoin;
+//! use bitcoin::network::message_bloom_filter::BloomFlags;
+//! use bitcoin::util::bloom::BloomFilter;
+//!
+//! # fn main() {
+//! let mut filter = BloomFilter::new(10, 0.0001, 0, BloomFlags::All);
+//! filter.insert(b"an element");
+//! assert!(filter.contains(b"an element"));
+//! assert!(!filter.contains(b"another element"));
+//! # }
+//! ```
+
+use std::cmp;
+use std::collections::HashSet;
+use std::f64::consts::LN_2;
+
+use blockdata::block::Block;
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction};
+use consensus::encode::serialize;
+use network::message_bloom_filter::{BloomFlags, FilterLoadMessage};
+use util::endian;
+use util::merkleblock::MerkleBlock;
+
+/// Maximum size of a bloom filter in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// Maximum number of hash functions of a bloom filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// A BIP37 bloom filter
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BloomFilter {
+    content: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized to hold `elements` elements with a false
+    /// positive rate of `fp_rate`, as far as the size limits allow. A rate of
+    /// 1 or more gives an empty filter, which matches everything, and a rate
+    /// of 0 or less the largest filter.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = cmp::max(elements, 1);
+        let max_bits = (MAX_BLOOM_FILTER_SIZE * 8) as f64;
+        let bits = if fp_rate >= 1.0 {
+            0.0
+        } else if fp_rate > 0.0 {
+            -1.0 / (LN_2 * LN_2) * elements as f64 * fp_rate.ln()
+        } else {
+            max_bits
+        };
+        let len = bits.min(max_bits) as usize / 8;
+        let hash_funcs = ((len * 8 / elements) as f64 * LN_2) as u32;
+        BloomFilter {
+            content: vec![0; len],
+            hash_funcs: cmp::min(hash_funcs, MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    /// Create the filter loaded by a `filterload` message, or `None` if it
+    /// exceeds the size limits, for which Bitcoin Core bans the peer
+    pub fn from_filter_load(msg: FilterLoadMessage) -> Option<BloomFilter> {
+        let filter = BloomFilter {
+            content: msg.filter,
+            hash_funcs: msg.n_hash_functions,
+            tweak: msg.n_tweak,
+            flags: msg.n_flags,
+        };
+        if filter.is_within_size_constraints() {
+            Some(filter)
+        } else {
+            None
+        }
+    }
+
+    /// The update flags of the filter
+    pub fn flags(&self) -> BloomFlags {
+        self.flags
+    }
+
+    /// Whether the filter respects the size limits of BIP37
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.content.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    fn bit_index(&self, hash_num: u32, data: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(0xFBA4_C795).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.content.len() * 8)
+    }
+
+    /// Add `data` to the filter
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.content.is_empty() {
+            return;
+        }
+        for hash_num in 0..self.hash_funcs {
+            let idx = self.bit_index(hash_num, data);
+            self.content[idx >> 3] |= 1 << (idx & 7);
+        }
+    }
+
+    /// Whether `data` may have been added to the filter. An empty filter
+    /// matches everything.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.content.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|hash_num| {
+            let idx = self.bit_index(hash_num, data);
+            self.content[idx >> 3] & (1 << (idx & 7)) != 0
+        })
+    }
+
+    /// Add `outpoint` to the filter
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Whether `outpoint` may have been added to the filter
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Whether `tx` matches the filter, that is its txid, a data push of
+    /// one of its output scripts, one of its spent outpoints or a data push
+    /// of one of its input scripts does. When an output matches, its
+    /// outpoint is added to the filter according to the update flags so
+    /// that transactions spending it match as well.
+    pub fn match_transaction(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        let mut found = self.contains(&txid[..]);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if self.matches_push(&output.script_pubkey) {
+                found = true;
+                let update = match self.flags {
+                    BloomFlags::None => false,
+                    BloomFlags::All => true,
+                    BloomFlags::PubkeyOnly => {
+                        output.script_pubkey.is_p2pk() || is_multisig(&output.script_pubkey)
+                    }
+                };
+                if update {
+                    self.insert_outpoint(&OutPoint::new(txid, vout as u32));
+                }
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.matches_push(&input.script_sig)
+        })
+    }
+
+    /// Whether a non-empty data push of `script` matches the filter
+    fn matches_push(&self, script: &Script) -> bool {
+        script.iter(false).any(|instruction| match instruction {
+            Instruction::PushBytes(data) => !data.is_empty() && self.contains(data),
+            _ => false,
+        })
+    }
+
+    /// Build the merkle block of `block` proving the transactions which
+    /// match the filter, updating the filter on the way
+    pub fn match_block(&mut self, block: &Block) -> MerkleBlock {
+        let matches: HashSet<_> = block.txdata.iter()
+            .filter(|tx| self.match_transaction(tx))
+            .map(Transaction::txid)
+            .collect();
+        MerkleBlock::from_block(block, &matches)
+    }
+}
+
+impl From<BloomFilter> for FilterLoadMessage {
+    fn from(filter: BloomFilter) -> FilterLoadMessage {
+        FilterLoadMessage {
+            filter: filter.content,
+            n_hash_functions: filter.hash_funcs,
+            n_tweak: filter.tweak,
+            n_flags: filter.flags,
+        }
+    }
+}
+
+
+/// Whether `script` is a bare multisig output script, as recognized by
+/// Bitcoin Core's `Solver`
+fn is_multisig(script: &Script) -> bool {
+    let instructions: Vec<_> = script.iter(true).collect();
+    if instructions.len() < 4 {
+        return false;
+    }
+    let small_int = |instruction: &Instruction| match *instruction {
+        Instruction::Op(op) => match op.classify() {
+            opcodes::Class::PushNum(n) if n >= 1 => Some(n as usize),
+            _ => None,
+        },
+        _ => None,
+    };
+    let (required, keys) = match (small_int(&instructions[0]), small_int(&instructions[instructions.len() - 2])) {
+        (Some(required), Some(keys)) => (required, keys),
+        _ => return false,
+    };
+    instructions[instructions.len() - 1] == Instruction::Op(opcodes::all::OP_CHECKMULTISIG)
+        && required <= keys
+        && instructions.len() == keys + 3
+        && instructions[1..keys + 1].iter().all(|instruction| match *instruction {
+            Instruction::PushBytes(key) => key.len() == 33 || key.len() == 65,
+            _ => false,
+        })
+}
+
+/// MurmurHash3 (x86_32) of `data`
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h1 = seed;
+    let (body, tail) = data.split_at(data.len() - data.len() % 4);
+    for block in body.chunks(4) {
+        let k1 = endian::slice_to_u32_le(block).wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let mut k1 = 0u32;
+    for (i, byte) in tail.iter().enumerate() {
+        k1 ^= u32::from(*byte) << (8 * i);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85eb_ca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2_ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use hex::decode as hex_decode;
+
+    use hashes::hex::FromHex;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use hash_types::Txid;
+    use network::message::{NetworkMessage, RawNetworkMessage};
+    use network::message_bloom_filter::{BloomFlags, FilterLoadMessage};
+    use util::key::PublicKey;
+    use MerkleBlock;
+
+    use super::{murmur3, BloomFilter};
+
+    #[test]
+    fn murmur3_vectors() {
+        // From Bitcoin Core's hash_tests.cpp
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xFBA4C795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514E28B7, 0x00000000, "00"),
+            (0xEA3F0B17, 0xFBA4C795, "00"),
+            (0xFD6CF10D, 0x00000000, "ff"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xfc2e4a15, 0x00000000, "001122334455"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+            (0xb4698def, 0x00000000, "001122334455667788"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &hex_decode(data).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn create_insert_serialize() {
+        // From Bitcoin Core's bloom_tests.cpp
+        for &(tweak, expected) in &[(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            filter.insert(&hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap());
+            assert!(filter.contains(&hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+            assert!(!filter.contains(&hex_decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+            filter.insert(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+            assert!(filter.contains(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap()));
+            filter.insert(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+            assert!(filter.contains(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap()));
+            assert!(filter.is_within_size_constraints());
+
+            let msg = FilterLoadMessage::from(filter.clone());
+            assert_eq!(serialize(&msg), hex_decode(expected).unwrap());
+            let decoded = deserialize::<FilterLoadMessage>(&serialize(&msg)).unwrap();
+            assert_eq!(BloomFilter::from_filter_load(decoded), Some(filter));
+        }
+
+        // Sizes are capped
+        let filter = BloomFilter::new(1_000_000, 0.000_001, 0, BloomFlags::None);
+        assert!(filter.is_within_size_constraints());
+        assert_eq!(FilterLoadMessage::from(filter).filter.len(), 36_000);
+        for &(elements, fp_rate, len) in &[(0, 0.01, 1), (10, 0.0, 36_000), (10, -1.0, 36_000), (10, 1.0, 0), (10, 2.0, 0)] {
+            let filter = BloomFilter::new(elements, fp_rate, 0, BloomFlags::None);
+            assert!(filter.is_within_size_constraints());
+            assert_eq!(filter.content.len(), len);
+        }
+        let nan = BloomFilter::new(10, (-1.0f64).sqrt(), 0, BloomFlags::None);
+        assert_eq!(nan.content.len(), 36_000);
+
+        // Oversized filters are not loaded
+        let load = |len, n_hash_functions| FilterLoadMessage {
+            filter: vec![0; len],
+            n_hash_functions,
+            n_tweak: 0,
+            n_flags: BloomFlags::None,
+        };
+        assert!(BloomFilter::from_filter_load(load(36_000, 50)).is_some());
+        assert!(BloomFilter::from_filter_load(load(36_001, 50)).is_none());
+        assert!(BloomFilter::from_filter_load(load(36_000, 51)).is_none());
+    }
+
+    fn key() -> PublicKey {
+        PublicKey::from_slice(
+            &hex_decode("033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52").unwrap()
+        ).unwrap()
+    }
+
+    fn p2pkh(pubkey_hash: &[u8]) -> Script {
+        Builder::new()
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(pubkey_hash)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script()
+    }
+
+    // A transaction paying to a P2PKH output, and one spending that output
+    fn test_transactions() -> (Transaction, Transaction) {
+        let script_sig = Builder::new().push_slice(&[0x30; 71]).push_key(&key()).into_script();
+        let funding = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_hex(
+                    "ce9ea9f6f5e422c6a9dbcddb3b9a14d1c78fab9ab520cb281aa2a74a09575da1"
+                ).unwrap(), 1),
+                script_sig: script_sig.clone(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 100_000_000,
+                script_pubkey: p2pkh(&hex_decode("0389035a9225b3839e2bbf32d826a1e222031fd8").unwrap()),
+            }],
+        };
+        let spending = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding.txid(), 0),
+                script_sig,
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 99_000_000,
+                script_pubkey: p2pkh(&[0x11; 20]),
+            }],
+        };
+        (funding, spending)
+    }
+
+    #[test]
+    fn match_transaction() {
+        let (funding, spending) = test_transactions();
+        let pubkey_hash = hex_decode("0389035a9225b3839e2bbf32d826a1e222031fd8").unwrap();
+
+        // By txid
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        filter.insert(&funding.txid()[..]);
+        assert!(filter.match_transaction(&funding));
+        assert!(!filter.match_transaction(&spending));
+
+        // By output script push, adding the outpoint so that the spend matches
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        filter.insert(&pubkey_hash);
+        assert!(!filter.match_transaction(&spending));
+        assert!(filter.match_transaction(&funding));
+        assert!(filter.contains_outpoint(&OutPoint::new(funding.txid(), 0)));
+        assert!(filter.match_transaction(&spending));
+
+        // Without updates, or for outputs other than P2PK and multisig, the spend doesn't match
+        for &flags in &[BloomFlags::None, BloomFlags::PubkeyOnly] {
+            let mut filter = BloomFilter::new(10, 0.000_001, 0, flags);
+            filter.insert(&pubkey_hash);
+            assert!(filter.match_transaction(&funding));
+            assert!(!filter.match_transaction(&spending));
+        }
+
+        // By input script push
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::None);
+        filter.insert(&key().to_bytes());
+        assert!(filter.match_transaction(&spending));
+
+        // By spent outpoint
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::None);
+        filter.insert_outpoint(&spending.input[0].previous_output);
+        assert!(filter.match_transaction(&spending));
+        assert!(!filter.match_transaction(&funding));
+    }
+
+    #[test]
+    fn update_pubkey_only() {
+        let (mut funding, mut spending) = test_transactions();
+        let key = key();
+        let p2pk = Builder::new().push_key(&key).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let multisig = Builder::new().push_int(1).push_key(&key).push_int(1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script();
+
+        for script in &[p2pk, multisig] {
+            funding.output[0].script_pubkey = script.clone();
+            spending.input[0].previous_output = OutPoint::new(funding.txid(), 0);
+            spending.input[0].script_sig = Default::default();
+            let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::PubkeyOnly);
+            filter.insert(&key.to_bytes());
+            assert!(filter.match_transaction(&funding));
+            assert!(filter.match_transaction(&spending));
+        }
+    }
+
+    #[test]
+    fn match_block() {
+        // Block 80000, see `util::merkleblock`
+        let block = deserialize(&hex_decode(
+            "01000000ba8b9cda965dd8e536670f9ddec10e53aab14b20bacad27b9137190000000000190760b278fe7b8565fda3b968b918d5fd997f993b23674c0af3b6fde300b38f33a5914ce6ed5b1b01e32f570201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0704e6ed5b1b014effffffff0100f2052a01000000434104b68a50eaa0287eff855189f949c1c6e5f58b37c88231373d8a59809cbae83059cc6469d65c665ccfd1cfeb75c6e8e19413bba7fbff9bc762419a76d87b16086eac000000000100000001a6b97044d03da79c005b20ea9c0e1a6d9dc12d9f7b91a5911c9030a439eed8f5000000004948304502206e21798a42fae0e854281abd38bacd1aeed3ee3738d9e1446618c4571d1090db022100e2ac980643b0b82c0e88ffdfec6b64e3e6ba35e7ba5fdd7d5d6cc8d25c6b241501ffffffff0100f2052a010000001976a914404371705fa9bd789a2fcd52d2c580b65d35549d88ac00000000"
+        ).unwrap()).unwrap();
+
+        let mut filter = BloomFilter::new(1, 0.000_001, 0, BloomFlags::None);
+        filter.insert(&hex_decode("404371705fa9bd789a2fcd52d2c580b65d35549d").unwrap());
+        let merkle_block: MerkleBlock = filter.match_block(&block);
+        let (mut matches, mut indexes) = (vec![], vec![]);
+        merkle_block.extract_matches(&mut matches, &mut indexes).unwrap();
+        assert_eq!(indexes, vec![1]);
+        assert_eq!(matches, vec![block.txdata[1].txid()]);
+
+        let msg = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::MerkleBlock(merkle_block) };
+        let bytes = serialize(&msg);
+        assert_eq!(&bytes[4..16], b"merkleblock\0");
+        // Decoding pads the flag bits of the partial merkle tree, so compare encodings
+        assert_eq!(serialize(&deserialize::<RawNetworkMessage>(&bytes).unwrap()), bytes);
+    }
+}
@@ -17,20 +17,25 @@
 
 use std::cmp::min;
 use std::default::Default;
+use std::io;
 
-use hashes::{sha256d, Hash};
+use hashes::Hash;
 
 use consensus::encode::Encodable;
+use hash_types::{Txid, Wtxid, TxMerkleNode, WitnessMerkleNode};
 
 /// Any collection of objects for which a merkle root makes sense to calculate
 pub trait MerkleRoot {
     /// Construct a merkle tree from a collection, with elements ordered as
     /// they were in the original collection, and return the merkle root.
-    fn merkle_root(&self) -> sha256d::Hash;
+    fn merkle_root(&self) -> TxMerkleNode;
 }
 
-/// Calculates the merkle root of a list of txids hashes directly
-pub fn bitcoin_merkle_root(data: Vec<sha256d::Hash>) -> sha256d::Hash {
+/// Calculates the merkle root of a list of hashes directly
+pub fn bitcoin_merkle_root<T>(data: Vec<T>) -> T
+    where T: Hash + Encodable,
+          <T as Hash>::Engine: io::Write
+{
     // Base case
     if data.len() < 1 {
         return Default::default();
@@ -43,16 +48,30 @@ pub fn bitcoin_merkle_root(data: Vec<sha256d::Hash>) -> sha256d::Hash {
     for idx in 0..((data.len() + 1) / 2) {
         let idx1 = 2 * idx;
         let idx2 = min(idx1 + 1, data.len() - 1);
-        let mut encoder = sha256d::Hash::engine();
+        let mut encoder = T::engine();
         data[idx1].consensus_encode(&mut encoder).unwrap();
         data[idx2].consensus_encode(&mut encoder).unwrap();
-        next.push(sha256d::Hash::from_engine(encoder));
+        next.push(T::from_engine(encoder));
     }
     bitcoin_merkle_root(next)
 }
 
+/// Calculates the merkle root of a list of txids, as committed to in a block header
+pub fn txid_merkle_root(txids: &[Txid]) -> TxMerkleNode {
+    let hashes = txids.iter().map(|txid| TxMerkleNode::from_hash(txid.as_hash())).collect();
+    bitcoin_merkle_root(hashes)
+}
+
+/// Calculates the witness merkle root of a list of wtxids, as committed to in
+/// the coinbase transaction. Per BIP141 the caller must pass the all-zero
+/// wtxid (`Wtxid::default()`) in place of the coinbase's own wtxid.
+pub fn witness_merkle_root(wtxids: &[Wtxid]) -> WitnessMerkleNode {
+    let hashes = wtxids.iter().map(|wtxid| WitnessMerkleNode::from_hash(wtxid.as_hash())).collect();
+    bitcoin_merkle_root(hashes)
+}
+
 /// Objects which are referred to by hash
-pub trait BitcoinHash {
-    /// Produces a Sha256dHash which can be used to refer to the object
-    fn bitcoin_hash(&self) -> sha256d::Hash;
+pub trait BitcoinHash<T: Hash> {
+    /// Produces a hash which can be used to refer to the object
+    fn bitcoin_hash(&self) -> T;
 }
@@ -26,7 +26,7 @@
 //!
 //! ```rust
 //! extern crate bitcoin;
-//! use bitcoin::hashes::sha256d;
+//! use bitcoin::hash_types::Txid;
 //! use bitcoin::hashes::hex::FromHex;
 //! use bitcoin::{Block, MerkleBlock};
 //!
@@ -41,12 +41,12 @@
 //! let mb: MerkleBlock = bitcoin::consensus::deserialize(&mb_bytes).unwrap();
 //!
 //! // Authenticate and extract matched transaction ids
-//! let mut matches: Vec<sha256d::Hash> = vec![];
+//! let mut matches: Vec<Txid> = vec![];
 //! let mut index: Vec<u32> = vec![];
 //! assert!(mb.extract_matches(&mut matches, &mut index).is_ok());
 //! assert_eq!(1, matches.len());
 //! assert_eq!(
-//!     sha256d::Hash::from_hex(
+//!     Txid::from_hex(
 //!         "5a4ebf66822b0b2d56bd9dc64ece0bc38ee7844a23ff1d7320a88c5fdb2ad3e2").unwrap(),
 //!     matches[0]
 //! );
@@ -58,13 +58,13 @@
 use std::collections::HashSet;
 use std::io;
 
-use hashes::{sha256d, Hash};
+use hashes::Hash;
 
 use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
 use consensus::encode::{self, Decodable, Encodable};
-use util::hash::BitcoinHash;
+use hash_types::{Txid, TxMerkleNode};
 use util::merkleblock::MerkleBlockError::*;
-use {Block, BlockHeader};
+use {Block, BlockHeader, Transaction};
 
 /// An error when verifying the merkle block
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -119,7 +119,7 @@ pub struct PartialMerkleTree {
     /// node-is-parent-of-matched-txid bits
     bits: Vec<bool>,
     /// Transaction ids and internal hashes
-    hashes: Vec<sha256d::Hash>,
+    hashes: Vec<TxMerkleNode>,
 }
 
 impl PartialMerkleTree {
@@ -133,18 +133,18 @@ impl PartialMerkleTree {
     ///
     /// ```rust
     /// extern crate bitcoin;
-    /// use bitcoin::hashes::sha256d;
+    /// use bitcoin::hash_types::Txid;
     /// use bitcoin::hashes::hex::FromHex;
     /// use bitcoin::util::merkleblock::PartialMerkleTree;
     ///
     /// # fn main() {
     /// // Block 80000
-    /// let txids: Vec<sha256d::Hash> = [
+    /// let txids: Vec<Txid> = [
     ///     "c06fbab289f723c6261d3030ddb6be121f7d2508d77862bb1e484f5cd7f92b25",
     ///     "5a4ebf66822b0b2d56bd9dc64ece0bc38ee7844a23ff1d7320a88c5fdb2ad3e2",
     /// ]
     /// .iter()
-    /// .map(|hex| sha256d::Hash::from_hex(hex).unwrap())
+    /// .map(|hex| Txid::from_hex(hex).unwrap())
     /// .collect();
     ///
     /// // Select the second transaction
@@ -153,7 +153,7 @@ impl PartialMerkleTree {
     /// assert!(tree.extract_matches(&mut vec![], &mut vec![]).is_ok());
     /// # }
     /// ```
-    pub fn from_txids(txids: &[sha256d::Hash], matches: &[bool]) -> Self {
+    pub fn from_txids(txids: &[Txid], matches: &[bool]) -> Self {
         // We can never have zero txs in a merkle block, we always need the coinbase tx
         assert_ne!(txids.len(), 0);
         assert_eq!(txids.len(), matches.len());
@@ -178,9 +178,9 @@ impl PartialMerkleTree {
     /// returns the merkle root, or error in case of failure
     pub fn extract_matches(
         &self,
-        matches: &mut Vec<sha256d::Hash>,
+        matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
-    ) -> Result<sha256d::Hash, MerkleBlockError> {
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
         matches.clear();
         indexes.clear();
         // An empty set will not work
@@ -188,7 +188,7 @@ impl PartialMerkleTree {
             return Err(NoTransactions);
         };
         // check for excessively high numbers of transactions
-        if self.num_transactions > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT {
+        if self.num_transactions as usize > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT {
             return Err(TooManyTransactions);
         }
         // there can never be more hashes provided than one for every txid
@@ -231,10 +231,10 @@ impl PartialMerkleTree {
     }
 
     /// Calculate the hash of a node in the merkle tree (at leaf level: the txid's themselves)
-    fn calc_hash(&self, height: u32, pos: u32, txids: &[sha256d::Hash]) -> sha256d::Hash {
+    fn calc_hash(&self, height: u32, pos: u32, txids: &[Txid]) -> TxMerkleNode {
         if height == 0 {
             // Hash at height 0 is the txid itself
-            txids[pos as usize]
+            TxMerkleNode::from_hash(txids[pos as usize].as_hash())
         } else {
             // Calculate left hash
             let left = self.calc_hash(height - 1, pos * 2, txids);
@@ -254,7 +254,7 @@ impl PartialMerkleTree {
         &mut self,
         height: u32,
         pos: u32,
-        txids: &[sha256d::Hash],
+        txids: &[Txid],
         matches: &[bool],
     ) {
         // Determine whether this node is the parent of at least one matched txid
@@ -288,9 +288,9 @@ impl PartialMerkleTree {
         pos: u32,
         bits_used: &mut u32,
         hash_used: &mut u32,
-        matches: &mut Vec<sha256d::Hash>,
+        matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
-    ) -> Result<sha256d::Hash, MerkleBlockError> {
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
         if *bits_used as usize >= self.bits.len() {
             return Err(BadFormat("Overflowed the bits array".to_owned()));
         }
@@ -305,7 +305,7 @@ impl PartialMerkleTree {
             *hash_used += 1;
             if height == 0 && parent_of_match {
                 // in case of height 0, we have a matched txid
-                matches.push(hash);
+                matches.push(Txid::from_hash(hash.as_hash()));
                 indexes.push(pos);
             }
             Ok(hash)
@@ -343,11 +343,11 @@ impl PartialMerkleTree {
     }
 
     /// Helper method to produce SHA256D(left + right)
-    fn parent_hash(left: sha256d::Hash, right: sha256d::Hash) -> sha256d::Hash {
-        let mut encoder = sha256d::Hash::engine();
+    fn parent_hash(left: TxMerkleNode, right: TxMerkleNode) -> TxMerkleNode {
+        let mut encoder = TxMerkleNode::engine();
         left.consensus_encode(&mut encoder).unwrap();
         right.consensus_encode(&mut encoder).unwrap();
-        sha256d::Hash::from_engine(encoder)
+        TxMerkleNode::from_engine(encoder)
     }
 }
 
@@ -369,7 +369,7 @@ impl Encodable for PartialMerkleTree {
 impl Decodable for PartialMerkleTree {
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
         let num_transactions: u32 = Decodable::consensus_decode(&mut d)?;
-        let hashes: Vec<sha256d::Hash> = Decodable::consensus_decode(&mut d)?;
+        let hashes: Vec<TxMerkleNode> = Decodable::consensus_decode(&mut d)?;
 
         let bytes: Vec<u8> = Decodable::consensus_decode(d)?;
         let mut bits: Vec<bool> = vec![false; bytes.len() * 8];
@@ -407,7 +407,7 @@ impl MerkleBlock {
     ///
     /// ```rust
     /// extern crate bitcoin;
-    /// use bitcoin::hashes::sha256d;
+    /// use bitcoin::hash_types::Txid;
     /// use bitcoin::hashes::hex::FromHex;
     /// use bitcoin::{Block, MerkleBlock};
     ///
@@ -426,25 +426,25 @@ impl MerkleBlock {
     /// let block: Block = bitcoin::consensus::deserialize(&block_bytes).unwrap();
     ///
     /// // Create a merkle block containing a single transaction
-    /// let txid = sha256d::Hash::from_hex(
+    /// let txid = Txid::from_hex(
     ///     "5a4ebf66822b0b2d56bd9dc64ece0bc38ee7844a23ff1d7320a88c5fdb2ad3e2").unwrap();
     /// let match_txids = vec![txid].into_iter().collect();
     /// let mb = MerkleBlock::from_block(&block, &match_txids);
     ///
     /// // Authenticate and extract matched transaction ids
-    /// let mut matches: Vec<sha256d::Hash> = vec![];
+    /// let mut matches: Vec<Txid> = vec![];
     /// let mut index: Vec<u32> = vec![];
     /// assert!(mb.extract_matches(&mut matches, &mut index).is_ok());
     /// assert_eq!(txid, matches[0]);
     /// # }
     /// ```
-    pub fn from_block(block: &Block, match_txids: &HashSet<sha256d::Hash>) -> Self {
+    pub fn from_block(block: &Block, match_txids: &HashSet<Txid>) -> Self {
         let header = block.header;
 
         let mut matches: Vec<bool> = Vec::with_capacity(block.txdata.len());
-        let mut hashes: Vec<sha256d::Hash> = Vec::with_capacity(block.txdata.len());
+        let mut hashes: Vec<Txid> = Vec::with_capacity(block.txdata.len());
 
-        for hash in block.txdata.iter().map(BitcoinHash::bitcoin_hash) {
+        for hash in block.txdata.iter().map(Transaction::txid) {
             matches.push(match_txids.contains(&hash));
             hashes.push(hash);
         }
@@ -458,7 +458,7 @@ impl MerkleBlock {
     /// returns Ok(()) on success, or error in case of failure
     pub fn extract_matches(
         &self,
-        matches: &mut Vec<sha256d::Hash>,
+        matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
     ) -> Result<(), MerkleBlockError> {
         let merkle_root = self.txn.extract_matches(matches, indexes)?;
@@ -496,10 +496,11 @@ mod tests {
     use std::cmp::min;
 
     use hashes::hex::{FromHex, ToHex};
-    use hashes::{sha256d, Hash};
+    use hashes::Hash;
     use secp256k1::rand::prelude::*;
 
     use consensus::encode::{deserialize, serialize};
+    use hash_types::{Txid, TxMerkleNode};
     use util::hash::{bitcoin_merkle_root, BitcoinHash};
     use util::merkleblock::{MerkleBlock, PartialMerkleTree};
     use {hex, Block};
@@ -512,11 +513,12 @@ mod tests {
         for num_tx in tx_counts {
             // Create some fake tx ids
             let txids = (1..num_tx + 1) // change to `1..=num_tx` when min Rust >= 1.26.0
-                .map(|i| sha256d::Hash::from_hex(&format!("{:064x}", i)).unwrap())
+                .map(|i| Txid::from_hex(&format!("{:064x}", i)).unwrap())
                 .collect::<Vec<_>>();
 
             // Calculate the merkle root and height
-            let merkle_root_1 = bitcoin_merkle_root(txids.clone());
+            let hashes = txids.iter().map(|t| t.as_hash()).collect();
+            let merkle_root_1: TxMerkleNode = bitcoin_merkle_root(hashes).into();
             let mut height = 1;
             let mut ntx = num_tx;
             while ntx > 1 {
@@ -563,7 +565,7 @@ mod tests {
 
                 // Check that it has the same merkle root as the original, and a valid one
                 assert_eq!(merkle_root_1, merkle_root_2);
-                assert_ne!(merkle_root_2, sha256d::Hash::default());
+                assert_ne!(merkle_root_2, TxMerkleNode::default());
 
                 // check that it contains the matched transactions (in the same order!)
                 assert_eq!(match_txid1, match_txid2);
@@ -585,9 +587,9 @@ mod tests {
     #[test]
     fn pmt_malleability() {
         // Create some fake tx ids with the last 2 hashes repeating
-        let txids: Vec<sha256d::Hash> = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 9, 10]
+        let txids: Vec<Txid> = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 9, 10]
             .iter()
-            .map(|i| sha256d::Hash::from_hex(&format!("{:064x}", i)).unwrap())
+            .map(|i| Txid::from_hex(&format!("{:064x}", i)).unwrap())
             .collect();
 
         let matches = vec![
@@ -628,12 +630,12 @@ mod tests {
     fn merkleblock_construct_from_txids_found() {
         let block = get_block_13b8a();
 
-        let txids: Vec<sha256d::Hash> = [
+        let txids: Vec<Txid> = [
             "74d681e0e03bafa802c8aa084379aa98d9fcd632ddc2ed9782b586ec87451f20",
             "f9fc751cb7dc372406a9f8d738d5e6f8f63bab71986a39cf36ee70ee17036d07",
         ]
         .iter()
-        .map(|hex| sha256d::Hash::from_hex(hex).unwrap())
+        .map(|hex| Txid::from_hex(hex).unwrap())
         .collect();
 
         let txid1 = txids[0];
@@ -644,7 +646,7 @@ mod tests {
 
         assert_eq!(merkle_block.header.bitcoin_hash(), block.bitcoin_hash());
 
-        let mut matches: Vec<sha256d::Hash> = vec![];
+        let mut matches: Vec<Txid> = vec![];
         let mut index: Vec<u32> = vec![];
 
         assert_eq!(
@@ -670,14 +672,14 @@ mod tests {
         let block = get_block_13b8a();
         let txids = ["c0ffee00003bafa802c8aa084379aa98d9fcd632ddc2ed9782b586ec87451f20"]
             .iter()
-            .map(|hex| sha256d::Hash::from_hex(hex).unwrap())
+            .map(|hex| Txid::from_hex(hex).unwrap())
             .collect();
 
         let merkle_block = MerkleBlock::from_block(&block, &txids);
 
         assert_eq!(merkle_block.header.bitcoin_hash(), block.bitcoin_hash());
 
-        let mut matches: Vec<sha256d::Hash> = vec![];
+        let mut matches: Vec<Txid> = vec![];
         let mut index: Vec<u32> = vec![];
 
         assert_eq!(
@@ -699,7 +701,7 @@ mod tests {
             let hashes = &mut self.hashes;
             let mut hash = hashes[n].into_inner();
             hash[(bit >> 3) as usize] ^= 1 << (bit & 7);
-            hashes[n] = sha256d::Hash::from_slice(&hash).unwrap();
+            hashes[n] = TxMerkleNode::from_slice(&hash).unwrap();
         }
     }
 
@@ -22,6 +22,7 @@ pub mod amount;
 pub mod base58;
 pub mod bip32;
 pub mod bip143;
+pub mod bloom;
 pub mod contracthash;
 pub mod hash;
 pub mod merkleblock;
@@ -29,6 +30,7 @@ pub mod misc;
 pub mod psbt;
 pub mod uint;
 pub mod bip158;
+pub mod txbuilder;
 
 pub(crate) mod endian;
 
@@ -0,0 +1,472 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Transaction building
+//!
+//! This module builds unsigned transactions paying a set of recipients from
+//! candidate outputs at a target fee rate. Outputs are selected largest
+//! first, change is added unless it would be dust, in which case it goes to
+//! the fee, and the result is a PSBT ready to be passed to signers.
+//!
+
+use std::{cmp, error, fmt};
+
+use blockdata::locktime::Sequence;
+use blockdata::script::Script;
+use blockdata::transaction::{weight_to_vsize, OutPoint, Transaction, TxIn, TxOut};
+use consensus::encode::{Encodable, VarInt};
+use policy::Policy;
+use util::address::Address;
+use util::amount::{Amount, FeeRate};
+use util::psbt::PartiallySignedTransaction;
+
+/// Weight of the segwit marker and flag of a transaction with witnesses
+const SEGWIT_HEADER_WEIGHT: usize = 2;
+
+/// An output which can be spent by the built transaction
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Utxo {
+    /// The outpoint of the output
+    pub outpoint: OutPoint,
+    /// The output
+    pub txout: TxOut,
+    /// The weight an input spending the output adds to a transaction once
+    /// signed: its outpoint, sequence, scriptSig and witness. For example
+    /// 272 for a P2WPKH output and 592 for a P2PKH one with a compressed
+    /// key.
+    pub spend_weight: usize,
+    /// Whether the output is a P2SH output wrapping a witness program,
+    /// which cannot be told from its script alone
+    pub nested_segwit: bool,
+    /// The transaction containing the output. Signers need it to check the
+    /// value of outputs which are not spent with a witness, so it is
+    /// required for those.
+    pub prev_tx: Option<Transaction>,
+}
+
+impl Utxo {
+    /// Whether the output is spent with a witness
+    fn is_segwit(&self) -> bool {
+        self.nested_segwit || self.txout.script_pubkey.is_witness_program()
+    }
+}
+
+/// Reason a transaction cannot be built
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// There is no recipient
+    NoRecipients,
+    /// The amount of the recipient at this index is dust
+    DustRecipient(usize),
+    /// The candidate outputs are not worth the amounts and the fee
+    InsufficientFunds {
+        /// The value needed, including the fee
+        needed: Amount,
+        /// The value of all candidate outputs worth spending
+        available: Amount,
+    },
+    /// The amounts or the fee overflow
+    ValueOverflow,
+    /// The output spent is not spent with a witness, and its `prev_tx` is missing
+    MissingPrevTx(OutPoint),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DustRecipient(i) => write!(f, "amount of recipient {} is dust", i),
+            Error::InsufficientFunds { needed, available } => write!(f,
+                "insufficient funds: {} needed, {} available", needed, available),
+            Error::MissingPrevTx(ref o) => write!(f, "transaction of legacy output {} missing", o),
+            _ => f.write_str(self.reason()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        self.reason()
+    }
+}
+
+impl Error {
+    fn reason(&self) -> &'static str {
+        match *self {
+            Error::NoRecipients => "no recipients",
+            Error::DustRecipient(..) => "recipient amount is dust",
+            Error::InsufficientFunds { .. } => "insufficient funds",
+            Error::ValueOverflow => "value overflow",
+            Error::MissingPrevTx(..) => "transaction of legacy output missing",
+        }
+    }
+}
+
+/// The size of `output` in bytes
+fn output_size(output: &TxOut) -> usize {
+    output.consensus_encode(&mut ::std::io::sink()).expect("writing to a sink never fails")
+}
+
+/// Builder of an unsigned transaction
+#[derive(Clone, Debug)]
+pub struct TxBuilder {
+    recipients: Vec<TxOut>,
+    utxos: Vec<Utxo>,
+    fee_rate: FeeRate,
+    change_script: Script,
+    policy: Policy,
+    tip_height: Option<u32>,
+    rbf: bool,
+}
+
+impl TxBuilder {
+    /// Start building a transaction paying `fee_rate` and sending the change
+    /// to `change_script`
+    pub fn new(fee_rate: FeeRate, change_script: Script) -> TxBuilder {
+        TxBuilder {
+            recipients: Vec::new(),
+            utxos: Vec::new(),
+            fee_rate,
+            change_script,
+            policy: Policy::default(),
+            tip_height: None,
+            rbf: false,
+        }
+    }
+
+    /// Pay `amount` to `address`. Recipients are paid in the order they
+    /// are added.
+    pub fn add_recipient(self, address: &Address, amount: Amount) -> TxBuilder {
+        self.add_recipient_script(address.script_pubkey(), amount)
+    }
+
+    /// Pay `amount` to `script_pubkey`
+    pub fn add_recipient_script(mut self, script_pubkey: Script, amount: Amount) -> TxBuilder {
+        self.recipients.push(TxOut { value: amount.as_sat(), script_pubkey });
+        self
+    }
+
+    /// Add a candidate output to spend
+    pub fn add_utxo(mut self, utxo: Utxo) -> TxBuilder {
+        self.utxos.push(utxo);
+        self
+    }
+
+    /// Use the dust limits of `policy` for the recipients and the change,
+    /// instead of Bitcoin Core's default ones
+    pub fn policy(mut self, policy: Policy) -> TxBuilder {
+        self.policy = policy;
+        self
+    }
+
+    /// Discourage fee sniping by setting the lock time to the height of
+    /// the chain tip, so that the transaction cannot be included in a
+    /// block reorganizing the tip away
+    pub fn anti_fee_sniping(mut self, tip_height: u32) -> TxBuilder {
+        self.tip_height = Some(tip_height);
+        self
+    }
+
+    /// Whether the transaction signals that it may be replaced (BIP125)
+    pub fn enable_rbf(mut self, rbf: bool) -> TxBuilder {
+        self.rbf = rbf;
+        self
+    }
+
+    /// The weight of the transaction spending `inputs` and paying
+    /// `outputs`, once signed
+    fn weight(&self, inputs: &[&Utxo], outputs: &[&TxOut]) -> usize {
+        let mut size = 4 + VarInt(inputs.len() as u64).len() + VarInt(outputs.len() as u64).len() + 4;
+        size += outputs.iter().map(|o| output_size(o)).sum::<usize>();
+        let mut weight = size * 4 + inputs.iter().map(|u| u.spend_weight).sum::<usize>();
+        if inputs.iter().any(|u| u.is_segwit()) {
+            // Legacy inputs then have an empty witness, a single zero byte
+            weight += SEGWIT_HEADER_WEIGHT + inputs.iter().filter(|u| !u.is_segwit()).count();
+        }
+        weight
+    }
+
+    /// The fee of a transaction of `weight` at the target fee rate
+    fn fee(&self, weight: usize) -> Result<Amount, Error> {
+        self.fee_rate.fee_vb(weight_to_vsize(weight)).ok_or(Error::ValueOverflow)
+    }
+
+    /// Select outputs to spend, add change and build the transaction
+    pub fn finish(self) -> Result<PartiallySignedTransaction, Error> {
+        if self.recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        let mut target = Amount::ZERO;
+        for (i, recipient) in self.recipients.iter().enumerate() {
+            if self.policy.is_dust(recipient) {
+                return Err(Error::DustRecipient(i));
+            }
+            target = target.checked_add(Amount::from_sat(recipient.value)).ok_or(Error::ValueOverflow)?;
+        }
+        let outputs: Vec<&TxOut> = self.recipients.iter().collect();
+
+        // Spend the largest outputs first, skipping those which cost more
+        // to spend than they are worth
+        let mut candidates = Vec::new();
+        let mut available = Amount::ZERO;
+        for utxo in &self.utxos {
+            let input_fee = self.fee(utxo.spend_weight)?;
+            if utxo.txout.value > input_fee.as_sat() {
+                available = available.checked_add(Amount::from_sat(utxo.txout.value)).ok_or(Error::ValueOverflow)?;
+                candidates.push(utxo);
+            }
+        }
+        candidates.sort_by_key(|utxo| cmp::Reverse(utxo.txout.value));
+
+        let mut selected = Vec::new();
+        let mut selected_value = Amount::ZERO;
+        let mut needed = target;
+        for utxo in candidates {
+            selected.push(utxo);
+            selected_value += Amount::from_sat(utxo.txout.value);
+            needed = target.checked_add(self.fee(self.weight(&selected, &outputs))?).ok_or(Error::ValueOverflow)?;
+            if selected_value >= needed {
+                break;
+            }
+        }
+        if selected_value < needed {
+            return Err(Error::InsufficientFunds { needed, available });
+        }
+        if let Some(utxo) = selected.iter().find(|utxo| !utxo.is_segwit() && utxo.prev_tx.is_none()) {
+            return Err(Error::MissingPrevTx(utxo.outpoint));
+        }
+
+        // Add change unless it would be dust once it pays for itself
+        let mut output = self.recipients.clone();
+        let mut change = TxOut { value: 0, script_pubkey: self.change_script.clone() };
+        let fee = {
+            let mut with_change = outputs.clone();
+            with_change.push(&change);
+            self.fee(self.weight(&selected, &with_change))?
+        };
+        let value = selected_value.checked_sub(target).and_then(|v| v.checked_sub(fee));
+        if let Some(value) = value {
+            change.value = value.as_sat();
+            if !self.policy.is_dust(&change) {
+                output.push(change);
+            }
+        }
+
+        let (lock_time, sequence) = match (self.tip_height, self.rbf) {
+            (Some(height), true) => (height, Sequence::ENABLE_RBF_NO_LOCKTIME),
+            (Some(height), false) => (height, Sequence::ENABLE_LOCKTIME_NO_RBF),
+            (None, true) => (0, Sequence::ENABLE_RBF_NO_LOCKTIME),
+            (None, false) => (0, Sequence::MAX),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time,
+            input: selected.iter().map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: Script::new(),
+                sequence: sequence.0,
+                witness: Vec::new(),
+            }).collect(),
+            output,
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).expect("the transaction is unsigned");
+        for (input, utxo) in psbt.inputs.iter_mut().zip(selected) {
+            input.non_witness_utxo = utxo.prev_tx.clone();
+            if utxo.is_segwit() {
+                input.witness_utxo = Some(utxo.txout.clone());
+            }
+        }
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use blockdata::locktime::Sequence;
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, TxOut};
+    use hashes::Hash;
+    use hash_types::Txid;
+    use hex::decode as hex_decode;
+    use util::address::Address;
+    use util::amount::{Amount, FeeRate};
+
+    use super::*;
+
+    fn p2wpkh(n: u8) -> Script {
+        let mut script = vec![0x00, 0x14];
+        script.extend_from_slice(&[n; 20]);
+        Script::from(script)
+    }
+
+    fn utxo(n: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid::from_slice(&[n; 32]).unwrap(), 0),
+            txout: TxOut { value, script_pubkey: p2wpkh(n) },
+            spend_weight: 272,
+            nested_segwit: false,
+            prev_tx: None,
+        }
+    }
+
+    fn address() -> Address {
+        Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap()
+    }
+
+    #[test]
+    fn build_with_change() {
+        let rate = FeeRate::from_sat_per_vb(10).unwrap();
+        let psbt = TxBuilder::new(rate, p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(50_000))
+            .add_utxo(utxo(1, 30_000))
+            .add_utxo(utxo(2, 100_000))
+            .add_utxo(utxo(3, 40_000))
+            .finish()
+            .unwrap();
+        let tx = &psbt.global.unsigned_tx;
+
+        // The largest output is enough
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, utxo(2, 0).outpoint);
+        assert_eq!(tx.input[0].sequence, Sequence::MAX.0);
+        assert_eq!(tx.lock_time, 0);
+        assert_eq!(psbt.inputs[0].witness_utxo, Some(utxo(2, 100_000).txout));
+        assert_eq!(psbt.inputs[0].non_witness_utxo, None);
+
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].script_pubkey, address().script_pubkey());
+        assert_eq!(tx.output[0].value, 50_000);
+        assert_eq!(tx.output[1].script_pubkey, p2wpkh(9));
+        // Signed, the transaction is 41 + 68 + 31 + 31 + 0.5 vbytes
+        let fee = 100_000 - 50_000 - tx.output[1].value;
+        assert_eq!(fee, 1410);
+        let signed_weight = tx.get_weight() + 272 - 4 * 41 + 2;
+        assert_eq!(rate.fee_vb(weight_to_vsize(signed_weight)), Some(Amount::from_sat(fee)));
+    }
+
+    #[test]
+    fn build_without_change() {
+        let rate = FeeRate::from_sat_per_vb(1).unwrap();
+        let builder = TxBuilder::new(rate, p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(50_000))
+            .add_utxo(utxo(1, 30_000))
+            .add_utxo(utxo(2, 20_400));
+        // The change of 400 - 209 would be dust
+        let psbt = builder.clone().finish().unwrap();
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[0].previous_output, utxo(1, 0).outpoint);
+        assert_eq!(tx.output.len(), 1);
+
+        let psbt = builder.add_utxo(utxo(3, 20_600)).finish().unwrap();
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input[1].previous_output, utxo(3, 0).outpoint);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value, 30_000 + 20_600 - 50_000 - 209);
+    }
+
+    #[test]
+    fn lock_time_and_rbf() {
+        let builder = TxBuilder::new(FeeRate::from_sat_per_vb(1).unwrap(), p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(10_000))
+            .add_utxo(utxo(1, 20_000));
+        let tx = builder.clone().anti_fee_sniping(650_000).finish().unwrap().global.unsigned_tx;
+        assert_eq!(tx.lock_time, 650_000);
+        assert_eq!(tx.input[0].sequence, Sequence::ENABLE_LOCKTIME_NO_RBF.0);
+        let tx = builder.clone().enable_rbf(true).finish().unwrap().global.unsigned_tx;
+        assert_eq!(tx.lock_time, 0);
+        assert!(Sequence(tx.input[0].sequence).is_rbf());
+    }
+
+    #[test]
+    fn legacy_input() {
+        let prev_tx: Transaction = ::consensus::deserialize(&hex_decode(
+            "0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c49\
+            3046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd\
+            7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f50\
+            6efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a92\
+            25b3839e2bbf32d826a1e222031fd888ac00000000"
+        ).unwrap()).unwrap();
+        let p2pkh = Utxo {
+            outpoint: OutPoint::new(prev_tx.txid(), 0),
+            txout: prev_tx.output[0].clone(),
+            spend_weight: 592,
+            nested_segwit: false,
+            prev_tx: Some(prev_tx.clone()),
+        };
+        let builder = TxBuilder::new(FeeRate::from_sat_per_vb(1).unwrap(), p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(10_000));
+        let psbt = builder.clone().add_utxo(p2pkh.clone()).finish().unwrap();
+        assert_eq!(psbt.inputs[0].non_witness_utxo, Some(prev_tx));
+        assert_eq!(psbt.inputs[0].witness_utxo, None);
+        // Without witnesses, the signed transaction is 220 vbytes
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value, 100_000_000 - 10_000 - 220);
+
+        // Next to a segwit input, a legacy one has an empty witness
+        assert_eq!(builder.weight(&[&p2pkh], &[]), 4 * 10 + 592);
+        assert_eq!(builder.weight(&[&p2pkh, &utxo(1, 0)], &[]), 4 * 10 + 592 + 272 + 2 + 1);
+
+        // Signers need the previous transaction of legacy outputs
+        let outpoint = p2pkh.outpoint;
+        let legacy = Utxo { prev_tx: None, ..p2pkh };
+        assert_eq!(builder.clone().add_utxo(legacy.clone()).finish(), Err(Error::MissingPrevTx(outpoint)));
+
+        // P2SH outputs are only spent with a witness when said so
+        let p2sh = Script::from(hex_decode("a914f815b036d9bbbce5e9f2a00abd1bf3dc91e9551087").unwrap());
+        let legacy_p2sh = Utxo { txout: TxOut { value: 20_000, script_pubkey: p2sh }, ..legacy };
+        assert_eq!(builder.clone().add_utxo(legacy_p2sh.clone()).finish(),
+                   Err(Error::MissingPrevTx(outpoint)));
+        // Legacy outputs left unspent need no previous transaction
+        let psbt = builder.clone().add_utxo(legacy_p2sh.clone()).add_utxo(utxo(1, 100_000)).finish().unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.input[0].previous_output, utxo(1, 0).outpoint);
+        let nested = Utxo { nested_segwit: true, spend_weight: 364, ..legacy_p2sh.clone() };
+        let psbt = builder.add_utxo(nested).finish().unwrap();
+        assert_eq!(psbt.inputs[0].witness_utxo, Some(legacy_p2sh.txout));
+        assert_eq!(psbt.inputs[0].non_witness_utxo, None);
+        // 72 bytes of outputs and the header, the input and the segwit
+        // marker and flag weigh 654, or 164 vbytes
+        assert_eq!(psbt.global.unsigned_tx.output[1].value, 20_000 - 10_000 - 164);
+    }
+
+    #[test]
+    fn errors() {
+        let rate = FeeRate::from_sat_per_vb(10).unwrap();
+        assert_eq!(TxBuilder::new(rate, p2wpkh(9)).add_utxo(utxo(1, 1000)).finish(), Err(Error::NoRecipients));
+        let result = TxBuilder::new(rate, p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(10_000))
+            .add_recipient(&address(), Amount::from_sat(293))
+            .finish();
+        assert_eq!(result, Err(Error::DustRecipient(1)));
+
+        // The second output costs 680 to spend and is left out
+        let result = TxBuilder::new(rate, p2wpkh(9))
+            .add_recipient(&address(), Amount::from_sat(10_000))
+            .add_utxo(utxo(1, 10_000))
+            .add_utxo(utxo(2, 680))
+            .finish();
+        assert_eq!(result, Err(Error::InsufficientFunds {
+            needed: Amount::from_sat(11_100),
+            available: Amount::from_sat(10_000),
+        }));
+    }
+}